@@ -121,7 +121,7 @@ fn main() {
         //         .expect("Cannot read threshold"),
         threshold: 0,
         // window_size_ns: (window_size_s * (NS_TO_SEC as f64)) as u64,
-        window_size_ns: NS_TO_SEC,
+        window_size_ns: u64::from(NS_TO_SEC),
         // epochs: u64::from_str(matches
         //                           .value_of("epochs")
         //                           .expect("Epochs parameter missing"))
@@ -34,12 +34,15 @@ fn main() {
 
     let message_delay = matches
         .value_of("message-delay")
-        .map(|m| m.parse::<u64>().expect("message-delay must be u64"));
+        .map(|m| m.parse::<pag_construction::input::MessageDelay>().expect("Cannot read message-delay"));
 
     let log_path = matches.value_of("INPUT").unwrap();
     let records =
-        pag_construction::input::read_sorted_trace_from_file_and_cut_messages(log_path,
-                                                                              message_delay);
+        pag_construction::input::read_sorted_trace_from_file_and_cut_messages(&[log_path.to_string()],
+                                                                              message_delay,
+                                                                              None,
+                                                                              Default::default())
+            .expect("couldn't read trace");
 
     use rayon::prelude::*;
 
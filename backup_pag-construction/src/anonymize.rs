@@ -0,0 +1,83 @@
+// Copyright 2017 ETH Zurich. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Replaces worker and operator IDs by small sequential integers so that output can be shared
+//! without revealing the topology of the traced computation.
+//!
+//! IDs are assigned in the order they are first seen in the (sorted) trace, so the same trace
+//! is always anonymized the same way. Records are anonymized before the PAG is built, hence
+//! all outputs only contain anonymized IDs; options referring to workers or operators (e.g.
+//! `--operator-cost`) refer to anonymized IDs as well.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::Hash;
+use std::io::Write;
+
+use logformat::{LogRecord, OperatorId, Worker};
+
+/// Assigns `0, 1, 2, ...` to values in the order they are first seen.
+struct FirstSeen<T: Eq + Hash> {
+    ids: HashMap<T, u64>,
+}
+
+impl<T: Eq + Hash + Copy> FirstSeen<T> {
+    fn get(&mut self, value: T) -> u64 {
+        let next = self.ids.len() as u64;
+        *self.ids.entry(value).or_insert(next)
+    }
+
+    fn sorted(&self) -> Vec<(u64, T)> {
+        let mut mapping: Vec<_> = self.ids.iter().map(|(value, id)| (*id, *value)).collect();
+        mapping.sort_by_key(|&(id, _)| id);
+        mapping
+    }
+}
+
+/// Anonymizes the worker and operator IDs of log records.
+pub struct Anonymizer {
+    workers: FirstSeen<Worker>,
+    operators: FirstSeen<OperatorId>,
+    mapping_path: Option<String>,
+}
+
+impl Anonymizer {
+    /// Creates an anonymizer. The mapping back to the original IDs is only written (to
+    /// `mapping_path`) if a path is given; keep that file private.
+    pub fn new(mapping_path: Option<String>) -> Self {
+        Anonymizer {
+            workers: FirstSeen { ids: HashMap::new() },
+            operators: FirstSeen { ids: HashMap::new() },
+            mapping_path,
+        }
+    }
+
+    /// Replaces the IDs of `record` by their anonymized IDs.
+    pub fn anonymize(&mut self, record: &mut LogRecord) {
+        record.local_worker = self.workers.get(record.local_worker);
+        record.remote_worker = record.remote_worker.map(|worker| self.workers.get(worker));
+        record.operator_id = record.operator_id.map(|operator| self.operators.get(operator));
+    }
+
+    /// Writes the mapping from anonymized to original IDs as CSV, if a path was given.
+    pub fn write_mapping(&self) -> std::io::Result<()> {
+        let path = match self.mapping_path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+        let mut file = File::create(path)?;
+        writeln!(file, "kind,anonymized,original")?;
+        for (id, worker) in self.workers.sorted() {
+            writeln!(file, "worker,{},{}", id, worker)?;
+        }
+        for (id, operator) in self.operators.sorted() {
+            writeln!(file, "operator,{},{}", id, operator)?;
+        }
+        Ok(())
+    }
+}
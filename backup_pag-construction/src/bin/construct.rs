@@ -7,13 +7,60 @@
 // except according to those terms.
 
 use std::str::FromStr;
+use std::sync::Arc;
 
 use clap::{App, Arg};
 
-use pag_construction::dataflow::{Config, run_dataflow};
+use logformat::ActivityType;
+
+use pag_construction::EdgeIdentity;
+use pag_construction::dataflow::{AnalysisMode, Config, NodePredicate, run_dataflow};
 
 const NS_TO_SEC: u64 = 1_000_000_000;
 
+/// Parses an `ID=MULTIPLIER` pair of an operator ID and its cost multiplier.
+fn operator_cost(cost: &str) -> (u64, f64) {
+    let mut parts = cost.splitn(2, '=');
+    let operator_id = parts.next()
+        .and_then(|id| u64::from_str(id).ok())
+        .expect("Cannot read operator ID of operator-cost parameter");
+    let multiplier = parts.next()
+        .and_then(|m| f64::from_str(m).ok())
+        .expect("Cannot read multiplier of operator-cost parameter");
+    (operator_id, multiplier)
+}
+
+/// Parses a `TYPE=NAME` pair of a numeric activity type and its name in human-readable output.
+fn activity_label(label: &str) -> (u8, String) {
+    let mut parts = label.splitn(2, '=');
+    let activity_type = parts.next()
+        .and_then(|activity_type| u8::from_str(activity_type).ok())
+        .expect("Cannot read activity type of activity-label parameter");
+    let name = parts.next().expect("Cannot read name of activity-label parameter");
+    (activity_type, name.to_string())
+}
+
+/// Parses an `ACTIVITY=NS` pair of an activity type name and the delay to add to its edges.
+fn injected_delay(delay: &str) -> (ActivityType, u64) {
+    let mut parts = delay.splitn(2, '=');
+    let activity_type = parts.next()
+        .and_then(|name| ActivityType::from_str(name).ok())
+        .expect("Cannot read activity type of inject-delay parameter");
+    let delay_ns = parts.next()
+        .and_then(|ns| u64::from_str(ns).ok())
+        .expect("Cannot read delay of inject-delay parameter");
+    (activity_type, delay_ns)
+}
+
+/// Parses a comma-separated list of worker IDs into a predicate selecting nodes on them.
+fn worker_predicate(workers: &str) -> NodePredicate {
+    let workers: Vec<u64> = workers
+        .split(',')
+        .map(|w| u64::from_str(w.trim()).expect("Cannot read worker ID"))
+        .collect();
+    Arc::new(move |node| workers.contains(&node.worker_id))
+}
+
 fn main() {
     // NOTE: implement a dataflow program which constructs the program activity graph:
     // [INPUT]
@@ -34,7 +81,7 @@ fn main() {
         .setting(clap::AppSettings::TrailingVarArg)
         .about("Construct PAG from log")
         .arg(Arg::with_name("INPUT")
-            .help("Sets the log file to read")
+            .help("Sets the log file to read, a directory of numbered trace segments, - to read from stdin, or tcp://host:port to receive records")
             .index(1)
             .required(true))
         .arg(Arg::with_name("threshold")
@@ -61,21 +108,34 @@ fn main() {
             .takes_value(true)
             .required(true))
         .arg(Arg::with_name("message-delay")
-            .help("Sets a constant message dely")
+            .help("Clips messages to NS nanoseconds, or per activity type as e.g. DataMessage=1000,ControlMessage=5000,NS")
             .long("message-delay")
-            .value_name("message-delay")
+            .value_name("NS")
             .takes_value(true)
             .required(false))
         .arg(Arg::with_name("bc-dot")
             .help("Produce a BC DOT file per time slice")
             .short("d")
             .long("bc-dot"))
+        .arg(Arg::with_name("pag-cypher")
+            .help("Produce a file of Cypher statements per time slice to import the PAG with BC into Neo4j")
+            .long("pag-cypher"))
         .arg(Arg::with_name("pag-dot")
             .help("Produce a PAG DOT file per time slice")
             .long("pag-dot"))
         .arg(Arg::with_name("pag-msgpack")
             .help("Produce a PAG msgpack file per time slice")
             .long("pag-msgpack"))
+        .arg(Arg::with_name("pag-cbor")
+            .help("Produce a PAG CBOR file per time slice")
+            .long("pag-cbor"))
+        .arg(Arg::with_name("pag-html")
+            .help("Produce a single HTML file to step through the PAGs of all time slices")
+            .long("pag-html"))
+        .arg(Arg::with_name("pag-tracy")
+            .help("Write the processing edges to tracy/pag.json as a Chrome JSON trace; convert it \
+                   with Tracy's import-chrome tool to open it in Tracy")
+            .long("pag-tracy"))
         .arg(Arg::with_name("v")
             .help("Print more verbose output")
             .short("v")
@@ -87,17 +147,345 @@ fn main() {
         .arg(Arg::with_name("no-insert-waiting")
             .help("Do not nsert waiting edges but use unknown for all gaps")
             .long("no-insert-waiting"))
+        .arg(Arg::with_name("mode")
+            .help("How far the analysis runs: pag-only, pag-and-bc or full (the default)")
+            .long("mode")
+            .takes_value(true)
+            .value_name("MODE")
+            .conflicts_with_all(&["no-summary", "no-bc"]))
         .arg(Arg::with_name("no-summary")
-            .help("Do not compute summaries")
+            .help("Do not compute summaries, same as --mode pag-and-bc")
             .long("no-summary"))
         .arg(Arg::with_name("no-bc")
-            .help("Do not compute BC")
+            .help("Do not compute BC, same as --mode pag-only")
             .long("no-bc"))
+        .arg(Arg::with_name("slack")
+            .help("Print the earliest and latest start and the slack of every edge")
+            .long("slack"))
+        .arg(Arg::with_name("summary-group-by")
+            .help("Additionally print summaries grouped by activity, operator, scope or worker")
+            .long("summary-group-by")
+            .takes_value(true)
+            .value_name("KEY"))
+        .arg(Arg::with_name("scope-summary")
+            .help("Additionally summarize BC and the critical path per dataflow scope")
+            .long("scope-summary"))
+        .arg(Arg::with_name("critical-path-report")
+            .help("Print the operators most often on the critical path across the whole run")
+            .long("critical-path-report"))
+        .arg(Arg::with_name("edge-distribution")
+            .help("Only print the distribution of the number of PAG edges per time slice")
+            .long("edge-distribution"))
+        .arg(Arg::with_name("global-bc")
+            .help("Also compute BC over the union of all time slices (needs memory for the whole trace)")
+            .long("global-bc"))
+        .arg(Arg::with_name("partial-windows")
+            .help("What to do with the summaries of the usually partial first and last time slices: keep, drop or tag")
+            .long("partial-windows")
+            .takes_value(true)
+            .value_name("POLICY"))
+        .arg(Arg::with_name("bc-sample-fraction")
+            .help("Estimate BC from the paths of this fraction of the start nodes, e.g. 0.1")
+            .long("bc-sample-fraction")
+            .takes_value(true)
+            .value_name("FRACTION"))
+        .arg(Arg::with_name("bc-confidence")
+            .help("Print BC_CONFIDENCE lines with the sample size and confidence interval of every edge's BC")
+            .long("bc-confidence"))
+        .arg(Arg::with_name("edge-identity-scope")
+            .help("Distinguish operators in different scopes when relating edges across time slices")
+            .long("edge-identity-scope"))
+        .arg(Arg::with_name("fill-absent-operators")
+            .help("Print zero-valued summaries for operators without activity in a window")
+            .long("fill-absent-operators"))
+        .arg(Arg::with_name("remove-degenerate-edges")
+            .help("Remove self-loops and zero-duration edges from the PAG")
+            .long("remove-degenerate-edges"))
+        .arg(Arg::with_name("sp-seed")
+            .help("Seed for picking single paths, for reproducible SP_SUMMARY output")
+            .long("sp-seed")
+            .takes_value(true)
+            .value_name("SEED"))
+        .arg(Arg::with_name("epoch-bounds")
+            .help("Print the start and end timestamp in ns of every time slice")
+            .long("epoch-bounds"))
+        .arg(Arg::with_name("input-queue")
+            .help("Read the (sorted) trace in the background, queueing at most N records")
+            .long("input-queue")
+            .takes_value(true)
+            .value_name("N"))
+        .arg(Arg::with_name("compare-metrics")
+            .help("Print the correlation between edge BC and edge weight per time slice")
+            .long("compare-metrics"))
+        .arg(Arg::with_name("anonymize")
+            .help("Replace worker and operator IDs by sequential IDs in all output")
+            .long("anonymize"))
+        .arg(Arg::with_name("anonymize-mapping")
+            .help("Write the mapping from anonymized to original IDs to this file (keep it private)")
+            .long("anonymize-mapping")
+            .takes_value(true)
+            .value_name("PATH")
+            .requires("anonymize"))
+        .arg(Arg::with_name("alert-weighted-bc")
+            .help("Alert when an operator's weighted BC stays above this value (see --alert-streak)")
+            .long("alert-weighted-bc")
+            .takes_value(true)
+            .value_name("BC"))
+        .arg(Arg::with_name("alert-streak")
+            .help("Number of consecutive time slices above --alert-weighted-bc before alerting")
+            .long("alert-streak")
+            .takes_value(true)
+            .value_name("K")
+            .default_value("3"))
+        .arg(Arg::with_name("dump-stride")
+            .help("Only write per-time-slice dump files for every N-th time slice")
+            .long("dump-stride")
+            .takes_value(true)
+            .value_name("N"))
+        .arg(Arg::with_name("max-dump-files")
+            .help("Stop writing a per-time-slice dump after N files (see --rotate-dumps)")
+            .long("max-dump-files")
+            .takes_value(true)
+            .value_name("N"))
+        .arg(Arg::with_name("rotate-dumps")
+            .help("Replace the oldest dump file once --max-dump-files is reached")
+            .long("rotate-dumps")
+            .requires("max-dump-files"))
+        .arg(Arg::with_name("comm-matrix")
+            .help("Print the communication between every pair of workers per time slice")
+            .long("comm-matrix"))
+        .arg(Arg::with_name("dump-retries")
+            .help("Retry failed writes of per-time-slice dump files and SQLite inserts N times, then skip the time slice")
+            .long("dump-retries")
+            .takes_value(true)
+            .value_name("N")
+            .default_value("3"))
+        .arg(Arg::with_name("state-profile")
+            .help("Print the share of active, waiting and communication time of every operator")
+            .long("state-profile"))
+        .arg(Arg::with_name("weight-stats")
+            .help("Print the count, min, max, mean and standard deviation of the edge weights of every operator")
+            .long("weight-stats"))
+        .arg(Arg::with_name("parallelism")
+            .help("Print the work, span and work/span parallelism of every window and of the whole run")
+            .long("parallelism"))
+        .arg(Arg::with_name("stream-input")
+            .help("Feed the trace record by record while decoding it instead of reading it into memory first")
+            .long("stream-input"))
+        .arg(Arg::with_name("reorder-window")
+            .help("With --stream-input or --reorder-input, sort records that are out of order by at most NS nanoseconds")
+            .long("reorder-window")
+            .takes_value(true)
+            .value_name("NS")
+            .default_value("0"))
+        .arg(Arg::with_name("summary-format")
+            .help("Print SUMMARY lines as csv (the default) or as a JSON object per line (jsonl)")
+            .long("summary-format")
+            .takes_value(true)
+            .value_name("FORMAT"))
+        .arg(Arg::with_name("pag-dot-combined")
+            .help("Write the PAGs of all time slices to a single DOT file with a cluster per slice")
+            .long("pag-dot-combined"))
+        .arg(Arg::with_name("bc-msgpack")
+            .help("Write the edges of every time slice with their BC as msgpack")
+            .long("bc-msgpack"))
+        .arg(Arg::with_name("include-edge-types")
+            .help("Only keep PAG edges of these activity types, e.g. DataMessage,ControlMessage")
+            .long("include-edge-types")
+            .takes_value(true)
+            .value_name("ACTIVITIES"))
+        .arg(Arg::with_name("window-stride")
+            .help("Start a window every this many seconds; windows overlap if this is less than the window size")
+            .long("window-stride")
+            .takes_value(true)
+            .value_name("SECONDS"))
+        .arg(Arg::with_name("merge-trace")
+            .help("Merge the records of another trace, e.g. of another worker, with INPUT by timestamp")
+            .long("merge-trace")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("PATH"))
+        .arg(Arg::with_name("quiet-epochs")
+            .help("Do not print EPOCH lines as the analysis progresses")
+            .long("quiet-epochs"))
+        .arg(Arg::with_name("bc-top-k")
+            .help("Only keep the K edges with the highest BC in every time slice")
+            .long("bc-top-k")
+            .takes_value(true)
+            .value_name("K"))
+        .arg(Arg::with_name("pag-graphml")
+            .help("Produce a PAG GraphML file per time slice")
+            .long("pag-graphml"))
+        .arg(Arg::with_name("pag-msgpack-sharded")
+            .help("Produce a PAG msgpack file per time slice and worker, without collecting the PAG on one worker")
+            .long("pag-msgpack-sharded"))
+        .arg(Arg::with_name("histogram")
+            .help("Print a histogram of the edge durations of every time slice")
+            .long("histogram"))
+        .arg(Arg::with_name("histogram-buckets")
+            .help("Comma-separated bucket boundaries in ns for --histogram, e.g. 1000,1000000 (default: powers of ten)")
+            .long("histogram-buckets")
+            .takes_value(true)
+            .value_name("NS"))
+        .arg(Arg::with_name("socket-idle-timeout")
+            .help("With a tcp://host:port log path, end the input after MS milliseconds without records")
+            .long("socket-idle-timeout")
+            .takes_value(true)
+            .value_name("MS")
+            .default_value("5000"))
+        .arg(Arg::with_name("metrics-addr")
+            .help("Serve per-epoch counts as Prometheus metrics at http://ADDR/metrics (needs the metrics feature)")
+            .long("metrics-addr")
+            .takes_value(true)
+            .value_name("ADDR"))
+        .arg(Arg::with_name("bc-traverse")
+            .help("Which edges BC and the critical path traverse: no-waiting (the default) or include-waiting")
+            .long("bc-traverse")
+            .takes_value(true)
+            .value_name("MODE"))
+        .arg(Arg::with_name("dry-run")
+            .help("Only decode the trace, check that it is sorted and print the number of records per time slice")
+            .long("dry-run"))
+        .arg(Arg::with_name("reorder-input")
+            .help("Sort records that are out of order within --reorder-window instead of aborting")
+            .long("reorder-input"))
+        .arg(Arg::with_name("operator-summary")
+            .help("Print OPERATOR_SUMMARY lines with the BC of each time slice totaled per operator")
+            .long("operator-summary"))
+        .arg(Arg::with_name("max-weight-path")
+            .help("Follow the heaviest edge at every node of the single paths of SP_SUMMARY instead of a random one")
+            .long("max-weight-path"))
+        .arg(Arg::with_name("output-dir")
+            .help("Write the run manifest (manifest.json) recording the options and inputs to this directory")
+            .long("output-dir")
+            .value_name("DIR")
+            .takes_value(true))
+        .arg(Arg::with_name("mark-empty-epochs")
+            .help("Print SUMMARY <epoch> EMPTY and SP_SUMMARY <epoch> EMPTY for time slices without summaries")
+            .long("mark-empty-epochs"))
+        .arg(Arg::with_name("threshold-percentile")
+            .help("Use this percentile of the gaps between records as the unknown edge threshold if THRESHOLD is 0")
+            .long("threshold-percentile")
+            .value_name("PERCENTILE")
+            .takes_value(true))
+        .arg(Arg::with_name("dump-seed-edges")
+            .help("Print the start node of the single path of every time slice as SEED lines")
+            .long("dump-seed-edges"))
+        .arg(Arg::with_name("max-windows")
+            .help("Only analyze this many time slices from the first one and stop")
+            .long("max-windows")
+            .value_name("WINDOWS")
+            .takes_value(true))
+        .arg(Arg::with_name("report-skew")
+            .help("Print SKEW lines with how far the analysis falls behind the trace time per time slice")
+            .long("report-skew"))
+        .arg(Arg::with_name("longest-path")
+            .help("Print the heaviest path from a start node to an end node per time slice as LONGEST_PATH lines")
+            .long("longest-path"))
+        .arg(Arg::with_name("message-delay-mode")
+            .help("Whether --message-delay shifts the timestamps of clipped messages (shift, the default) or only reports them (annotate)")
+            .long("message-delay-mode")
+            .takes_value(true)
+            .value_name("MODE"))
+        .arg(Arg::with_name("max-inflight-windows")
+            .help("Process up to N time slices at once, which needs memory for all of them")
+            .long("max-inflight-windows")
+            .value_name("N")
+            .takes_value(true))
+        .arg(Arg::with_name("summary-parquet")
+            .help("Write the summaries to a Parquet file (needs the parquet-output feature)")
+            .long("summary-parquet")
+            .value_name("PATH")
+            .takes_value(true))
+        .arg(Arg::with_name("focus-operators")
+            .help("Only analyze the edges of these operators and their neighbors, e.g. 3,7")
+            .long("focus-operators")
+            .takes_value(true)
+            .value_name("OPERATORS"))
+        .arg(Arg::with_name("focus-unattributed")
+            .help("Keep the neighboring edges without an operator with --focus-operators")
+            .long("focus-unattributed"))
+        .arg(Arg::with_name("validate-pag")
+            .help("Print ORPHAN lines for PAG edges that are not connected to the rest of their time slice")
+            .long("validate-pag"))
+        .arg(Arg::with_name("max-buffered-edges-per-epoch")
+            .help("Sample the seed edge and warn once a stage buffers more than N edges of an epoch")
+            .long("max-buffered-edges-per-epoch")
+            .takes_value(true)
+            .value_name("N"))
+        .arg(Arg::with_name("pag-chrome")
+            .help("Produce a Chrome trace of all PAG edges for chrome://tracing or Perfetto")
+            .long("pag-chrome"))
+        .arg(Arg::with_name("activity-label")
+            .help("Name an activity type in human-readable output, e.g. 5=Shuffle")
+            .long("activity-label")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("TYPE=NAME"))
+        .arg(Arg::with_name("explain")
+            .help("Describe the analysis that would run with the given options without running it")
+            .long("explain"))
         .arg(Arg::with_name("waiting-message")
             .help("Consider messages with a lenght of 2*threshold waiting")
             .long("waiting-message")
             .takes_value(true)
             .value_name("delayed_message_threshold"))
+        .arg(Arg::with_name("bc-spill-threshold")
+            .help("Spill BC traversal state to disk once more than this many edges are buffered")
+            .long("bc-spill-threshold")
+            .takes_value(true)
+            .value_name("EDGES"))
+        .arg(Arg::with_name("sample-every")
+            .help("Only analyze about one in N records for a fast, approximate preview")
+            .long("sample-every")
+            .takes_value(true)
+            .value_name("N"))
+        .arg(Arg::with_name("heat-csv")
+            .help("Write the total weight per epoch and operator to a CSV file")
+            .long("heat-csv")
+            .takes_value(true)
+            .value_name("PATH"))
+        .arg(Arg::with_name("bc-source-workers")
+            .help("Only count BC paths starting on these workers (comma-separated)")
+            .long("bc-source-workers")
+            .takes_value(true)
+            .value_name("WORKERS"))
+        .arg(Arg::with_name("bc-sink-workers")
+            .help("Only count BC paths ending on these workers (comma-separated)")
+            .long("bc-sink-workers")
+            .takes_value(true)
+            .value_name("WORKERS"))
+        .arg(Arg::with_name("corrupt-records")
+            .help("What to do with records that cannot be decoded: abort, skip, or skip-N to skip at most N")
+            .long("corrupt-records")
+            .takes_value(true)
+            .value_name("POLICY"))
+        .arg(Arg::with_name("operator-cost")
+            .help("Multiply the weight of an operator's edges by a cost for weighted BC, e.g. 3=2.5")
+            .long("operator-cost")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("ID=MULTIPLIER"))
+        .arg(Arg::with_name("inject-delay")
+            .help("Add synthetic delay to edges of an activity type for a what-if analysis, e.g. DataMessage=1000000")
+            .long("inject-delay")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("ACTIVITY=NS"))
+        .arg(Arg::with_name("max-buffered-epochs")
+            .help("Drop the oldest epoch with an error once a stage buffers more than N epochs")
+            .long("max-buffered-epochs")
+            .takes_value(true)
+            .value_name("N"))
+        .arg(Arg::with_name("sqlite")
+            .help("Write edges, BC values and summaries to a SQLite database")
+            .long("sqlite")
+            .takes_value(true)
+            .value_name("PATH"))
         .arg(Arg::with_name("TIMELY")
             .multiple(true))
         .get_matches();
@@ -126,23 +514,184 @@ fn main() {
                                   .value_of("epochs")
                                   .expect("Epochs parameter missing"))
                 .expect("Cannot read epochs parameter"),
-        message_delay: if matches.is_present("message-delay") {
-            Some(u64::from_str(matches.value_of("message-delay").unwrap())
-                     .expect("Cannot read message-delay parameter"))
-        } else {
-            None
-        },
+        message_delay: matches.value_of("message-delay")
+            .map(|delay| delay.parse().expect("Cannot read message-delay parameter")),
         verbose: matches.occurrences_of("v"),
         dump_pag: matches.is_present("dump-pag"),
         write_bc_dot: matches.is_present("bc-dot"),
         write_pag_dot: matches.is_present("pag-dot"),
         write_pag_msgpack: matches.is_present("pag-msgpack"),
+        write_pag_cbor: matches.is_present("pag-cbor"),
+        write_pag_cypher: matches.is_present("pag-cypher"),
         insert_waiting_edges: !matches.is_present("no-insert-waiting"),
-        disable_summary: matches.is_present("no-summary"),
-        disable_bc: matches.is_present("no-bc"),
+        mode: match matches.value_of("mode") {
+            Some(mode) => mode.parse().expect("Cannot read mode parameter"),
+            None if matches.is_present("no-bc") => AnalysisMode::PagOnly,
+            None if matches.is_present("no-summary") => AnalysisMode::PagAndBc,
+            None => AnalysisMode::Full,
+        },
         waiting_message: u64::from_str(matches.value_of("waiting-message").unwrap_or("0"))
             .expect("Cannot read waiting-message parameter"),
+        bc_spill_threshold: matches.value_of("bc-spill-threshold")
+            .map(|t| usize::from_str(t).expect("Cannot read bc-spill-threshold parameter")),
+        sample_every: matches.value_of("sample-every")
+            .map(|n| usize::from_str(n).expect("Cannot read sample-every parameter")),
+        heat_csv_path: matches.value_of("heat-csv").map(String::from),
+        write_pag_html: matches.is_present("pag-html"),
+        write_pag_tracy: matches.is_present("pag-tracy"),
+        global_bc: matches.is_present("global-bc"),
+        partial_windows: matches.value_of("partial-windows")
+            .map(|p| p.parse().expect("Cannot read partial-windows parameter"))
+            .unwrap_or_default(),
+        bc_sources: matches.value_of("bc-source-workers").map(worker_predicate),
+        bc_sinks: matches.value_of("bc-sink-workers").map(worker_predicate),
+        corrupt_record_policy: matches.value_of("corrupt-records")
+            .map(|p| p.parse().expect("Cannot read corrupt-records parameter"))
+            .unwrap_or_default(),
+        edge_distribution_only: matches.is_present("edge-distribution"),
+        critical_path_report: matches.is_present("critical-path-report"),
+        max_buffered_epochs: matches.value_of("max-buffered-epochs")
+            .map(|n| usize::from_str(n).expect("Cannot read max-buffered-epochs parameter")),
+        sqlite_path: matches.value_of("sqlite").map(String::from),
+        sqlite_output: None,
+        group_by_scope: matches.is_present("scope-summary"),
+        compute_slack: matches.is_present("slack"),
+        summary_grouping: matches.value_of("summary-group-by")
+            .map(|key| key.parse().expect("Cannot read summary-group-by parameter"))
+            .unwrap_or_default(),
+        operator_cost_multipliers: matches.values_of("operator-cost")
+            .map(|costs| costs.map(operator_cost).collect())
+            .unwrap_or_default(),
+        delay_injection: matches.values_of("inject-delay")
+            .map(|delays| delays.map(injected_delay).collect())
+            .unwrap_or_default(),
+        explain: matches.is_present("explain"),
+        bc_sample_fraction: matches.value_of("bc-sample-fraction")
+            .map(|fraction| f64::from_str(fraction).expect("Cannot read bc-sample-fraction parameter")),
+        bc_confidence: matches.is_present("bc-confidence"),
+        epoch_results: None,
+        edge_identity: if matches.is_present("edge-identity-scope") {
+            Some(Arc::new(EdgeIdentity::with_scope))
+        } else {
+            None
+        },
+        fill_absent_operators: matches.is_present("fill-absent-operators"),
+        remove_degenerate_edges: matches.is_present("remove-degenerate-edges"),
+        sp_seed: matches.value_of("sp-seed")
+            .map(|n| u64::from_str(n).expect("Cannot read sp-seed parameter")),
+        print_epoch_bounds: matches.is_present("epoch-bounds"),
+        input_queue_capacity: matches.value_of("input-queue")
+            .map(|n| usize::from_str(n).expect("Cannot read input-queue parameter")),
+        compare_metrics: matches.is_present("compare-metrics"),
+        anonymize: matches.is_present("anonymize"),
+        anonymize_mapping_path: matches.value_of("anonymize-mapping").map(String::from),
+        alert_weighted_bc: matches.value_of("alert-weighted-bc")
+            .map(|bc| f64::from_str(bc).expect("Cannot read alert-weighted-bc parameter")),
+        alert_streak: matches.value_of("alert-streak")
+            .map(|k| usize::from_str(k).expect("Cannot read alert-streak parameter"))
+            .expect("alert-streak has a default"),
+        dump_stride: matches.value_of("dump-stride")
+            .map(|n| usize::from_str(n).expect("Cannot read dump-stride parameter")),
+        max_dump_files: matches.value_of("max-dump-files")
+            .map(|n| usize::from_str(n).expect("Cannot read max-dump-files parameter")),
+        rotate_dumps: matches.is_present("rotate-dumps"),
+        communication_matrix: matches.is_present("comm-matrix"),
+        dump_write_retries: matches.value_of("dump-retries")
+            .map(|n| u32::from_str(n).expect("Cannot read dump-retries parameter"))
+            .expect("dump-retries has a default"),
+        state_profile: matches.is_present("state-profile"),
+        parallelism: matches.is_present("parallelism"),
+        stream_input: matches.is_present("stream-input"),
+        reorder_window_ns: matches.value_of("reorder-window")
+            .map(|n| u64::from_str(n).expect("Cannot read reorder-window parameter"))
+            .expect("reorder-window has a default"),
+        summary_format: matches.value_of("summary-format")
+            .map(|f| f.parse().expect("Cannot read summary-format parameter"))
+            .unwrap_or_default(),
+        write_pag_dot_combined: matches.is_present("pag-dot-combined"),
+        write_bc_msgpack: matches.is_present("bc-msgpack"),
+        include_edge_types: matches.value_of("include-edge-types").map(|edge_types| {
+            edge_types.split(',')
+                .map(|name| ActivityType::from_str(name.trim()).expect("Cannot read include-edge-types parameter"))
+                .collect()
+        }),
+        window_stride_ns: matches.value_of("window-stride").map(|s| {
+            (f64::from_str(s).expect("Cannot read window-stride parameter") * (NS_TO_SEC as f64)) as u64
+        }),
+        summary_sink: None,
+        log_paths: matches.values_of("merge-trace")
+            .map(|paths| paths.map(String::from).collect())
+            .unwrap_or_default(),
+        quiet_epochs: matches.is_present("quiet-epochs"),
+        bc_top_k: matches.value_of("bc-top-k").map(|k| usize::from_str(k).expect("Cannot read bc-top-k parameter")),
+        write_pag_graphml: matches.is_present("pag-graphml"),
+        write_pag_msgpack_sharded: matches.is_present("pag-msgpack-sharded"),
+        dump_histogram: matches.is_present("histogram"),
+        histogram_buckets: matches.value_of("histogram-buckets").map(|buckets| {
+            buckets.split(',')
+                .map(|ns| u64::from_str(ns.trim()).expect("Cannot read histogram-buckets parameter"))
+                .collect()
+        }),
+        socket_idle_timeout_ms: matches.value_of("socket-idle-timeout")
+            .map(|ms| u64::from_str(ms).expect("Cannot read socket-idle-timeout parameter"))
+            .expect("socket-idle-timeout has a default"),
+        metrics_addr: matches.value_of("metrics-addr").map(String::from),
+        bc_traverse: matches.value_of("bc-traverse")
+            .map(|m| m.parse().expect("Cannot read bc-traverse parameter"))
+            .unwrap_or_default(),
+        dry_run: matches.is_present("dry-run"),
+        reorder_input: matches.is_present("reorder-input"),
+        operator_summary: matches.is_present("operator-summary"),
+        logical_windows: None,
+        max_weight_path: matches.is_present("max-weight-path"),
+        output_dir: matches.value_of("output-dir").map(String::from),
+        mark_empty_epochs: matches.is_present("mark-empty-epochs"),
+        threshold_percentile: matches.value_of("threshold-percentile")
+            .map(|p| f64::from_str(p).expect("Cannot read threshold-percentile parameter")),
+        dump_seed_edges: matches.is_present("dump-seed-edges"),
+        max_windows: matches.value_of("max-windows")
+            .map(|w| u64::from_str(w).expect("Cannot read max-windows parameter")),
+        report_skew: matches.is_present("report-skew"),
+        longest_path: matches.is_present("longest-path"),
+        message_delay_mode: matches.value_of("message-delay-mode")
+            .map(|mode| mode.parse().expect("Cannot read message-delay-mode parameter"))
+            .unwrap_or_default(),
+        weight_stats: matches.is_present("weight-stats"),
+        max_inflight_windows: matches.value_of("max-inflight-windows")
+            .map(|n| usize::from_str(n).expect("Cannot read max-inflight-windows parameter"))
+            .unwrap_or(1),
+        summary_parquet_path: matches.value_of("summary-parquet").map(String::from),
+        focus_operators: matches.value_of("focus-operators").map(|operators| {
+            operators.split(',')
+                .map(|id| u64::from_str(id.trim()).expect("Cannot read focus-operators parameter"))
+                .collect()
+        }),
+        focus_unattributed: matches.is_present("focus-unattributed"),
+        validate_pag: matches.is_present("validate-pag"),
+        max_buffered_edges_per_epoch: matches.value_of("max-buffered-edges-per-epoch")
+            .map(|n| usize::from_str(n).expect("Cannot read max-buffered-edges-per-epoch parameter")),
+        write_pag_chrome: matches.is_present("pag-chrome"),
+        activity_labels: matches.values_of("activity-label")
+            .map(|labels| labels.map(activity_label).collect()),
     };
 
-    run_dataflow(config).unwrap();
+    if config.explain {
+        println!("{}", config.explain());
+        return;
+    }
+
+    let guards = run_dataflow(config).unwrap_or_else(|why| {
+        eprintln!("error: {}", why);
+        std::process::exit(1);
+    });
+    for result in guards.join() {
+        match result {
+            Ok(Ok(())) => (),
+            Ok(Err(why)) => {
+                eprintln!("error: {}", why);
+                std::process::exit(1);
+            }
+            Err(why) => panic!("worker failed: {}", why),
+        }
+    }
 }
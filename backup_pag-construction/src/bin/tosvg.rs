@@ -162,7 +162,7 @@ fn main() {
 
     let message_delay = matches
         .value_of("message-delay")
-        .map(|m| m.parse::<u64>().expect("message-delay must be u64"));
+        .map(|m| m.parse::<pag_construction::input::MessageDelay>().expect("Cannot read message-delay"));
 
     let log_path = matches.value_of("INPUT").unwrap();
     let out_path = matches.value_of("output").unwrap();
@@ -177,7 +177,8 @@ fn main() {
 
     let (records, workers) =
         {
-            let mut records = pag_construction::input::read_sorted_trace_from_file_and_cut_messages(log_path, message_delay);
+            let mut records = pag_construction::input::read_sorted_trace_from_file_and_cut_messages(&[log_path.to_string()], message_delay, None, Default::default())
+                .expect("couldn't read trace");
             let workers = pag_construction::input::workers_in_trace(&records);
             ::pag_construction::input::infer_correlator_ids(&workers, &mut records);
             (records, workers)
@@ -0,0 +1,204 @@
+// Copyright 2017 ETH Zurich. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Encodes PAGs as a CBOR sequence (RFC 8742) for consumption by other languages.
+//!
+//! Every `PagOutput` is a self-contained CBOR map, so readers can decode a file incrementally.
+//! Start and end nodes are `{"kind": "start" | "end", "node": NODE}`, edges are
+//! `{"kind": "edge", "source": NODE, "destination": NODE, "activity": NAME,
+//! "operator_address": [ID, ...] | null, "injected_delay_ns": NS, "traverse": TRAVERSAL}`
+//! where nodes are `{"timestamp_ns": NS, "worker": ID}`.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use serde_cbor::Value;
+
+use crate::{OperatorAddress, PagEdge, PagNode, PagOutput, TraversalType};
+
+fn text(s: &str) -> Value {
+    Value::Text(s.to_string())
+}
+
+fn map(entries: Vec<(&str, Value)>) -> Value {
+    Value::Map(entries.into_iter().map(|(key, value)| (text(key), value)).collect())
+}
+
+fn node_to_value(node: &PagNode) -> Value {
+    map(vec![("timestamp_ns", Value::Integer(node.timestamp.as_nanos() as i128)),
+             ("worker", Value::Integer(i128::from(node.worker_id)))])
+}
+
+fn traverse_name(traverse: &TraversalType) -> &'static str {
+    match *traverse {
+        TraversalType::Undefined => "undefined",
+        TraversalType::Block => "block",
+        TraversalType::Unbounded => "unbounded",
+    }
+}
+
+/// Encodes a single PAG element.
+pub fn to_value(output: &PagOutput) -> Value {
+    match *output {
+        PagOutput::StartNode(ref node) => map(vec![("kind", text("start")), ("node", node_to_value(node))]),
+        PagOutput::EndNode(ref node) => map(vec![("kind", text("end")), ("node", node_to_value(node))]),
+        PagOutput::Edge(ref edge) => {
+            let operator_address = match edge.operator_address {
+                Some(ref address) => {
+                    Value::Array(address.0.iter().map(|id| Value::Integer(i128::from(*id))).collect())
+                }
+                None => Value::Null,
+            };
+            map(vec![("kind", text("edge")),
+                     ("source", node_to_value(&edge.source)),
+                     ("destination", node_to_value(&edge.destination)),
+                     ("activity", text(edge.edge_type.name())),
+                     ("operator_address", operator_address),
+                     ("injected_delay_ns", Value::Integer(i128::from(edge.injected_delay_ns))),
+                     ("traverse", text(traverse_name(&edge.traverse)))])
+        }
+    }
+}
+
+fn field<'a>(value: &'a BTreeMap<Value, Value>, name: &str) -> Result<&'a Value, String> {
+    value.get(&text(name)).ok_or_else(|| format!("missing field {:?}", name))
+}
+
+fn as_map(value: &Value) -> Result<&BTreeMap<Value, Value>, String> {
+    match *value {
+        Value::Map(ref map) => Ok(map),
+        ref other => Err(format!("expected a map, found {:?}", other)),
+    }
+}
+
+fn as_u64(value: &Value) -> Result<u64, String> {
+    match *value {
+        Value::Integer(i) if i >= 0 && i <= i128::from(u64::max_value()) => Ok(i as u64),
+        ref other => Err(format!("expected an unsigned integer, found {:?}", other)),
+    }
+}
+
+fn as_text(value: &Value) -> Result<&str, String> {
+    match *value {
+        Value::Text(ref s) => Ok(s),
+        ref other => Err(format!("expected a string, found {:?}", other)),
+    }
+}
+
+fn node_from_value(value: &Value) -> Result<PagNode, String> {
+    let node = as_map(value)?;
+    Ok(PagNode {
+        timestamp: Duration::from_nanos(as_u64(field(node, "timestamp_ns")?)?),
+        worker_id: as_u64(field(node, "worker")?)?,
+    })
+}
+
+/// Decodes a single PAG element encoded by `to_value`.
+pub fn from_value(value: &Value) -> Result<PagOutput, String> {
+    let output = as_map(value)?;
+    match as_text(field(output, "kind")?)? {
+        "start" => Ok(PagOutput::StartNode(node_from_value(field(output, "node")?)?)),
+        "end" => Ok(PagOutput::EndNode(node_from_value(field(output, "node")?)?)),
+        "edge" => {
+            let operator_address = match *field(output, "operator_address")? {
+                Value::Null => None,
+                Value::Array(ref ids) => {
+                    Some(OperatorAddress::new(ids.iter().map(as_u64).collect::<Result<_, _>>()?))
+                }
+                ref other => return Err(format!("expected an operator address, found {:?}", other)),
+            };
+            let traverse = match as_text(field(output, "traverse")?)? {
+                "undefined" => TraversalType::Undefined,
+                "block" => TraversalType::Block,
+                "unbounded" => TraversalType::Unbounded,
+                other => return Err(format!("unknown traversal type {:?}", other)),
+            };
+            Ok(PagOutput::Edge(PagEdge {
+                source: node_from_value(field(output, "source")?)?,
+                destination: node_from_value(field(output, "destination")?)?,
+                edge_type: as_text(field(output, "activity")?)?.parse()?,
+                operator_id: operator_address.as_ref().map(OperatorAddress::operator_id),
+                operator_address,
+                injected_delay_ns: as_u64(field(output, "injected_delay_ns")?)?,
+                traverse,
+            }))
+        }
+        other => Err(format!("unknown PAG element kind {:?}", other)),
+    }
+}
+
+/// Appends `outputs` to `writer` as a CBOR sequence.
+pub fn write<'a, W, I>(writer: &mut W, outputs: I) -> serde_cbor::Result<()>
+    where W: Write,
+          I: IntoIterator<Item = &'a PagOutput>
+{
+    for output in outputs {
+        serde_cbor::to_writer(&mut *writer, &to_value(output))?;
+    }
+    Ok(())
+}
+
+/// Reads a CBOR sequence of PAG elements, e.g. a file written by `DumpPAG::dump_cbor`.
+pub fn read<P: AsRef<Path>>(path: P) -> Result<Vec<PagOutput>, String> {
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|why| format!("couldn't open {:?}: {:?}", path, why))?;
+    serde_cbor::Deserializer::from_reader(BufReader::new(file))
+        .into_iter::<Value>()
+        .map(|value| from_value(&value.map_err(|why| format!("{:?}", why))?))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use logformat::ActivityType;
+
+    fn node(timestamp: u64, worker_id: u64) -> PagNode {
+        PagNode {
+            timestamp: Duration::from_nanos(timestamp),
+            worker_id,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let outputs = vec![PagOutput::StartNode(node(10, 0)),
+                           PagOutput::Edge(PagEdge {
+                               source: node(10, 0),
+                               destination: node(25, 1),
+                               edge_type: ActivityType::DataMessage,
+                               operator_id: Some(3),
+                               operator_address: Some(OperatorAddress::new(vec![0, 3])),
+                               injected_delay_ns: 5,
+                               traverse: TraversalType::Unbounded,
+                           }),
+                           PagOutput::Edge(PagEdge {
+                               source: node(25, 1),
+                               destination: node(40, 1),
+                               edge_type: ActivityType::Waiting,
+                               operator_id: None,
+                               operator_address: None,
+                               injected_delay_ns: 0,
+                               traverse: TraversalType::Block,
+                           }),
+                           PagOutput::EndNode(node(40, 1))];
+
+        let path = std::env::temp_dir().join(format!("pag_cbor_round_trip_{}.cbor", std::process::id()));
+        {
+            let mut file = File::create(&path).unwrap();
+            write(&mut file, &outputs).unwrap();
+        }
+        let read_back = read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read_back, outputs);
+    }
+}
@@ -7,8 +7,15 @@
 // except according to those terms.
 
 use std;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
+use std::cell::RefCell;
 use std::convert::From as StdFrom;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
 use std::time::Duration;
 
 use rand::seq::SliceRandom;
@@ -18,7 +25,8 @@ use time;
 use abomonation::Abomonation;
 
 use timely;
-use timely::communication::allocator::Allocate;
+use timely::ExchangeData;
+use timely::communication::allocator::{Allocate, Generic};
 use timely::communication::initialize::WorkerGuards;
 use timely::dataflow::channels::pact;
 use timely::dataflow::operators::aggregation::Aggregate;
@@ -26,42 +34,1163 @@ use timely::dataflow::operators::exchange::Exchange;
 use timely::dataflow::operators::generic::operator::Operator;
 use timely::dataflow::operators::input::Handle as InputHandle;
 use timely::dataflow::operators::probe::Handle as ProbeHandle;
-use timely::dataflow::operators::{Accumulate, Filter, Input, Inspect, Map, Probe};
-use timely::dataflow::Scope;
+use timely::dataflow::operators::{Accumulate, Concat, Delay, Filter, Input, Inspect, Map, Probe};
+use timely::dataflow::scopes::Child;
+use timely::dataflow::{Scope, Stream};
+use timely::progress::Timestamp;
 use timely::worker::Worker;
 
+use crate::anonymize::Anonymizer;
 use crate::input;
-use crate::output::{DumpPAG, DumpHistogram};
-use crate::BuildProgramActivityGraph;
-use crate::{PagOutput, TraverseNoWaiting};
+use crate::slack;
+use crate::sqlite::{SharedSqliteOutput, SqliteOutput};
+use crate::input::{ClippedMessages, CorruptRecordPolicy, MessageDelay, MessageDelayMode, ReadStatus, TraceError};
+use crate::output::{self, DumpBC, DumpFiles, DumpOptions, DumpPAG, DumpHistogram};
+use crate::{BuildProgramActivityGraph, PagOptions};
+use crate::{activity_label, EdgeIdentity, EdgeIdentityFn, OperatorAddress, PagEdge, PagNode, PagOutput, TraversalType};
+use crate::{window_bounds, LogicalWindows, TraverseIncludingWaiting, TraverseNoWaiting};
 
-use logformat::LogRecord;
+use logformat::{ActivityType, LogRecord};
+use logformat::pair::Pair;
 
-use snailtrail::exploration::{BetweennessCentrality, SinglePath};
+use json::JsonValue;
+
+use futures::channel::mpsc::{self as async_mpsc, UnboundedReceiver, UnboundedSender};
+
+use snailtrail::exploration::{BetweennessCentrality, CriticalPath, MaxWeightPath, SinglePath, SpillConfig};
 use snailtrail::graph::SrcDst;
 use snailtrail::hash_code;
 
-use rand::thread_rng;
+use rand::rngs::StdRng;
+use rand::{thread_rng, Rng, SeedableRng};
 
-#[derive(Clone)]
+/// Version of the output formats (column layout of SUMMARY/SP_SUMMARY lines etc.).
+/// Bump whenever the columns of any output change.
+pub const OUTPUT_FORMAT_VERSION: u32 = 3;
+
+/// Name of the sidecar file describing the output formats of a run.
+pub const OUTPUT_MANIFEST_PATH: &str = "output_manifest.json";
+
+/// Name of the file recording the parameters and inputs of a run, see `Config::run_manifest_path`.
+pub const RUN_MANIFEST_FILE: &str = "manifest.json";
+
+const SUMMARY_COLUMNS: &str = "epoch,activity,operator,src,dst,crosses,bc,weighted_bc,count,weight,activity_name,bc_critical_share";
+const SP_SUMMARY_COLUMNS: &str = "epoch,worker,activity,operator,bc,weighted_bc,count,weight";
+const HEAT_COLUMNS: &str = "epoch,operator_id,total_weight";
+const SCOPE_SUMMARY_COLUMNS: &str = "epoch,scope,bc,weighted_bc,count,weight";
+const SCOPE_SP_SUMMARY_COLUMNS: &str = "epoch,scope,count,weight";
+const GROUPED_SUMMARY_COLUMNS: &str = "epoch,key,bc,weighted_bc,count,weight";
+const GLOBAL_SUMMARY_COLUMNS: &str = "activity,operator,src,dst,scope,bc,weighted_bc,count,weight";
+const BC_CONFIDENCE_COLUMNS: &str =
+    "epoch,src,src_ns,dst,dst_ns,activity,operator,bc,samples,stderr,ci_low,ci_high,approximate";
+const DEGENERATE_EDGES_COLUMNS: &str = "epoch,removed";
+const EPOCH_BOUNDS_COLUMNS: &str = "epoch,start_ns,end_ns";
+const CORR_COLUMNS: &str = "epoch,edges,pearson,spearman";
+const ALERT_COLUMNS: &str = "epoch,operator,epochs,weighted_bc";
+const ALERT_CLEAR_COLUMNS: &str = "epoch,operator";
+const COMM_MATRIX_COLUMNS: &str = "epoch,src,dst,count,weight";
+const STATE_PROFILE_COLUMNS: &str =
+    "epoch,operator,active,waiting,exchanging,active_fraction,waiting_fraction,exchanging_fraction";
+const PARALLELISM_COLUMNS: &str = "epoch,work,span,ratio";
+const PARALLELISM_TOTAL_COLUMNS: &str = "work,span,ratio";
+const HISTOGRAM_COLUMNS: &str = "epoch,bucket,count";
+const DRY_RUN_COLUMNS: &str = "epoch,records";
+const OPERATOR_SUMMARY_COLUMNS: &str = "epoch,operator,bc,count";
+const SKEW_COLUMNS: &str = "epoch,logical_ns,wallclock_ns,delta_ns";
+const LONGEST_PATH_COLUMNS: &str = "epoch,position,src,src_ns,dst,dst_ns,activity,operator,weight";
+const CLIPPED_MESSAGE_COLUMNS: &str = "sender,receiver,activity,sent_ns,received_ns,clipped_ns";
+const WEIGHT_STATS_COLUMNS: &str = "epoch,operator,count,min,max,mean,stddev";
+const COUNT_COLUMNS: &str = "epoch,worker,stream,count";
+const CONTENT_COLUMNS: &str = "epoch,worker,stream,content";
+const EPOCH_COLUMNS: &str = "stream,epoch,time_ns";
+const QUEUE_COLUMNS: &str = "epoch,depth";
+
+/// Headers of the outputs that are printed by every run (or with `-v`), printed once by worker
+/// 0 before the computation starts. Optional outputs print their header where they are set up.
+///
+/// Unlike most outputs, these lines separate their columns by spaces, and so do their headers,
+/// see `print_header`. `QUEUE`, `EPOCH_BOUNDS` and `SKEW` lines are space separated as well.
+const HEADERS: &[(&str, &str)] = &[("COUNT", COUNT_COLUMNS),
+                                   ("CONTENT", CONTENT_COLUMNS),
+                                   ("SP_SUMMARY", SP_SUMMARY_COLUMNS),
+                                   ("EPOCH", EPOCH_COLUMNS)];
+
+/// Prints the header of the space separated output `prefix` with the comma separated `columns`.
+fn print_header(prefix: &str, columns: &str) {
+    println!("# {} {}", prefix, columns.replace(',', " "));
+}
+const SLACK_COLUMNS: &str =
+    "epoch,src_worker,src_timestamp,dst_worker,dst_timestamp,activity,operator,earliest_start,latest_start,slack";
+
+/// What to do with the summaries of the first and last epoch of a trace. The capture usually
+/// starts and stops in the middle of these windows, so their summaries are misleadingly low.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PartialWindows {
+    /// Print them like any other epoch (the default)
+    Keep,
+    /// Do not print them
+    Drop,
+    /// Print a `# PARTIAL <epoch>` line before them
+    Tag,
+}
+
+impl Default for PartialWindows {
+    fn default() -> Self {
+        PartialWindows::Keep
+    }
+}
+
+impl std::str::FromStr for PartialWindows {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(PartialWindows::Keep),
+            "drop" => Ok(PartialWindows::Drop),
+            "tag" => Ok(PartialWindows::Tag),
+            _ => Err(format!("unknown partial window policy {:?}", s)),
+        }
+    }
+}
+
+/// How far the analysis pipeline runs. Each mode includes the stages of the previous ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnalysisMode {
+    /// Only construct the PAG and write the PAG outputs
+    PagOnly,
+    /// Compute BC as well, with its outputs, but no summaries
+    PagAndBc,
+    /// Run the whole pipeline including summaries (the default)
+    Full,
+}
+
+impl Default for AnalysisMode {
+    fn default() -> Self {
+        AnalysisMode::Full
+    }
+}
+
+impl std::str::FromStr for AnalysisMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pag-only" => Ok(AnalysisMode::PagOnly),
+            "pag-and-bc" => Ok(AnalysisMode::PagAndBc),
+            "full" => Ok(AnalysisMode::Full),
+            _ => Err(format!("unknown analysis mode {:?}", s)),
+        }
+    }
+}
+
+/// Which PAG edges BC and the critical path traverse.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraverseMode {
+    /// Do not traverse waiting edges, see `TraverseNoWaiting` (the default)
+    NoWaiting,
+    /// Traverse waiting edges as well, see `TraverseIncludingWaiting`
+    IncludeWaiting,
+}
+
+impl Default for TraverseMode {
+    fn default() -> Self {
+        TraverseMode::NoWaiting
+    }
+}
+
+impl std::str::FromStr for TraverseMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "no-waiting" => Ok(TraverseMode::NoWaiting),
+            "include-waiting" => Ok(TraverseMode::IncludeWaiting),
+            _ => Err(format!("unknown traverse mode {:?}", s)),
+        }
+    }
+}
+
+impl TraverseMode {
+    /// Whether a traversal in this mode follows `output`.
+    fn traverses(self, output: &PagOutput) -> bool {
+        match (self, output) {
+            (_, &PagOutput::StartNode(_)) |
+            (_, &PagOutput::EndNode(_)) => false,
+            (TraverseMode::NoWaiting, &PagOutput::Edge(ref e)) => e.traverse != TraversalType::Block,
+            (TraverseMode::IncludeWaiting, &PagOutput::Edge(_)) => true,
+        }
+    }
+}
+
+/// Why the analysis could not run to completion.
+#[derive(Debug)]
+pub enum DataflowError {
+    /// Timely could not start the workers, e.g. because of invalid arguments
+    TimelyInit(String),
+    /// The trace could not be opened or read
+    TraceIo(std::io::Error),
+    /// A record of the trace could not be decoded, see `CorruptRecordPolicy`
+    TraceDecode(String),
+    /// The configuration is invalid
+    Config(String),
+    /// The record at `index` of the input is earlier than the record before it, see
+    /// `Config::reorder_input`
+    UnsortedInput {
+        index: usize,
+        previous: Duration,
+        timestamp: Duration,
+    },
+}
+
+impl std::fmt::Display for DataflowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            DataflowError::TimelyInit(ref why) => write!(f, "couldn't start timely: {}", why),
+            DataflowError::TraceIo(ref why) => write!(f, "couldn't read the trace: {}", why),
+            DataflowError::TraceDecode(ref why) => write!(f, "couldn't decode the trace: {}", why),
+            DataflowError::Config(ref why) => write!(f, "invalid configuration: {}", why),
+            DataflowError::UnsortedInput { index, previous, timestamp } => {
+                write!(f,
+                       "the input is not sorted: record {} at {:?} is earlier than record {} at {:?}",
+                       index,
+                       timestamp,
+                       index - 1,
+                       previous)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DataflowError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            DataflowError::TraceIo(ref why) => Some(why),
+            _ => None,
+        }
+    }
+}
+
+impl From<TraceError> for DataflowError {
+    fn from(error: TraceError) -> Self {
+        match error {
+            TraceError::Io(why) => DataflowError::TraceIo(why),
+            TraceError::Decode(why) => DataflowError::TraceDecode(why),
+        }
+    }
+}
+
+impl From<std::io::Error> for DataflowError {
+    fn from(error: std::io::Error) -> Self {
+        DataflowError::TraceIo(error)
+    }
+}
+
+/// How `SUMMARY` lines are formatted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SummaryFormat {
+    /// Comma-separated values in the order of the `# SUMMARY` header (the default)
+    Csv,
+    /// A JSON object per line with the column names of the header as keys
+    Jsonl,
+}
+
+impl Default for SummaryFormat {
+    fn default() -> Self {
+        SummaryFormat::Csv
+    }
+}
+
+impl std::str::FromStr for SummaryFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(SummaryFormat::Csv),
+            "jsonl" => Ok(SummaryFormat::Jsonl),
+            _ => Err(format!("unknown summary format {:?}", s)),
+        }
+    }
+}
+
+/// Selects PAG nodes, e.g. the start and end nodes that delimit paths for BC.
+pub type NodePredicate = Arc<dyn Fn(&PagNode) -> bool + Send + Sync>;
+
+#[derive(Clone, Default)]
 pub struct Config {
     pub timely_args: Vec<String>,
     pub log_path: String,
     pub threshold: u64,
-    pub window_size_ns: u32,
+    pub window_size_ns: u64,
     pub epochs: u64,
-    pub message_delay: Option<u64>,
+    /// Clip messages to this delay, see `MessageDelay`
+    pub message_delay: Option<MessageDelay>,
     pub verbose: u64,
     pub dump_pag: bool,
     pub write_bc_dot: bool,
     pub write_pag_dot: bool,
     pub write_pag_msgpack: bool,
+    /// Write the PAG of each epoch as a CBOR sequence (see `cbor`)
+    pub write_pag_cbor: bool,
+    /// Write the PAG of each epoch with BC as Cypher statements for Neo4j
+    pub write_pag_cypher: bool,
     pub insert_waiting_edges: bool,
-    pub disable_summary: bool,
-    pub disable_bc: bool,
+    /// Which stages of the pipeline run
+    pub mode: AnalysisMode,
     pub waiting_message: u64,
+    /// Spill BC traversal state to disk once more than this many edges are buffered
+    pub bc_spill_threshold: Option<usize>,
+    /// Only analyze about one in this many records; results are approximate
+    pub sample_every: Option<usize>,
+    /// Write a long-format `epoch,operator_id,total_weight` CSV to this path
+    pub heat_csv_path: Option<String>,
+    pub write_pag_html: bool,
+    /// Only count paths starting at the `StartNode`s selected by this predicate (default: all)
+    pub bc_sources: Option<NodePredicate>,
+    /// Only count paths ending at the `EndNode`s selected by this predicate (default: all)
+    pub bc_sinks: Option<NodePredicate>,
+    pub corrupt_record_policy: CorruptRecordPolicy,
+    /// Only print the distribution of edge counts per epoch, skipping BC and summaries
+    pub edge_distribution_only: bool,
+    /// Cost multiplier per `operator_id` applied to edge weights for `weighted_bc` (default: 1.0)
+    pub operator_cost_multipliers: HashMap<logformat::OperatorId, f64>,
+    /// Synthetic delay in ns added to the weight of edges of each activity type (what-if analysis)
+    pub delay_injection: HashMap<ActivityType, u64>,
+    /// Print a ranked report of the operators on the critical path across the whole run
+    pub critical_path_report: bool,
+    /// Maximum number of epochs buffered by the `SeedEdge` and `count` stages before the oldest
+    /// is dropped with an error (default: unbounded)
+    pub max_buffered_epochs: Option<usize>,
+    /// Write edges, BC values and summaries to a SQLite database at this path
+    pub sqlite_path: Option<String>,
+    /// The database at `sqlite_path`, opened by `run_dataflow` before building the dataflow.
+    /// Without it, `build_dataflow` opens the database itself and skips the SQLite output if it
+    /// cannot.
+    pub sqlite_output: Option<SharedSqliteOutput>,
+    /// Additionally summarize BC and the critical path per dataflow scope (region)
+    pub group_by_scope: bool,
+    /// Print the earliest and latest start and the slack of every edge (critical path method)
+    pub compute_slack: bool,
+    /// Additionally print summaries grouped by this key
+    pub summary_grouping: SummaryGrouping,
+    /// Write the processing edges to `tracy/pag.json` as a Chrome JSON trace. Tracy cannot open
+    /// it directly, convert it with Tracy's `import-chrome` tool first.
+    pub write_pag_tracy: bool,
+    /// Additionally compute BC once over the union of the PAGs of all epochs (see `global_bc`)
+    pub global_bc: bool,
+    /// How to treat the summaries of the first and last epochs, which are usually partial
+    pub partial_windows: PartialWindows,
+    /// Only describe the dataflow that would be built (see `Config::explain`) instead of running it
+    pub explain: bool,
+    /// Estimate BC from the paths of about this fraction of the start nodes of every epoch
+    /// instead of all of them (default: exact BC). Start nodes are picked by a hash of the node,
+    /// and BC values are scaled by the inverse of the fraction
+    pub bc_sample_fraction: Option<f64>,
+    /// Print a `BC_CONFIDENCE` line per edge with its BC, the number of paths it was estimated
+    /// from and the standard error and 95% confidence interval of the estimate, see
+    /// `bc_confidence`
+    pub bc_confidence: bool,
+    /// Send the summaries of each epoch here, see `epoch_results`
+    pub epoch_results: Option<EpochResultSender>,
+    /// Identifies the same edge across epochs (default: `EdgeIdentity::of`)
+    pub edge_identity: Option<EdgeIdentityFn>,
+    /// Print zero-valued summaries for operators absent in an epoch (see `fill_absent_operators`)
+    pub fill_absent_operators: bool,
+    /// Remove self-loops and zero-duration edges from the PAG, printing how many per epoch
+    pub remove_degenerate_edges: bool,
+    /// Seed for picking the start of each epoch's single path; runs with the same seed pick the
+    /// same paths (default: random). BC sampling does not use it, see `bc_sample_fraction`
+    pub sp_seed: Option<u64>,
+    /// Print the time range of the trace covered by each epoch as `EPOCH_BOUNDS <epoch>
+    /// <start_ns> <end_ns>` once the epoch is complete
+    pub print_epoch_bounds: bool,
+    /// Read the trace on a background thread through a queue of at most this many records,
+    /// which must then be sorted by timestamp (default: read and sort the whole trace first)
+    pub input_queue_capacity: Option<usize>,
+    /// Print the correlation between the BC and the weight of the edges of each epoch
+    pub compare_metrics: bool,
+    /// Replace worker and operator IDs by sequential IDs in all output (see `anonymize`)
+    pub anonymize: bool,
+    /// Write the mapping from anonymized to original IDs to this (private) file
+    pub anonymize_mapping_path: Option<String>,
+    /// Alert when the weighted BC of an operator exceeds this value for `alert_streak` epochs
+    pub alert_weighted_bc: Option<f64>,
+    /// Number of consecutive epochs above `alert_weighted_bc` before alerting (at least 1)
+    pub alert_streak: usize,
+    /// Only write per-epoch dump files (DOT, msgpack, CBOR, Cypher) for every this many epochs
+    pub dump_stride: Option<usize>,
+    /// Maximum number of files written by each per-epoch dump
+    pub max_dump_files: Option<usize>,
+    /// Once `max_dump_files` is reached, replace the oldest file instead of stopping to dump
+    pub rotate_dumps: bool,
+    /// Print the count and weight of the communication between every pair of workers per epoch
+    pub communication_matrix: bool,
+    /// How often failed writes of per-epoch dump files and SQLite inserts are retried before
+    /// skipping the epoch
+    pub dump_write_retries: u32,
+    /// Print how the weight of each operator splits into work, waiting and communication
+    pub state_profile: bool,
+    /// Print the total edge weight (work), the critical path weight (span) and their ratio per
+    /// epoch and over the run
+    pub parallelism: bool,
+    /// Decode and feed the trace record by record instead of reading it into memory first
+    pub stream_input: bool,
+    /// How far out of order records may be when streaming the input
+    pub reorder_window_ns: u64,
+    /// How `SUMMARY` lines are formatted
+    pub summary_format: SummaryFormat,
+    /// Write the PAGs of all epochs to a single DOT file with a cluster per epoch
+    pub write_pag_dot_combined: bool,
+    /// Write the edges of each epoch with their BC as msgpack
+    pub write_bc_msgpack: bool,
+    /// Only keep PAG edges of these activity types, before BC and all other analyses. Paths
+    /// are only counted through the remaining edges. Waiting edges inserted because of
+    /// `insert_waiting_edges` are dropped unless `Waiting` is included.
+    pub include_edge_types: Option<Vec<ActivityType>>,
+    /// Start a window every this many nanoseconds. Windows overlap if this is smaller than
+    /// `window_size_ns` and each record is analyzed in every window containing it.
+    pub window_stride_ns: Option<u64>,
+    /// Pass `SUMMARY` tuples here instead of printing them, see `run_dataflow_with_sink`
+    pub summary_sink: Option<SummarySink>,
+    /// Further traces, e.g. one per worker, merged with `log_path` by timestamp
+    pub log_paths: Vec<String>,
+    /// Do not print an `EPOCH` line whenever the input or a probe advances
+    pub quiet_epochs: bool,
+    /// Only keep this many edges with the highest BC per epoch, for all outputs based on BC
+    pub bc_top_k: Option<usize>,
+    /// Write the PAG of each epoch as GraphML
+    pub write_pag_graphml: bool,
+    /// Write the PAG of each epoch as msgpack, a shard per worker (see `dump_msgpack_sharded`)
+    pub write_pag_msgpack_sharded: bool,
+    /// Print a histogram of the edge weights of each epoch
+    pub dump_histogram: bool,
+    /// Boundaries (ns) of the histogram buckets, powers of ten if not given
+    pub histogram_buckets: Option<Vec<u64>>,
+    /// With a `tcp://host:port` log path, end the input after this many ms without records
+    pub socket_idle_timeout_ms: u64,
+    /// Serve per-epoch counts as Prometheus metrics at `http://<addr>/metrics`, requires the
+    /// `metrics` feature
+    pub metrics_addr: Option<String>,
+    /// Which edges BC and the critical path traverse
+    pub bc_traverse: TraverseMode,
+    /// Only decode the trace, check that it is sorted and count its records per window
+    pub dry_run: bool,
+    /// Sort the input within `reorder_window_ns` before feeding it instead of failing on
+    /// records that are out of order
+    pub reorder_input: bool,
+    /// Print the BC of each epoch totaled per operator
+    pub operator_summary: bool,
+    /// The event time intervals of the epochs if they are logical epochs instead of windows of
+    /// `window_size_ns`, set by `build_dataflow_pair`
+    pub logical_windows: Option<LogicalWindows>,
+    /// Follow the heaviest edge at every node of a single path instead of a random one
+    pub max_weight_path: bool,
+    /// The directory to write the run manifest to instead of the working directory
+    pub output_dir: Option<String>,
+    /// Sets the unknown edge threshold to this percentile of the gaps between consecutive records
+    /// of a worker, determined by a first pass over the trace. Only used if `threshold` is 0.
+    /// The first pass reads the whole trace, so it is an error with `stream_input` or
+    /// `input_queue_capacity`.
+    pub threshold_percentile: Option<f64>,
+    /// Print the start node each single path is seeded from as `SEED <epoch> <edge>`, or
+    /// `SEED <epoch> NONE` for epochs without one
+    pub dump_seed_edges: bool,
+    /// Stop the input after this many windows from the first one, e.g. to explore the start of
+    /// a long trace
+    pub max_windows: Option<u64>,
+    /// Print a `SKEW` line per epoch relating the trace time to the wall-clock time at which
+    /// the last stage completed it
+    pub report_skew: bool,
+    /// Print the maximum-weight path from a start node to an end node of every epoch as
+    /// `LONGEST_PATH` lines, following the edges `bc_traverse` traverses
+    pub longest_path: bool,
+    /// Whether `message_delay` moves the timestamps of clipped messages or only reports them.
+    /// Either way, every clipped message is printed as a `CLIPPED_MESSAGE` line with its
+    /// original timestamps.
+    pub message_delay_mode: MessageDelayMode,
+    /// Print the count, minimum, maximum, mean and standard deviation of the edge weights of
+    /// every operator and epoch as `WEIGHT_STATS` lines
+    pub weight_stats: bool,
+    /// Let up to this many windows be processed at once before feeding the input waits for
+    /// the oldest one to complete; 0 and 1 process one window at a time. This overlaps the
+    /// stages of consecutive windows on multi-core machines, but every stage buffers the state
+    /// of all windows in flight (records, PAG edges, BC paths), so memory grows about linearly
+    /// with it. The windows in flight add to the lag of `epochs`: the input waits for all but
+    /// the last `epochs + max_inflight_windows - 1` windows. See also `max_buffered_epochs`.
+    pub max_inflight_windows: usize,
+    /// Write the summaries to this Parquet file as well, see `parquet_summary`. Requires the
+    /// `parquet-output` feature.
+    pub summary_parquet_path: Option<String>,
+    /// Only analyze the edges of these operators and the edges sharing a node with them, so that
+    /// paths through the operators are not severed. Start and end nodes are kept.
+    pub focus_operators: Option<Vec<u64>>,
+    /// Keep the edges without an operator that share a node with an edge of `focus_operators`,
+    /// which are dropped otherwise
+    pub focus_unattributed: bool,
+    /// Print `ORPHAN <epoch> <edge>` for every edge whose source is neither a start node nor the
+    /// destination of another edge, or whose destination is neither an end node nor the source
+    /// of another edge
+    pub validate_pag: bool,
+    /// Maximum number of edges buffered per epoch by the `SeedEdge` and `count` stages (default:
+    /// unbounded). Past it, `SeedEdge` keeps a single start node by reservoir sampling; `count`
+    /// needs all of its input and only warns
+    pub max_buffered_edges_per_epoch: Option<usize>,
+    /// Write all edges to `chrome/pag.json` as a Chrome trace, see `DumpPAG::dump_chrome_trace`
+    pub write_pag_chrome: bool,
+    /// Names of activity types in human-facing output (SUMMARY, CONTENT and DOT labels), keyed
+    /// by the numeric activity type. Types without a label keep `ActivityType::name`; the
+    /// `activity` column and other machine formats keep the number
+    pub activity_labels: Option<HashMap<u8, String>>,
+    /// Print `SUMMARY <epoch> EMPTY` and `SP_SUMMARY <epoch> EMPTY` for epochs without any
+    /// summaries, to tell them apart from epochs that were not processed. Summaries passed to a
+    /// `summary_sink` are not marked.
+    pub mark_empty_epochs: bool,
+}
+
+impl Config {
+    /// A builder starting from the defaults of `construct`, see `ConfigBuilder`.
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::new()
+    }
+
+    /// The distance between the starts of consecutive windows. Windows are tumbling (the stride
+    /// is the window size) unless a smaller `window_stride_ns` is given.
+    pub fn window_stride(&self) -> u64 {
+        match self.window_stride_ns {
+            Some(stride) if stride > 0 && stride < self.window_size_ns => stride,
+            _ => self.window_size_ns,
+        }
+    }
+
+    /// The window `feed_input` sorts the input within, if `reorder_input` is set.
+    pub fn input_reorder_window(&self) -> Option<Duration> {
+        if self.reorder_input {
+            Some(Duration::from_nanos(self.reorder_window_ns))
+        } else {
+            None
+        }
+    }
+
+    /// The window the streamed input is sorted within, if `stream_input` is set.
+    pub fn stream_reorder_window(&self) -> Option<Duration> {
+        if self.stream_input {
+            Some(Duration::from_nanos(self.reorder_window_ns))
+        } else {
+            None
+        }
+    }
+
+    /// The traces to read: `log_path` followed by `log_paths`.
+    pub fn trace_paths(&self) -> Vec<String> {
+        let mut paths = vec![self.log_path.clone()];
+        paths.extend(self.log_paths.iter().cloned());
+        paths
+    }
+
+    /// Where `run_dataflow` records the configuration and inputs of the run: `manifest.json` in
+    /// `output_dir`, or next to the dumps in the working directory.
+    pub fn run_manifest_path(&self) -> String {
+        match self.output_dir {
+            Some(ref dir) => Path::new(dir).join(RUN_MANIFEST_FILE).to_string_lossy().into_owned(),
+            None => RUN_MANIFEST_FILE.to_string(),
+        }
+    }
+
+    /// `message_delay` with `message_delay_mode` applied.
+    pub fn message_delay_with_mode(&self) -> Option<MessageDelay> {
+        self.message_delay.clone().map(|delay| MessageDelay {
+            mode: self.message_delay_mode,
+            ..delay
+        })
+    }
+
+    /// The options of dumps writing a file per epoch.
+    pub fn dump_options(&self) -> DumpOptions {
+        DumpOptions {
+            stride: self.dump_stride,
+            max_files: self.max_dump_files,
+            rotate: self.rotate_dumps,
+            write_retries: self.dump_write_retries,
+            activity_labels: self.activity_labels.clone(),
+        }
+    }
+
+    /// Describes the dataflow `build_dataflow` would construct for this configuration, its
+    /// outputs and any risky combinations of options, without running anything.
+    pub fn explain(&self) -> String {
+        let mut lines = Vec::new();
+        let mut warnings = Vec::new();
+
+        lines.push(format!("Input: {} with the timely arguments {:?}", self.log_path, self.timely_args));
+        if self.dry_run {
+            lines.push("  dry run: the trace is only decoded and checked, nothing is analyzed".to_string());
+        }
+        if !self.log_paths.is_empty() {
+            lines.push(format!("  merged by timestamp with {}", self.log_paths.join(", ")));
+        }
+        if let Some(addr) = input::socket_address(&self.log_path) {
+            lines.push(format!("  receiving records on {}, reordering within {:?}, ending after {:?} without records",
+                               addr,
+                               Duration::from_nanos(self.reorder_window_ns),
+                               Duration::from_millis(self.socket_idle_timeout_ms)));
+            if self.message_delay.is_some() {
+                warnings.push("message clipping is not applied to records received over TCP");
+            }
+            if !self.log_paths.is_empty() {
+                warnings.push("traces cannot be merged with records received over TCP");
+            }
+        }
+        if let Some(n) = self.sample_every {
+            lines.push(format!("  sampling about 1 in {} records, results are approximate", n));
+        }
+        if let Some(ref delay) = self.message_delay {
+            lines.push(format!("  clipping messages longer than {} ns", delay));
+            if self.message_delay_mode == MessageDelayMode::Annotate {
+                lines.push("  clipped messages are only reported, their timestamps are kept".to_string());
+            }
+        }
+        lines.push(format!("  corrupt records: {:?}", self.corrupt_record_policy));
+        if self.reorder_input {
+            lines.push(format!("  sorting records that are out of order by at most {:?}",
+                               Duration::from_nanos(self.reorder_window_ns)));
+        } else {
+            lines.push("  records out of order abort the analysis".to_string());
+        }
+        if let Some(capacity) = self.input_queue_capacity {
+            lines.push(format!("  reading in the background, at most {} records queued", capacity));
+            if self.message_delay.is_some() {
+                warnings.push("message clipping is not applied with --input-queue");
+            }
+        } else if self.stream_input {
+            lines.push(format!("  streaming records, reordering within {:?}",
+                               Duration::from_nanos(self.reorder_window_ns)));
+        }
+        lines.push(format!("PAG: windows of {:?}, 1+{} windows in flight, unknown edge threshold {:?}",
+                           Duration::from_nanos(self.window_size_ns),
+                           self.epochs,
+                           Duration::from_nanos(self.threshold)));
+        if let Some(max_windows) = self.max_windows {
+            lines.push(format!("  only the first {} windows are analyzed", max_windows));
+        }
+        if self.max_inflight_windows > 1 {
+            lines.push(format!("  up to {} windows are processed at once", self.max_inflight_windows));
+            warnings.push("--max-inflight-windows buffers the state of every window in flight");
+        }
+        if let (Some(percentile), 0) = (self.threshold_percentile, self.threshold) {
+            lines.push(format!("  the unknown edge threshold is percentile {} of the gaps between records",
+                               percentile));
+        }
+        if self.window_stride() < self.window_size_ns {
+            lines.push(format!("  sliding windows starting every {:?}, records are analyzed once per window",
+                               Duration::from_nanos(self.window_stride())));
+        }
+        if let Some(ref edge_types) = self.include_edge_types {
+            lines.push(format!("  only edges of the activity types {:?}", edge_types));
+            if self.insert_waiting_edges && !edge_types.contains(&ActivityType::Waiting) {
+                warnings.push("inserted waiting edges are dropped, Waiting is not an included edge type");
+            }
+        }
+        if let Some(ref operators) = self.focus_operators {
+            lines.push(format!("  only edges of the operators {:?} and their neighbors{}",
+                               operators,
+                               if self.focus_unattributed { ", including edges without an operator" } else { "" }));
+        } else if self.focus_unattributed {
+            warnings.push("--focus-unattributed has no effect without --focus-operators");
+        }
+        lines.push(format!("  waiting edges: {}",
+                           if self.insert_waiting_edges { "inserted" } else { "unknown for all gaps" }));
+        if !self.delay_injection.is_empty() {
+            lines.push(format!("  injecting synthetic delay (ns) {:?}", self.delay_injection));
+        }
+        if self.remove_degenerate_edges {
+            lines.push("  removing self-loops and zero-duration edges".to_string());
+        }
+        if self.validate_pag {
+            lines.push("  reporting edges that are not connected to the rest of the PAG".to_string());
+        }
+        if self.anonymize {
+            lines.push("  anonymizing worker and operator IDs".to_string());
+        }
+
+        let mut outputs = Vec::new();
+        if let Some(ref addr) = self.metrics_addr {
+            outputs.push(format!("Prometheus metrics on http://{}/metrics", addr));
+            if cfg!(not(feature = "metrics")) {
+                warnings.push("--metrics-addr requires building with the metrics feature");
+            }
+        }
+        if self.dump_seed_edges {
+            outputs.push("single-path seeds on stdout".to_string());
+        }
+        if self.dump_pag {
+            outputs.push("PAG contents on stdout".to_string());
+            warnings.push("--dump-pag prints every PAG element and produces a lot of output");
+        }
+        if self.write_pag_dot {
+            outputs.push("DOT files dot/pag*.dot".to_string());
+        }
+        if self.write_pag_dot_combined {
+            outputs.push("DOT file of all windows dot/combined.dot".to_string());
+            warnings.push("--pag-dot-combined keeps the PAGs of all windows in memory");
+        }
+        if self.write_pag_chrome {
+            outputs.push("Chrome trace chrome/pag.json".to_string());
+        }
+        if self.write_pag_graphml {
+            outputs.push("GraphML files graphml/pag_*.graphml".to_string());
+        }
+        if self.write_pag_msgpack {
+            outputs.push("msgpack files msgpack_pag/output*".to_string());
+        }
+        if self.write_pag_msgpack_sharded {
+            outputs.push("msgpack shards msgpack_pag/output*-w<worker>.msgpack, one per worker".to_string());
+        }
+        if self.write_pag_cbor {
+            outputs.push("CBOR files cbor_pag/pag_*.cbor".to_string());
+        }
+        if self.write_pag_html {
+            outputs.push("HTML viewer html/pag.html".to_string());
+            warnings.push("the HTML viewer keeps the PAGs of all windows in memory");
+        }
+        if self.write_pag_tracy {
+            outputs.push("Chrome trace for Tracy's import-chrome tracy/pag.json".to_string());
+        }
+        if let Some(ref path) = self.sqlite_path {
+            outputs.push(format!("SQLite database {}", path));
+        }
+        if let Some(ref path) = self.summary_parquet_path {
+            outputs.push(format!("Parquet summaries {}", path));
+        }
+
+        if self.edge_distribution_only {
+            lines.push("Stages: edge count distribution only, BC and summaries are skipped".to_string());
+        } else if self.mode == AnalysisMode::PagOnly {
+            lines.push("Stages: PAG only, BC is disabled".to_string());
+        } else {
+            let mut stages = vec![match self.bc_traverse {
+                                      TraverseMode::NoWaiting => "BC",
+                                      TraverseMode::IncludeWaiting => "BC including waiting edges",
+                                  }];
+            if self.bc_top_k.is_some() {
+                stages.push("top-k edges by BC");
+            }
+            if self.global_bc {
+                stages.push("global BC over all windows");
+                warnings.push("--global-bc keeps the PAG of the whole trace in memory on worker 0");
+            }
+            if self.compute_slack {
+                stages.push("slack");
+            }
+            if self.longest_path {
+                stages.push("longest start-to-end paths");
+            }
+            if self.compare_metrics {
+                stages.push("BC/weight correlation");
+            }
+            if self.mode == AnalysisMode::Full {
+                stages.push("summaries");
+                stages.push(if self.max_weight_path {
+                    "single-path summaries (heaviest paths)"
+                } else {
+                    "single-path summaries"
+                });
+                if self.group_by_scope {
+                    stages.push("scope summaries");
+                }
+                if self.operator_summary {
+                    stages.push("operator summaries");
+                }
+                if self.communication_matrix {
+                    stages.push("communication matrices");
+                }
+                if let Some(threshold) = self.alert_weighted_bc {
+                    stages.push("bottleneck alerts");
+                    lines.push(format!("  alerting on weighted BC above {} for {} windows",
+                                       threshold,
+                                       self.alert_streak.max(1)));
+                }
+                if self.fill_absent_operators {
+                    stages.push("zero-filled summaries");
+                    warnings.push("--fill-absent-operators holds back all summaries until the input is complete");
+                }
+                if self.critical_path_report {
+                    stages.push("critical path report");
+                }
+            }
+            if self.parallelism {
+                stages.push("work/span parallelism");
+            }
+            lines.push(format!("Stages: {}", stages.join(", ")));
+            if self.bc_sources.is_some() || self.bc_sinks.is_some() {
+                lines.push("  BC only counts paths between the selected start and end nodes".to_string());
+            }
+            if let Some(fraction) = self.bc_sample_fraction {
+                lines.push(format!("  BC is estimated from the paths of {} of the start nodes", fraction));
+            }
+            if let Some(threshold) = self.bc_spill_threshold {
+                lines.push(format!("  BC spills to disk above {} buffered edges", threshold));
+            }
+            if !self.operator_cost_multipliers.is_empty() {
+                lines.push(format!("  weighted BC uses the cost multipliers {:?}",
+                                   self.operator_cost_multipliers));
+            }
+            if let Some(max_epochs) = self.max_buffered_epochs {
+                lines.push(format!("  at most {} windows are buffered, older ones are dropped", max_epochs));
+            }
+            if let Some(max_edges) = self.max_buffered_edges_per_epoch {
+                lines.push(format!("  seed edges are sampled from at most {} buffered edges per window",
+                                   max_edges));
+            }
+            if let Some(ref path) = self.heat_csv_path {
+                outputs.push(format!("heat CSV {}", path));
+            }
+            if self.write_bc_dot {
+                outputs.push("DOT files with BC dot/bc*.dot".to_string());
+            }
+            if self.write_bc_msgpack {
+                outputs.push("msgpack files with BC msgpack_bc/output{pag,bc}_*.msgpack".to_string());
+            }
+            if self.write_pag_cypher {
+                outputs.push("Cypher files cypher/pag_*.cypher".to_string());
+            }
+            if self.mode == AnalysisMode::PagAndBc &&
+               (self.group_by_scope || self.critical_path_report || self.heat_csv_path.is_some()) {
+                warnings.push("summary options have no effect without summaries");
+            }
+        }
+        if self.edge_distribution_only && (self.global_bc || self.compute_slack) {
+            warnings.push("BC options have no effect with --edge-distribution");
+        }
+        match self.threshold_percentile {
+            Some(_) if self.threshold == 0 && (self.stream_input || self.input_queue_capacity.is_some()) => {
+                warnings.push("--threshold-percentile reads the whole trace up front and cannot be combined \
+                               with --stream-input or --input-queue");
+            }
+            Some(_) if self.threshold == 0 => (),
+            Some(_) => warnings.push("the unknown edge threshold takes precedence over --threshold-percentile"),
+            None if self.threshold == 0 => {
+                warnings.push("the unknown edge threshold is 0, every gap becomes an edge");
+            }
+            None => (),
+        }
+
+        outputs.push(format!("output manifest {}", OUTPUT_MANIFEST_PATH));
+        outputs.push(format!("run manifest {}", self.run_manifest_path()));
+        lines.push(format!("Outputs: {}", outputs.join(", ")));
+        for warning in warnings {
+            lines.push(format!("WARNING {}", warning));
+        }
+        lines.join("\n")
+    }
+
+    /// Returns the configuration as JSON, e.g. for recording it next to the outputs.
+    pub fn to_json(&self) -> JsonValue {
+        object!{
+            "timely_args" => self.timely_args.clone(),
+            "log_path" => self.log_path.clone(),
+            "threshold" => self.threshold,
+            "window_size_ns" => self.window_size_ns,
+            "epochs" => self.epochs,
+            "message_delay" => self.message_delay.as_ref().map(MessageDelay::to_string),
+            "verbose" => self.verbose,
+            "dump_pag" => self.dump_pag,
+            "write_bc_dot" => self.write_bc_dot,
+            "write_pag_dot" => self.write_pag_dot,
+            "write_pag_msgpack" => self.write_pag_msgpack,
+            "write_pag_cbor" => self.write_pag_cbor,
+            "write_pag_cypher" => self.write_pag_cypher,
+            "insert_waiting_edges" => self.insert_waiting_edges,
+            "mode" => format!("{:?}", self.mode),
+            "waiting_message" => self.waiting_message,
+            "bc_spill_threshold" => self.bc_spill_threshold,
+            "sample_every" => self.sample_every,
+            "heat_csv_path" => self.heat_csv_path.clone(),
+            "write_pag_html" => self.write_pag_html,
+            "bc_sources_restricted" => self.bc_sources.is_some(),
+            "bc_sinks_restricted" => self.bc_sinks.is_some(),
+            "corrupt_record_policy" => format!("{:?}", self.corrupt_record_policy),
+            "edge_distribution_only" => self.edge_distribution_only,
+            "operator_cost_multipliers" => {
+                let mut multipliers = JsonValue::new_object();
+                for (operator_id, multiplier) in &self.operator_cost_multipliers {
+                    multipliers[operator_id.to_string()] = (*multiplier).into();
+                }
+                multipliers
+            },
+            "delay_injection" => {
+                let mut delays = JsonValue::new_object();
+                for (activity_type, delay) in &self.delay_injection {
+                    delays[activity_type.name()] = (*delay).into();
+                }
+                delays
+            },
+            "critical_path_report" => self.critical_path_report,
+            "max_buffered_epochs" => self.max_buffered_epochs,
+            "sqlite_path" => self.sqlite_path.clone(),
+            "sqlite_output" => self.sqlite_output.is_some(),
+            "group_by_scope" => self.group_by_scope,
+            "compute_slack" => self.compute_slack,
+            "summary_grouping" => self.summary_grouping.name(),
+            "write_pag_tracy" => self.write_pag_tracy,
+            "global_bc" => self.global_bc,
+            "partial_windows" => format!("{:?}", self.partial_windows),
+            "explain" => self.explain,
+            "bc_sample_fraction" => self.bc_sample_fraction,
+            "bc_confidence" => self.bc_confidence,
+            "epoch_results" => self.epoch_results.is_some(),
+            "custom_edge_identity" => self.edge_identity.is_some(),
+            "fill_absent_operators" => self.fill_absent_operators,
+            "remove_degenerate_edges" => self.remove_degenerate_edges,
+            "sp_seed" => self.sp_seed,
+            "print_epoch_bounds" => self.print_epoch_bounds,
+            "input_queue_capacity" => self.input_queue_capacity,
+            "compare_metrics" => self.compare_metrics,
+            "anonymize" => self.anonymize,
+            "alert_weighted_bc" => self.alert_weighted_bc,
+            "alert_streak" => self.alert_streak,
+            "dump_stride" => self.dump_stride,
+            "max_dump_files" => self.max_dump_files,
+            "rotate_dumps" => self.rotate_dumps,
+            "communication_matrix" => self.communication_matrix,
+            "dump_write_retries" => self.dump_write_retries,
+            "state_profile" => self.state_profile,
+            "parallelism" => self.parallelism,
+            "stream_input" => self.stream_input,
+            "reorder_window_ns" => self.reorder_window_ns,
+            "summary_format" => format!("{:?}", self.summary_format),
+            "write_pag_dot_combined" => self.write_pag_dot_combined,
+            "write_bc_msgpack" => self.write_bc_msgpack,
+            "include_edge_types" => self.include_edge_types.as_ref().map(|edge_types| {
+                edge_types.iter().map(|edge_type| edge_type.name()).collect::<Vec<_>>()
+            }),
+            "window_stride_ns" => self.window_stride_ns,
+            "summary_sink" => self.summary_sink.is_some(),
+            "log_paths" => self.log_paths.clone(),
+            "quiet_epochs" => self.quiet_epochs,
+            "bc_top_k" => self.bc_top_k,
+            "write_pag_graphml" => self.write_pag_graphml,
+            "write_pag_msgpack_sharded" => self.write_pag_msgpack_sharded,
+            "dump_histogram" => self.dump_histogram,
+            "histogram_buckets" => self.histogram_buckets.clone(),
+            "socket_idle_timeout_ms" => self.socket_idle_timeout_ms,
+            "metrics_addr" => self.metrics_addr.clone(),
+            "bc_traverse" => format!("{:?}", self.bc_traverse),
+            "dry_run" => self.dry_run,
+            "reorder_input" => self.reorder_input,
+            "operator_summary" => self.operator_summary,
+            "logical_windows" => self.logical_windows.is_some(),
+            "max_weight_path" => self.max_weight_path,
+            "output_dir" => self.output_dir.clone(),
+            "mark_empty_epochs" => self.mark_empty_epochs,
+            "threshold_percentile" => self.threshold_percentile,
+            "dump_seed_edges" => self.dump_seed_edges,
+            "max_windows" => self.max_windows,
+            "report_skew" => self.report_skew,
+            "longest_path" => self.longest_path,
+            "message_delay_mode" => format!("{:?}", self.message_delay_mode),
+            "weight_stats" => self.weight_stats,
+            "max_inflight_windows" => self.max_inflight_windows,
+            "summary_parquet_path" => self.summary_parquet_path.clone(),
+            "focus_operators" => self.focus_operators.clone(),
+            "focus_unattributed" => self.focus_unattributed,
+            "validate_pag" => self.validate_pag,
+            "max_buffered_edges_per_epoch" => self.max_buffered_edges_per_epoch,
+            "write_pag_chrome" => self.write_pag_chrome,
+            "activity_labels" => self.activity_labels.as_ref().map(|labels| {
+                let mut object = JsonValue::new_object();
+                for (activity_type, label) in labels {
+                    object[activity_type.to_string()] = label.clone().into();
+                }
+                object
+            })
+        }
+    }
+}
+
+/// Builds a `Config` for running the analysis from another program.
+///
+/// All outputs besides the summaries are off, and options without a setter keep the defaults
+/// of `construct`; use `configure` to change them.
+#[derive(Clone)]
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+impl ConfigBuilder {
+    pub fn new() -> Self {
+        ConfigBuilder {
+            config: Config {
+                alert_streak: 3,
+                dump_write_retries: 3,
+                socket_idle_timeout_ms: 5000,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Arguments for timely, e.g. `-w 4`. Without arguments the analysis runs on one worker.
+    pub fn timely_args<I: IntoIterator<Item = String>>(mut self, args: I) -> Self {
+        self.config.timely_args = args.into_iter().collect();
+        self
+    }
+
+    pub fn log_path<P: Into<String>>(mut self, log_path: P) -> Self {
+        self.config.log_path = log_path.into();
+        self
+    }
+
+    /// The unknown edge threshold in ns.
+    pub fn threshold(mut self, threshold: u64) -> Self {
+        self.config.threshold = threshold;
+        self
+    }
+
+    pub fn window_size_ns(mut self, window_size_ns: u64) -> Self {
+        self.config.window_size_ns = window_size_ns;
+        self
+    }
+
+    /// The number of epochs in flight.
+    pub fn epochs(mut self, epochs: u64) -> Self {
+        self.config.epochs = epochs;
+        self
+    }
+
+    /// Clip messages longer than `message_delay` ns.
+    pub fn message_delay(mut self, message_delay: u64) -> Self {
+        self.config.message_delay = Some(message_delay.into());
+        self
+    }
+
+    /// Clip messages longer than the delay of their activity type, see `MessageDelay`.
+    pub fn message_delay_per_activity(mut self, message_delay: MessageDelay) -> Self {
+        self.config.message_delay = Some(message_delay);
+        self
+    }
+
+    pub fn verbose(mut self, verbose: u64) -> Self {
+        self.config.verbose = verbose;
+        self
+    }
+
+    pub fn with_waiting_edges(mut self, insert_waiting_edges: bool) -> Self {
+        self.config.insert_waiting_edges = insert_waiting_edges;
+        self
+    }
+
+    /// Changes any other option.
+    pub fn configure<F: FnOnce(&mut Config)>(mut self, configure: F) -> Self {
+        configure(&mut self.config);
+        self
+    }
+
+    /// The configuration, or an error if the window size or `sample_every` is zero or no log path
+    /// was given.
+    pub fn build(self) -> Result<Config, String> {
+        if self.config.window_size_ns == 0 {
+            return Err("the window size must be positive".to_string());
+        }
+        if self.config.log_path.is_empty() {
+            return Err("a log path is required".to_string());
+        }
+        if self.config.sample_every == Some(0) {
+            return Err("sample_every must be positive".to_string());
+        }
+        Ok(self.config)
+    }
+}
+
+impl Default for ConfigBuilder {
+    fn default() -> Self {
+        ConfigBuilder::new()
+    }
+}
+
+/// Writes a sidecar file describing the format version, the column order of the textual
+/// outputs, the configuration and the crate version, so that consumers can check
+/// compatibility before parsing.
+fn write_output_manifest(config: &Config, path: &str) -> std::io::Result<()> {
+    fn columns(csv: &str) -> JsonValue {
+        csv.split(',').collect::<Vec<_>>().into()
+    }
+    let mut manifest = object!{
+        "format_version" => OUTPUT_FORMAT_VERSION,
+        "approximate" => config.sample_every.is_some(),
+        // The precision of sampled BC is reported per edge by `BC_CONFIDENCE`
+        "bc_estimator" => if config.bc_sample_fraction.is_some() { "sampled" } else { "exact" },
+        "delay_injected" => !config.delay_injection.is_empty(),
+        "crate_version" => env!("CARGO_PKG_VERSION"),
+        "columns" => object!{
+            "SUMMARY" => columns(SUMMARY_COLUMNS),
+            "HEAT" => columns(HEAT_COLUMNS),
+            "SCOPE_SUMMARY" => columns(SCOPE_SUMMARY_COLUMNS),
+            "SCOPE_SP_SUMMARY" => columns(SCOPE_SP_SUMMARY_COLUMNS),
+            "GROUPED_SUMMARY" => columns(GROUPED_SUMMARY_COLUMNS),
+            "GLOBAL_SUMMARY" => columns(GLOBAL_SUMMARY_COLUMNS),
+            "DEGENERATE_EDGES" => columns(DEGENERATE_EDGES_COLUMNS),
+            "EPOCH_BOUNDS" => columns(EPOCH_BOUNDS_COLUMNS),
+            "CORR" => columns(CORR_COLUMNS),
+            "ALERT" => columns(ALERT_COLUMNS),
+            "ALERT_CLEAR" => columns(ALERT_CLEAR_COLUMNS),
+            "COMM_MATRIX" => columns(COMM_MATRIX_COLUMNS),
+            "STATE_PROFILE" => columns(STATE_PROFILE_COLUMNS),
+            "SLACK" => columns(SLACK_COLUMNS),
+            "PARALLELISM" => columns(PARALLELISM_COLUMNS),
+            "PARALLELISM_TOTAL" => columns(PARALLELISM_TOTAL_COLUMNS),
+            "HISTOGRAM" => columns(HISTOGRAM_COLUMNS),
+            "DRY_RUN" => columns(DRY_RUN_COLUMNS),
+            "OPERATOR_SUMMARY" => columns(OPERATOR_SUMMARY_COLUMNS),
+            "SKEW" => columns(SKEW_COLUMNS),
+            "LONGEST_PATH" => columns(LONGEST_PATH_COLUMNS),
+            "CLIPPED_MESSAGE" => columns(CLIPPED_MESSAGE_COLUMNS),
+            "WEIGHT_STATS" => columns(WEIGHT_STATS_COLUMNS),
+            "BC_CONFIDENCE" => columns(BC_CONFIDENCE_COLUMNS),
+            "QUEUE" => columns(QUEUE_COLUMNS)
+        },
+        // The outputs whose columns are separated by spaces instead of commas
+        "space_separated" => HEADERS.iter()
+            .map(|&(prefix, _)| prefix)
+            .chain(vec!["QUEUE", "EPOCH_BOUNDS", "SKEW"])
+            .collect::<Vec<_>>(),
+        "config" => config.to_json()
+    };
+    for &(prefix, csv) in HEADERS {
+        manifest["columns"][prefix] = columns(csv);
+    }
+    let mut file = File::create(path)?;
+    file.write_all(manifest.pretty(2).as_bytes())
+}
+
+/// Formats `time` as an RFC 3339 UTC timestamp.
+fn utc_timestamp(time: std::time::SystemTime) -> String {
+    let since_epoch = time.duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+    let timespec = time::Timespec::new(since_epoch.as_secs() as i64, since_epoch.subsec_nanos() as i32);
+    time::at_utc(timespec).rfc3339().to_string()
 }
 
+/// Writes the run manifest, recording the full configuration, the crate version, the size and
+/// modification time of every input file and the start time of the run, so that archived
+/// results can be traced back to what produced them.
+fn write_run_manifest(config: &Config, path: &str, started: std::time::SystemTime) -> std::io::Result<()> {
+    let inputs: Vec<JsonValue> = config.trace_paths()
+        .into_iter()
+        .map(|log_path| {
+            let metadata = std::fs::metadata(&log_path).ok();
+            object!{
+                "path" => log_path,
+                "size" => metadata.as_ref().map(|metadata| metadata.len()),
+                "modified" => metadata.and_then(|metadata| metadata.modified().ok()).map(utc_timestamp)
+            }
+        })
+        .collect();
+    let manifest = object!{
+        "crate_version" => env!("CARGO_PKG_VERSION"),
+        "started" => utc_timestamp(started),
+        "inputs" => inputs,
+        "config" => config.to_json()
+    };
+    let mut file = File::create(path)?;
+    file.write_all(manifest.pretty(2).as_bytes())
+}
 
 #[derive(Abomonation, Debug, Clone, Default)]
 struct Summary<T: Abomonation> {
@@ -76,213 +1205,2201 @@ trait ImpreciseFrom<T> {
     fn from(value: T) -> Self;
 }
 
-impl<T> ImpreciseFrom<T> for T {
-    fn from(value: T) -> T {
-        value
+impl<T> ImpreciseFrom<T> for T {
+    fn from(value: T) -> T {
+        value
+    }
+}
+impl ImpreciseFrom<u64> for f64 {
+    fn from(value: u64) -> f64 {
+        value as f64
+    }
+}
+
+/// Force a type to be the same type as the target.
+///
+/// This is useful when Rust cannot determine the appropriate target type for a computation.
+trait SameType {
+    fn same_type(&self, typed: Self) -> Self;
+}
+
+impl<T> SameType for T {
+    fn same_type(&self, typed: Self) -> Self {
+        typed
+    }
+}
+
+impl<T: Abomonation + std::ops::Add<Output = T> + Copy> std::ops::AddAssign for Summary<T> {
+    fn add_assign(&mut self, other: Self) {
+        *self = Self {
+            bc: self.bc + other.bc,
+            weighted_bc: self.weighted_bc + other.weighted_bc,
+            weight: self.weight + other.weight,
+            count: self.count + other.count,
+        }
+    }
+}
+
+/// What an edge says about the operator it belongs to, for `STATE_PROFILE` output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum OperatorState {
+    /// Worker-local work, e.g. processing, scheduling or (de)serialization
+    Active = 0,
+    /// Waiting, including gaps of unknown activity
+    Waiting = 1,
+    /// Messages between workers
+    Exchanging = 2,
+}
+
+impl OperatorState {
+    fn of(activity_type: ActivityType) -> Self {
+        match activity_type {
+            ActivityType::Waiting | ActivityType::BusyWaiting | ActivityType::Unknown => OperatorState::Waiting,
+            _ if activity_type.is_worker_local() => OperatorState::Active,
+            _ => OperatorState::Exchanging,
+        }
+    }
+}
+
+/// Running statistics of edge weights, for `WEIGHT_STATS` output.
+#[derive(Clone, Copy, Debug)]
+struct WeightStats {
+    count: u64,
+    sum: u64,
+    sum_of_squares: f64,
+    min: u64,
+    max: u64,
+}
+
+impl Default for WeightStats {
+    fn default() -> Self {
+        WeightStats {
+            count: 0,
+            sum: 0,
+            sum_of_squares: 0.,
+            min: u64::max_value(),
+            max: 0,
+        }
+    }
+}
+
+impl WeightStats {
+    fn add(&mut self, weight: u64) {
+        self.count += 1;
+        self.sum += weight;
+        self.sum_of_squares += weight as f64 * weight as f64;
+        self.min = self.min.min(weight);
+        self.max = self.max.max(weight);
+    }
+
+    fn mean(&self) -> f64 {
+        self.sum as f64 / self.count as f64
+    }
+
+    /// The population standard deviation.
+    fn stddev(&self) -> f64 {
+        let mean = self.mean();
+        (self.sum_of_squares / self.count as f64 - mean * mean).max(0.).sqrt()
+    }
+}
+
+/// Key for aggregation. Local indicates a worker-local activity, with its `Worker` ID.
+/// Remote indicates a cross-worker activity, with source and destination.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Abomonation, Clone)]
+enum ActivityWorkers {
+    Local(logformat::Worker),
+    Remote(logformat::Worker, logformat::Worker),
+}
+
+/// The summary of a single edge with its BC.
+///
+/// `weighted_bc` scales the edge weight by the operator's cost multiplier (if any), while
+/// `weight` itself stays wall-clock time.
+fn edge_summary(edge: &PagOutput,
+                bc: f64,
+                operator_cost_multipliers: &HashMap<logformat::OperatorId, f64>)
+                -> Summary<f64> {
+    let w = edge.weight();
+    let cost_multiplier = match *edge {
+        PagOutput::Edge(ref e) => {
+            e.operator_address
+                .as_ref()
+                .and_then(|address| operator_cost_multipliers.get(&address.operator_id()))
+                .cloned()
+                .unwrap_or(1.0)
+        }
+        _ => 1.0,
+    };
+    Summary {
+        weight: w,
+        bc: bc,
+        weighted_bc: bc * bc.same_type(ImpreciseFrom::from(w)) * cost_multiplier,
+        count: 1,
+    }
+}
+
+/// Computes the key of an edge for `GROUPED_SUMMARY` output.
+pub type SummaryKeyFn = Arc<dyn Fn(&PagOutput) -> String + Send + Sync>;
+
+/// How to group edges in `GROUPED_SUMMARY` output, in addition to the `SUMMARY` output which is
+/// always grouped by `(activity_type, operator, workers, crosses)`.
+#[derive(Clone)]
+pub enum SummaryGrouping {
+    /// Only the default `SUMMARY` output
+    Default,
+    ActivityType,
+    Operator,
+    Scope,
+    SourceWorker,
+    /// Group by the key returned by a user-supplied function
+    Custom(SummaryKeyFn),
+}
+
+impl Default for SummaryGrouping {
+    fn default() -> Self {
+        SummaryGrouping::Default
+    }
+}
+
+impl std::str::FromStr for SummaryGrouping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(SummaryGrouping::Default),
+            "activity" => Ok(SummaryGrouping::ActivityType),
+            "operator" => Ok(SummaryGrouping::Operator),
+            "scope" => Ok(SummaryGrouping::Scope),
+            "worker" => Ok(SummaryGrouping::SourceWorker),
+            _ => Err(format!("unknown summary grouping {:?}", s)),
+        }
+    }
+}
+
+impl SummaryGrouping {
+    fn name(&self) -> &'static str {
+        match *self {
+            SummaryGrouping::Default => "default",
+            SummaryGrouping::ActivityType => "activity",
+            SummaryGrouping::Operator => "operator",
+            SummaryGrouping::Scope => "scope",
+            SummaryGrouping::SourceWorker => "worker",
+            SummaryGrouping::Custom(_) => "custom",
+        }
+    }
+
+    /// The grouping key of `edge`, or `None` for the default grouping.
+    fn key(&self, edge: &PagOutput) -> Option<String> {
+        let e = match *edge {
+            PagOutput::Edge(ref e) => e,
+            ref et => panic!("Unknown input: {:?}", et),
+        };
+        match *self {
+            SummaryGrouping::Default => None,
+            SummaryGrouping::ActivityType => Some(e.edge_type.name().to_string()),
+            SummaryGrouping::Operator => Some(scalar_operator_id(&e.operator_address).to_string()),
+            SummaryGrouping::Scope => Some(scope_name(&e.scope())),
+            SummaryGrouping::SourceWorker => Some(e.source.worker_id.to_string()),
+            SummaryGrouping::Custom(ref key_fn) => Some(key_fn(edge)),
+        }
+    }
+}
+
+/// A summary of an epoch, corresponding to a `SUMMARY` line of the text output.
+#[derive(Clone, Debug)]
+pub struct SummaryRow {
+    pub activity_type: ActivityType,
+    pub operator_id: u64,
+    pub src: u64,
+    pub dst: u64,
+    pub crosses: char,
+    pub bc: f64,
+    pub weighted_bc: f64,
+    pub count: u64,
+    pub weight: u64,
+}
+
+/// The results of an epoch, see `epoch_results`.
+#[derive(Clone, Debug)]
+pub struct EpochResult {
+    pub epoch: Duration,
+    pub summaries: Vec<SummaryRow>,
+}
+
+/// Receives the results of each epoch on worker 0.
+pub type EpochResultSender = Arc<Mutex<UnboundedSender<EpochResult>>>;
+
+/// A `SUMMARY` tuple as passed to a `SummarySink`.
+#[derive(Clone, Debug)]
+pub struct SummaryRecord {
+    pub epoch: Duration,
+    pub summary: SummaryRow,
+    /// The share of the summarized weight on the epoch's critical path
+    pub bc_critical_share: f64,
+    /// Whether the epoch is a partial window, see `PartialWindows`
+    pub partial: bool,
+}
+
+/// Receives every `SUMMARY` tuple on worker 0, see `run_dataflow_with_sink`.
+pub type SummarySink = Arc<Mutex<Box<dyn FnMut(SummaryRecord) + Send>>>;
+
+type SummaryKey = (ActivityType, Option<OperatorAddress>, ActivityWorkers, char);
+
+/// Prints `SUMMARY` lines in the configured format.
+///
+/// In CSV, partial windows are tagged with a `# PARTIAL <epoch>` line before their first
+/// summary. JSON objects have an additional `partial` field instead, so that every line after
+/// the `SUMMARY` prefix can be parsed on its own.
+struct SummaryWriter {
+    format: SummaryFormat,
+    last_tagged: Option<Duration>,
+    activity_labels: Option<HashMap<u8, String>>,
+}
+
+impl SummaryWriter {
+    fn new(format: SummaryFormat, activity_labels: Option<HashMap<u8, String>>) -> Self {
+        SummaryWriter {
+            format,
+            last_tagged: None,
+            activity_labels,
+        }
+    }
+
+    fn write(&mut self, epoch: &Duration, row: &SummaryRow, critical_share: f64, partial: bool) {
+        let activity_name = activity_label(row.activity_type, self.activity_labels.as_ref());
+        match self.format {
+            SummaryFormat::Csv => {
+                if partial && self.last_tagged != Some(*epoch) {
+                    self.last_tagged = Some(*epoch);
+                    println!("# PARTIAL {:?}", epoch);
+                }
+                println!("SUMMARY {:?},{},{},{},{},{},{},{},{},{},{},{}",
+                         epoch,
+                         row.activity_type as u8,
+                         row.operator_id,
+                         row.src,
+                         row.dst,
+                         row.crosses,
+                         row.bc,
+                         row.weighted_bc,
+                         row.count,
+                         row.weight,
+                         activity_name,
+                         critical_share);
+            }
+            SummaryFormat::Jsonl => {
+                let line = object!{
+                    "epoch" => format!("{:?}", epoch),
+                    "activity" => row.activity_type as u8,
+                    "operator" => row.operator_id,
+                    "src" => row.src,
+                    "dst" => row.dst,
+                    "crosses" => row.crosses.to_string(),
+                    "bc" => row.bc,
+                    "weighted_bc" => row.weighted_bc,
+                    "count" => row.count,
+                    "weight" => row.weight,
+                    "activity_name" => activity_name,
+                    "bc_critical_share" => critical_share,
+                    "partial" => partial
+                };
+                println!("SUMMARY {}", line.dump());
+            }
+        }
+    }
+}
+
+fn summary_row((key, summary): (SummaryKey, Summary<f64>)) -> SummaryRow {
+    let (activity_type, operator_address, workers, crosses) = key;
+    let (src, dst) = match workers {
+        ActivityWorkers::Local(w_id) => (w_id, w_id),
+        ActivityWorkers::Remote(src, dst) => (src, dst),
+    };
+    SummaryRow {
+        activity_type,
+        operator_id: scalar_operator_id(&operator_address),
+        src,
+        dst,
+        crosses,
+        bc: summary.bc,
+        weighted_bc: summary.weighted_bc,
+        count: summary.count,
+        weight: summary.weight,
+    }
+}
+
+/// The scope printed in scope summaries, `root` for edges in the top-level scope.
+fn scope_name(scope: &Option<OperatorAddress>) -> String {
+    scope.as_ref().map_or_else(|| "root".to_string(), OperatorAddress::to_string)
+}
+
+/// The scalar operator ID printed in summaries. Edges without an operator are printed as 255.
+fn scalar_operator_id(address: &Option<OperatorAddress>) -> u64 {
+    address.as_ref().map_or(255, OperatorAddress::operator_id)
+}
+
+/// Prints min/max/mean and percentiles of the number of edges per epoch.
+fn print_edge_distribution(mut edge_counts: Vec<usize>) {
+    if edge_counts.is_empty() {
+        println!("# EDGE DISTRIBUTION no epochs with edges");
+        return;
+    }
+    edge_counts.sort();
+    let percentile = |p: f64| {
+        edge_counts[((edge_counts.len() - 1) as f64 * p).round() as usize]
+    };
+    let total: usize = edge_counts.iter().sum();
+    println!("# EDGE DISTRIBUTION epochs,min,max,mean,p50,p90,p99");
+    println!("{},{},{},{:.1},{},{},{}",
+             edge_counts.len(),
+             edge_counts[0],
+             edge_counts[edge_counts.len() - 1],
+             total as f64 / edge_counts.len() as f64,
+             percentile(0.5),
+             percentile(0.9),
+             percentile(0.99));
+}
+
+/// Pearson correlation coefficient of `pairs`, `NaN` if either side is constant.
+fn pearson(pairs: &[(f64, f64)]) -> f64 {
+    let n = pairs.len() as f64;
+    let mean_x = pairs.iter().map(|p| p.0).sum::<f64>() / n;
+    let mean_y = pairs.iter().map(|p| p.1).sum::<f64>() / n;
+    let (mut cov, mut var_x, mut var_y) = (0., 0., 0.);
+    for &(x, y) in pairs {
+        cov += (x - mean_x) * (y - mean_y);
+        var_x += (x - mean_x) * (x - mean_x);
+        var_y += (y - mean_y) * (y - mean_y);
+    }
+    cov / (var_x * var_y).sqrt()
+}
+
+/// Ranks of `values` starting at 1, ties get the mean of their ranks.
+fn ranks(values: &[f64]) -> Vec<f64> {
+    let mut order: Vec<_> = (0..values.len()).collect();
+    order.sort_by(|&a, &b| values[a].partial_cmp(&values[b]).expect("values must not be NaN"));
+    let mut ranks = vec![0.; values.len()];
+    let mut start = 0;
+    while start < order.len() {
+        let mut end = start + 1;
+        while end < order.len() && values[order[end]] == values[order[start]] {
+            end += 1;
+        }
+        let rank = (start + end + 1) as f64 / 2.;
+        for &index in &order[start..end] {
+            ranks[index] = rank;
+        }
+        start = end;
+    }
+    ranks
+}
+
+/// Spearman rank correlation coefficient of `pairs`, i.e. the Pearson correlation of the ranks.
+fn spearman(pairs: &[(f64, f64)]) -> f64 {
+    let xs: Vec<_> = pairs.iter().map(|p| p.0).collect();
+    let ys: Vec<_> = pairs.iter().map(|p| p.1).collect();
+    let ranked: Vec<_> = ranks(&xs).into_iter().zip(ranks(&ys).into_iter()).collect();
+    pearson(&ranked)
+}
+
+/// Prints operators ranked by their cumulative weight on the critical path, together with the
+/// number of epochs they appeared in and their share of the total critical path weight.
+fn print_critical_path_report(per_operator: Vec<(Option<OperatorAddress>, (HashSet<Duration>, u64))>) {
+    let total: u64 = per_operator.iter().map(|&(_, (_, weight))| weight).sum();
+    let mut ranked: Vec<_> = per_operator
+        .into_iter()
+        .map(|(operator, (epochs, weight))| (operator, epochs.len(), weight))
+        .collect();
+    ranked.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.cmp(&b.0)));
+    println!("# CRITICAL PATH REPORT rank,operator,epochs,weight,percent");
+    for (rank, (operator, epochs, weight)) in ranked.into_iter().enumerate() {
+        let operator = operator.map_or_else(|| "-".to_string(), |address| address.to_string());
+        let percent = if total > 0 {
+            100. * weight as f64 / total as f64
+        } else {
+            0.
+        };
+        println!("CRITICAL_PATH {},{},{},{},{:.2}", rank + 1, operator, epochs, weight, percent);
+    }
+}
+
+/// Describes a PAG element in a `CONTENT` line, followed by the label of its activity type for
+/// edges.
+fn describe_output(output: &PagOutput, labels: Option<&HashMap<u8, String>>) -> String {
+    match *output {
+        PagOutput::Edge(ref e) => format!("{:?} {}", output, activity_label(e.edge_type, labels)),
+        _ => format!("{:?}", output),
+    }
+}
+
+/// Drops the oldest epochs from `state` while it holds more than `max_epochs`.
+///
+/// Operators buffer state per epoch until the epoch's notification arrives. If the computation
+/// stalls, epochs never close and the buffers grow without bounds; we rather drop the oldest
+/// epoch, whose results are lost, and report the stall than run out of memory.
+fn evict_stalled_epochs<V>(state: &mut HashMap<Duration, V>,
+                           max_epochs: Option<usize>,
+                           operator: &str) {
+    let max_epochs = match max_epochs {
+        Some(max_epochs) => max_epochs,
+        None => return,
+    };
+    while state.len() > max_epochs {
+        let oldest = *state.keys().min().expect("state is not empty");
+        state.remove(&oldest);
+        eprintln!("ERROR {} buffers more than {} epochs, the dataflow downstream of it seems \
+                   stalled; dropping epoch {:?}, its results will be incomplete",
+                  operator,
+                  max_epochs,
+                  oldest);
+    }
+}
+
+/// Warns once `operator` buffers more than `max_edges` edges of `epoch`, that is when a batch
+/// takes the buffer from `before` to `after` edges across the threshold.
+fn warn_on_large_epoch(before: usize,
+                       after: usize,
+                       max_edges: Option<usize>,
+                       operator: &str,
+                       epoch: &Duration) {
+    if let Some(max_edges) = max_edges {
+        if before <= max_edges && after > max_edges {
+            eprintln!("WARNING {} buffers more than {} edges of epoch {:?}, consider a shorter \
+                       window",
+                      operator,
+                      max_edges,
+                      epoch);
+        }
+    }
+}
+
+/// Adds `data` to the start nodes buffered for an epoch, of which `seen` have arrived so far.
+///
+/// Once more than `max_edges` have arrived, the buffer is reduced to a single, uniformly chosen
+/// start node that every further one replaces with probability `1 / seen` (reservoir sampling).
+/// This keeps the pick uniform, but unlike the sorted buffer it depends on the arrival order, so
+/// `sp_seed` no longer makes it reproducible across runs.
+fn sample_seed_edges<R: Rng>(accum: &mut Vec<PagOutput>,
+                             seen: &mut usize,
+                             data: &[PagOutput],
+                             max_edges: Option<usize>,
+                             rng: &mut R) {
+    let max_edges = match max_edges {
+        Some(max_edges) if *seen + data.len() > max_edges => max_edges,
+        _ => {
+            accum.extend_from_slice(data);
+            *seen += data.len();
+            return;
+        }
+    };
+    if *seen <= max_edges {
+        // First batch past the cap: collapse the buffer before sampling the rest
+        accum.extend_from_slice(data);
+        if let Some(elem) = accum.choose(rng).cloned() {
+            accum.clear();
+            accum.push(elem);
+        }
+        *seen += data.len();
+        return;
+    }
+    for elem in data {
+        *seen += 1;
+        if rng.gen_range(0, *seen) == 0 {
+            accum.clear();
+            accum.push(elem.clone());
+        }
+    }
+}
+
+/// Collects `stream` on worker 0 and passes the data of each epoch on once it is complete,
+/// sorted by `key`, so that its order does not depend on the schedule.
+fn sort_per_epoch<S, D, K, F>(stream: &Stream<S, D>, name: &str, key: F) -> Stream<S, D>
+    where S: Scope<Timestamp = Duration>,
+          D: ExchangeData,
+          K: Ord,
+          F: Fn(&D) -> K + 'static
+{
+    let mut per_epoch = HashMap::new();
+    stream.unary_notify(pact::Exchange::new(|_| 0), name, vec![], move |input, output, notificator| {
+        input.for_each(|time, data| {
+            per_epoch.entry(*time.time()).or_insert_with(Vec::new).extend(data.drain(..));
+            notificator.notify_at(time.retain());
+        });
+
+        notificator.for_each(|time, _count, _notify| {
+            let mut data = per_epoch.remove(time.time()).unwrap_or_default();
+            data.sort_by_key(|datum| key(datum));
+            output.session(&time).give_iterator(data.into_iter());
+        });
+    })
+}
+
+/// Collects `stream` on worker 0 and hands the data of each epoch to `write` once it is complete.
+fn write_per_epoch<S, D, F>(stream: &Stream<S, D>, name: &str, mut write: F)
+    where S: Scope<Timestamp = Duration>,
+          D: ExchangeData,
+          F: FnMut(&Duration, Vec<D>) + 'static
+{
+    let mut per_epoch = HashMap::new();
+    let mut vector = Vec::new();
+    stream.unary_notify::<(), _, _>(pact::Exchange::new(|_| 0),
+                                    name,
+                                    vec![],
+                                    move |input, _output, notificator| {
+        input.for_each(|time, data| {
+            data.swap(&mut vector);
+            per_epoch.entry(*time.time()).or_insert_with(Vec::new).extend(vector.drain(..));
+            notificator.notify_at(time.retain());
+        });
+        notificator.for_each(|time, _count, _notify| {
+            if let Some(data) = per_epoch.remove(time.time()) {
+                write(time.time(), data);
+            }
+        });
+    });
+}
+
+/// Calls `report` on worker 0 for every epoch without any `data`, in order, once no more data
+/// can arrive for it. Epochs are numbered by their nanoseconds and range from the first to the
+/// last epoch of `records`, so epochs without a single record in between are reported as well.
+fn report_empty_epochs<S, D1, D2, F>(records: &Stream<S, D1>, data: &Stream<S, D2>, name: &str, mut report: F)
+    where S: Scope<Timestamp = Duration>,
+          D1: ExchangeData,
+          D2: ExchangeData,
+          F: FnMut(&Duration) + 'static
+{
+    let mut first: Option<u64> = None;
+    let mut last = 0;
+    let mut next = None;
+    let mut nonempty = HashSet::new();
+    records.map(|_| ())
+        .binary_frontier::<_, (), _, _, _, _>(&data.map(|_| ()),
+                                              pact::Exchange::new(|_| 0),
+                                              pact::Exchange::new(|_| 0),
+                                              name,
+                                              |_cap, _info| {
+            move |records, data, _output| {
+                records.for_each(|time, _data| {
+                    let epoch = time.time().as_nanos() as u64;
+                    first = Some(first.map_or(epoch, |first| first.min(epoch)));
+                    last = last.max(epoch);
+                });
+                data.for_each(|time, _data| {
+                    nonempty.insert(time.time().as_nanos() as u64);
+                });
+                let first = match first {
+                    Some(first) => first,
+                    None => return,
+                };
+                let mut epoch = next.unwrap_or(first);
+                while epoch <= last {
+                    let time = Duration::from_nanos(epoch);
+                    if records.frontier().less_equal(&time) || data.frontier().less_equal(&time) {
+                        break;
+                    }
+                    if !nonempty.remove(&epoch) {
+                        report(&time);
+                    }
+                    epoch += 1;
+                    // Earlier epochs cannot appear any more once an epoch is complete
+                    next = Some(epoch);
+                }
+            }
+        });
+}
+
+/// Marks the data of the first and the last epoch of `stream` as partial.
+///
+/// Whether an epoch is the last one is only known once the input is complete, so the data of
+/// the latest epoch is held back until a later epoch completes or the input ends.
+fn mark_partial_windows<S, D>(stream: &Stream<S, D>) -> Stream<S, (D, bool)>
+    where S: Scope<Timestamp = Duration>,
+          D: ExchangeData
+{
+    let mut buffered = HashMap::new();
+    let mut first = None;
+    let mut vector = Vec::new();
+    stream.unary_frontier(pact::Exchange::new(|_| 0), "MarkPartialWindows", |_cap, _info| {
+        move |input, output| {
+            input.for_each(|time, data| {
+                data.swap(&mut vector);
+                let epoch = *time.time();
+                if first.map_or(true, |first| epoch < first) {
+                    first = Some(epoch);
+                }
+                buffered.entry(epoch)
+                    .or_insert_with(|| (time.retain(), Vec::new()))
+                    .1
+                    .extend(vector.drain(..));
+            });
+            let input_complete = input.frontier().frontier().is_empty();
+            let latest = buffered.keys().max().cloned();
+            let mut complete: Vec<_> = buffered
+                .keys()
+                .filter(|epoch| !input.frontier().less_equal(epoch))
+                .filter(|&epoch| input_complete || Some(*epoch) != latest)
+                .cloned()
+                .collect();
+            complete.sort();
+            for epoch in complete {
+                let (cap, data) = buffered.remove(&epoch).expect("epoch is buffered");
+                let partial = Some(epoch) == first || (input_complete && Some(epoch) == latest);
+                output.session(&cap).give_iterator(data.into_iter().map(|d| (d, partial)));
+            }
+        }
+    })
+}
+
+/// Adds zero-valued summaries for the operators absent in an epoch, so that every epoch has a
+/// summary for every `(activity_type, operator_address, workers)` seen during the whole run.
+///
+/// The full set of operators is only known once the input is complete, so all summaries are
+/// held back until then. Filled-in summaries do not cross the window boundaries (`N`).
+fn fill_absent_operators<S>(stream: &Stream<S, (SummaryKey, Summary<f64>)>)
+                            -> Stream<S, (SummaryKey, Summary<f64>)>
+    where S: Scope<Timestamp = Duration>
+{
+    let mut buffered = HashMap::new();
+    let mut operators = HashSet::new();
+    let mut vector = Vec::new();
+    stream.unary_frontier(pact::Exchange::new(|_| 0), "FillAbsentOperators", |_cap, _info| {
+        move |input, output| {
+            input.for_each(|time, data| {
+                data.swap(&mut vector);
+                let entry = buffered
+                    .entry(*time.time())
+                    .or_insert_with(|| (time.retain(), Vec::new(), HashSet::new()));
+                for ((activity_type, operator_address, workers, crosses), summary) in vector.drain(..) {
+                    let operator = (activity_type, operator_address, workers);
+                    operators.insert(operator.clone());
+                    entry.2.insert(operator.clone());
+                    entry.1.push(((operator.0, operator.1, operator.2, crosses), summary));
+                }
+            });
+            if !input.frontier().frontier().is_empty() {
+                return;
+            }
+            let mut epochs: Vec<_> = buffered.keys().cloned().collect();
+            epochs.sort();
+            for epoch in epochs {
+                let (cap, mut summaries, present) = buffered.remove(&epoch).expect("epoch is buffered");
+                for operator in operators.iter().filter(|operator| !present.contains(operator)) {
+                    let (activity_type, ref operator_address, ref workers) = *operator;
+                    summaries.push(((activity_type, operator_address.clone(), workers.clone(), 'N'),
+                                    Default::default()));
+                }
+                output.session(&cap).give_iterator(summaries.into_iter());
+            }
+        }
+    })
+}
+
+/// Pairs each summary with its `weighted_bc` relative to the total weight of the epoch's
+/// critical path (`sp`), i.e. the share of the bottleneck the summarized edges account for.
+/// The share is 0 for epochs without a critical path.
+fn critical_share<S>(summaries: &Stream<S, (SummaryKey, Summary<f64>)>,
+                     sp: &Stream<S, PagOutput>)
+                     -> Stream<S, ((SummaryKey, Summary<f64>), f64)>
+    where S: Scope<Timestamp = Duration>
+{
+    let mut summary_map = HashMap::new();
+    let mut sp_weight_map = HashMap::new();
+    let mut vector1 = Vec::new();
+    let mut vector2 = Vec::new();
+    summaries.binary_notify(&sp.map(|edge| edge.weight()),
+                            pact::Exchange::new(|_| 0),
+                            pact::Exchange::new(|_| 0),
+                            "CriticalShare",
+                            vec![],
+                            move |input1, input2, output, notificator| {
+        input1.for_each(|time, data| {
+            data.swap(&mut vector1);
+            summary_map.entry(*time.time()).or_insert_with(Vec::new).extend(vector1.drain(..));
+            notificator.notify_at(time.retain());
+        });
+        input2.for_each(|time, data| {
+            data.swap(&mut vector2);
+            *sp_weight_map.entry(*time.time()).or_insert(0u64) += vector2.drain(..).sum::<u64>();
+            notificator.notify_at(time.retain());
+        });
+        notificator.for_each(|time, _count, _notify| {
+            let sp_weight = sp_weight_map.remove(time.time()).unwrap_or(0);
+            if let Some(summaries) = summary_map.remove(time.time()) {
+                output.session(&time).give_iterator(summaries.into_iter().map(|summary| {
+                    let share = if sp_weight > 0 {
+                        summary.1.weighted_bc / sp_weight as f64
+                    } else {
+                        0.
+                    };
+                    (summary, share)
+                }));
+            }
+        });
+    })
+}
+
+/// Prints an `ALERT` once the weighted BC of an operator has exceeded `threshold` for `streak`
+/// consecutive epochs, and an `ALERT_CLEAR` in the first epoch it no longer does. An operator
+/// without activity in an epoch does not exceed the threshold.
+fn bottleneck_alerts<S>(summaries: &Stream<S, (SummaryKey, Summary<f64>)>, threshold: f64, streak: usize)
+    where S: Scope<Timestamp = Duration>
+{
+    let streak = streak.max(1);
+    let mut per_epoch = HashMap::new();
+    // Number of consecutive epochs each operator exceeded the threshold
+    let mut streaks: HashMap<u64, usize> = HashMap::new();
+    summaries
+        .map(|((_, operator_address, _, _), summary)| {
+                 (scalar_operator_id(&operator_address), summary.weighted_bc)
+             })
+        .unary_notify::<(), _, _>(pact::Exchange::new(|_| 0),
+                                  "BottleneckAlerts",
+                                  vec![],
+                                  move |input, _output, notificator| {
+            input.for_each(|time, data| {
+                let weighted_bc = per_epoch.entry(*time.time()).or_insert_with(HashMap::new);
+                for &(operator, bc) in data.iter() {
+                    *weighted_bc.entry(operator).or_insert(0.) += bc;
+                }
+                notificator.notify_at(time.retain());
+            });
+            notificator.for_each(|time, _count, _notify| {
+                let weighted_bc = per_epoch.remove(time.time()).unwrap_or_default();
+                for (&operator, &bc) in &weighted_bc {
+                    if bc > threshold {
+                        let epochs = streaks.entry(operator).or_insert(0);
+                        *epochs += 1;
+                        if *epochs >= streak {
+                            println!("ALERT {:?},{},{},{}", time.time(), operator, epochs, bc);
+                        }
+                    }
+                }
+                let mut recovered: Vec<_> = streaks
+                    .keys()
+                    .filter(|operator| weighted_bc.get(operator).map_or(true, |&bc| bc <= threshold))
+                    .cloned()
+                    .collect();
+                recovered.sort();
+                for operator in recovered {
+                    if streaks.remove(&operator).expect("operator has a streak") >= streak {
+                        println!("ALERT_CLEAR {:?},{}", time.time(), operator);
+                    }
+                }
+            });
+        });
+}
+
+/// The probes of a dataflow, each with the name used when printing its progress.
+pub struct ProbeRegistry<T: Timestamp> {
+    probes: Vec<(&'static str, ProbeHandle<T>)>,
+}
+
+impl<T: Timestamp> ProbeRegistry<T> {
+    pub fn new() -> Self {
+        ProbeRegistry { probes: Vec::new() }
+    }
+
+    /// Adds `probe` under `name`, which must not be registered yet.
+    pub fn register(&mut self, name: &'static str, probe: ProbeHandle<T>) {
+        if self.probes.iter().any(|&(registered, _)| registered == name) {
+            panic!("probe {:?} is registered twice", name);
+        }
+        self.probes.push((name, probe));
+    }
+
+    /// The probe registered under `name`.
+    pub fn get(&self, name: &str) -> &ProbeHandle<T> {
+        match self.probes.iter().find(|&&(registered, _)| registered == name) {
+            Some(&(_, ref probe)) => probe,
+            None => panic!("no probe {:?} in the dataflow, registered probes are {:?}",
+                           name,
+                           self.names()),
+        }
+    }
+
+    /// The names of the registered probes, in registration order.
+    pub fn names(&self) -> Vec<&'static str> {
+        self.probes.iter().map(|&(name, _)| name).collect()
+    }
+}
+
+impl<T: Timestamp> IntoIterator for ProbeRegistry<T> {
+    type Item = (&'static str, ProbeHandle<T>);
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.probes.into_iter()
+    }
+}
+
+struct ProbeWrapper {
+    probe: ProbeHandle<Duration>,
+    name: String,
+    current: Duration,
+    /// Advance without printing `EPOCH` lines
+    quiet: bool,
+    skew: Option<Skew>,
+}
+
+/// How far the wall clock advanced beyond the trace time as epochs complete, see
+/// `ProbeWrapper::report_skew`.
+struct Skew {
+    window_size_ns: u64,
+    window_stride_ns: u64,
+    /// The end of the first completed window in trace time, and the wall-clock time it
+    /// completed at
+    origin: Option<(u64, u64)>,
+}
+
+impl Skew {
+    /// Prints the `SKEW` line of `epoch`, which completed at the wall-clock time `now`.
+    fn report(&mut self, epoch: Duration, now: u64) {
+        let logical = epoch.as_nanos() as u64 * self.window_stride_ns + self.window_size_ns;
+        let (logical_origin, wallclock_origin) = *self.origin.get_or_insert((logical, now));
+        let logical = logical - logical_origin;
+        let wallclock = now - wallclock_origin;
+        println!("SKEW {} {} {} {}", epoch.as_nanos(), logical, wallclock, wallclock as i64 - logical as i64);
+    }
+}
+
+impl ProbeWrapper {
+    pub fn new(name: String, probe: ProbeHandle<Duration>) -> Self {
+        ProbeWrapper {
+            probe,
+            name,
+            current: Duration::new(0,0),
+            quiet: false,
+            skew: None,
+        }
+    }
+
+    pub fn print_and_advance(&mut self) {
+        while !self.probe.less_than(&self.current) {
+            let now = time::precise_time_ns();
+            if !self.quiet {
+                println!("EPOCH {} {:?} {:?}",
+                         self.name,
+                         self.current,
+                         now);
+            }
+            if let Some(ref mut skew) = self.skew {
+                skew.report(self.current, now);
+            }
+            // probe is past
+            self.current += Duration::new(0, 1);
+        }
+    }
+
+    pub fn set_current(&mut self, new_current: Duration) {
+        self.current = new_current;
+    }
+
+    pub fn set_quiet(&mut self, quiet: bool) {
+        self.quiet = quiet;
+    }
+
+    /// Prints a `SKEW` line whenever an epoch completes: the end of its window in trace time
+    /// and the wall-clock time, both relative to the first completed epoch, and how far the
+    /// wall clock is ahead. A growing delta means the analysis falls behind a live input.
+    pub fn report_skew(&mut self, window_size_ns: u64, window_stride_ns: u64) {
+        self.skew = Some(Skew {
+            window_size_ns,
+            window_stride_ns,
+            origin: None,
+        });
+    }
+
+    /// Wraps all probes of `registry`, keeping their order.
+    pub fn from_registry(registry: ProbeRegistry<Duration>) -> Vec<Self> {
+        registry
+            .into_iter()
+            .map(|(name, probe)| ProbeWrapper::new(StdFrom::from(name), probe))
+            .collect()
+    }
+}
+
+/// The first and the last window (epoch) whose interval `[start, start + size)` contains
+/// `timestamp`, where window `w` starts at `w * window_stride_ns`. Both are the same for
+/// tumbling windows.
+fn windows_containing(timestamp: Duration, window_size_ns: u64, window_stride_ns: u64) -> (Duration, Duration) {
+    let timestamp = timestamp.as_nanos() as u64;
+    let (size, stride) = (window_size_ns, window_stride_ns);
+    let first = match (timestamp + 1).checked_sub(size) {
+        Some(end) => (end + stride - 1) / stride,
+        None => 0,
+    };
+    (Duration::from_nanos(first), Duration::from_nanos(timestamp / stride))
+}
+
+/// Feeds `input_records` into the dataflow, advancing epochs as it goes. If the records come
+/// from a bounded queue, `queue_depth` is its current depth, printed after every epoch.
+///
+/// The records must be sorted by timestamp, a record earlier than the one before it is an
+/// error. With `Config::reorder_input`, records are sorted within the reorder window first (see
+/// `input::reorder`). With `Config::max_windows`, the records from the window `max_windows`
+/// after the first one on are not fed; the windows before it are processed completely.
+///
+/// Before a new epoch is fed, the dataflow is stepped until all but the last `epochs` plus
+/// `max_inflight_windows - 1` windows are complete. Epochs are window indices, so both are
+/// counts of windows and simply add up: `epochs` is the lag the input always keeps, and every
+/// window in flight beyond the first one extends it by one window.
+fn feed_input<A, I>(mut input: InputHandle<Duration, LogRecord>,
+              input_records: I,
+              mut probes: Vec<ProbeWrapper>,
+              computation: &mut Worker<A>,
+              config: &Config,
+              queue_depth: Option<Arc<AtomicUsize>>)
+              -> Result<(), DataflowError>
+    where A: Allocate,
+          I: IntoIterator<Item = LogRecord>,
+          I::IntoIter: 'static
+{
+    let window_size_ns = config.window_size_ns;
+    let window_stride_ns = config.window_stride();
+    let epochs = Duration::from_nanos(config.epochs);
+    let mut last_probe = probes.pop().expect("last probe has to exist");
+    // The windows in flight besides the current one, in epochs (window indices)
+    let inflight = Duration::from_nanos(config.max_inflight_windows.max(1) as u64 - 1);
+
+    let input_records: Box<dyn Iterator<Item = LogRecord>> = match config.input_reorder_window() {
+        Some(window) => Box::new(input::reorder(input_records.into_iter(), window)),
+        None => Box::new(input_records.into_iter()),
+    };
+    let mut old_epoch = Duration::new(0,0);
+    let mut first_epoch = Duration::new(0,0);
+    let mut node_count = 0;
+    let mut first = true;
+    let mut previous = None;
+    for (index, rec) in input_records.enumerate() {
+        // A record earlier than its epoch would silently be processed in the wrong epoch
+        if let Some(previous) = previous {
+            if rec.timestamp < previous {
+                return Err(DataflowError::UnsortedInput {
+                    index,
+                    previous,
+                    timestamp: rec.timestamp,
+                });
+            }
+        }
+        previous = Some(rec.timestamp);
+
+        // Assign records to slices by rounding timestamps. With sliding windows, records enter at
+        // the first window containing them and `build_dataflow` copies them to the others.
+        let (epoch, _) = windows_containing(rec.timestamp, window_size_ns, window_stride_ns);
+        if first {
+            first = false;
+            for probe in &mut probes {
+                probe.set_current(epoch);
+            }
+            last_probe.set_current(epoch);
+            old_epoch = epoch;
+            first_epoch = epoch;
+            if let Some(before) = epoch.checked_sub(Duration::new(0,1)) {
+                input.advance_to(before);
+            }
+        }
+        if let Some(max_windows) = config.max_windows {
+            if epoch >= first_epoch + Duration::from_nanos(max_windows) {
+                println!("# MAX_WINDOWS stopping the input at {:?} after {} windows", epoch, max_windows);
+                break;
+            }
+        }
+        // Advance time (must increase monotonically)
+        if input.epoch() < &epoch {
+            if !last_probe.quiet {
+                println!("EPOCH input {:?} {:?}", epoch, time::precise_time_ns());
+            }
+
+            input.advance_to(epoch);
+            let timer = ::std::time::Instant::now();
+            // Allow the computation to run until all but the last `epochs` epochs and the
+            // windows in flight beyond the first one have been processed. There is nothing to
+            // wait for before the first of them.
+            if let Some(horizon) = input.time().checked_sub(epochs + inflight) {
+                while last_probe.probe.less_than(&horizon) {
+                    for probe in &mut probes {
+                        probe.print_and_advance();
+                    }
+                    last_probe.print_and_advance();
+                    computation.step();
+                }
+            }
+            println!("Time: {:?}", timer.elapsed());
+            if let Some(ref queue_depth) = queue_depth {
+                println!("QUEUE {:?} {:?}", epoch, queue_depth.load(Ordering::SeqCst));
+            }
+        }
+        if epoch > old_epoch {
+            println!("COUNT {:?} {:?} nodes {:?}", old_epoch, 0, node_count);
+            print_throughput(old_epoch, node_count, window_stride_ns);
+            node_count = 0;
+            old_epoch = epoch;
+        }
+        input.send(rec);
+        node_count += 1;
+    }
+    while last_probe
+              .probe
+              .less_than(&(input.time())) {
+        for probe in &mut probes {
+            probe.print_and_advance();
+        }
+        last_probe.print_and_advance();
+        computation.step();
+    }
+    for probe in &mut probes {
+        probe.print_and_advance();
+    }
+    last_probe.print_and_advance();
+    println!("COUNT {:?} {:?} nodes {:?}", old_epoch, 0, node_count);
+    print_throughput(old_epoch, node_count, window_stride_ns);
+    Ok(())
+}
+
+/// Prints the records per second of trace time fed into `epoch`. Records only enter the first
+/// window containing them, so with sliding windows this is the rate of the `window_stride_ns`
+/// starting at `epoch`.
+fn print_throughput(epoch: Duration, records: u64, window_stride_ns: u64) {
+    println!("THROUGHPUT {:?} {:.2}", epoch, records as f64 * 1e9 / window_stride_ns as f64);
+}
+
+/// Warns if some analysis workers receive no records of the trace.
+///
+/// Per-worker stages exchange records by the worker that logged them, modulo the number of
+/// analysis workers, so analysis workers no captured worker's ID maps to receive no data and
+/// their per-worker output stays empty. This happens with more analysis workers than captured
+/// workers, but also with gaps in the captured workers' IDs. The analysis is still correct, but
+/// it is recommended to run with at most as many workers as the captured computation had.
+fn warn_on_worker_mismatch(trace_workers: &[logformat::Worker], peers: usize) {
+    let busy = busy_workers(trace_workers, peers);
+    if busy < peers {
+        eprintln!("WARNING trace contains records from {} worker(s) {:?} but only {} of the {} \
+                   analysis workers receive records; the others will stay idle. Consider \
+                   running with `-w {}`.",
+                  trace_workers.len(),
+                  trace_workers,
+                  busy,
+                  peers,
+                  busy.max(1));
+    }
+}
+
+/// The number of the `peers` analysis workers that receive the records of `trace_workers`.
+fn busy_workers(trace_workers: &[logformat::Worker], peers: usize) -> usize {
+    trace_workers
+        .iter()
+        .map(|&worker| worker % peers as logformat::Worker)
+        .collect::<HashSet<_>>()
+        .len()
+}
+
+// Read and decode the log records of the traces of `config` and feed them into the dataflow. The
+// records are read up front and sorted, unless they arrive over TCP, through the bounded input
+// queue or are streamed, in which case they are fed while they are read.
+fn read_and_execute_trace_from_file<A: Allocate>(config: &Config,
+                                    input: InputHandle<Duration, LogRecord>,
+                                    probes: Vec<ProbeWrapper>,
+                                    computation: &mut Worker<A>)
+                                    -> Result<(), DataflowError> {
+    fn write_mapping(anonymizer: &Anonymizer) {
+        if let Err(why) = anonymizer.write_mapping() {
+            eprintln!("couldn't write the anonymization mapping: {:?}", why);
+        }
+    }
+    let log_paths = &config.trace_paths();
+    let message_delay = config.message_delay_with_mode();
+    let sample_every = config.sample_every;
+    let corrupt_record_policy = config.corrupt_record_policy;
+    let input_queue_capacity = config.input_queue_capacity;
+    let stream_reorder_window = config.stream_reorder_window();
+    let anonymizer = if config.anonymize {
+        Some(Anonymizer::new(config.anonymize_mapping_path.clone()))
+    } else {
+        None
+    };
+    let clipped = message_delay.as_ref().map(|delay| delay.clipped.clone());
+    let socket = input::socket_address(&log_paths[0]);
+    if socket.is_some() || input_queue_capacity.is_some() || stream_reorder_window.is_some() {
+        let status = ReadStatus::default();
+        let (input_records, queue_depth): (Box<dyn Iterator<Item = LogRecord>>, _) =
+            match (socket, input_queue_capacity) {
+                (Some(addr), _) => {
+                    if message_delay.is_some() {
+                        eprintln!("WARNING message clipping is not applied to records received over TCP");
+                    }
+                    (Box::new(input::read_trace_from_socket(addr,
+                                                            Duration::from_millis(config.socket_idle_timeout_ms),
+                                                            stream_reorder_window.unwrap_or_default(),
+                                                            status.clone())?),
+                     None)
+                }
+                (None, Some(capacity)) => {
+                    if message_delay.is_some() {
+                        eprintln!("WARNING message clipping is not applied when reading with a bounded \
+                                   input queue");
+                    }
+                    let input_records = input::read_trace_in_background(log_paths,
+                                                                        capacity,
+                                                                        corrupt_record_policy,
+                                                                        status.clone())?;
+                    let queue_depth = input_records.depth_handle();
+                    (Box::new(input_records), Some(queue_depth))
+                }
+                (None, None) => {
+                    let reorder_window = stream_reorder_window.expect("the input is streamed");
+                    (Box::new(input::read_sorted_trace_from_file_iter(log_paths,
+                                                                      message_delay,
+                                                                      corrupt_record_policy,
+                                                                      reorder_window,
+                                                                      status.clone())?),
+                     None)
+                }
+            };
+        let input_records: Box<dyn Iterator<Item = LogRecord>> = match sample_every {
+            Some(every) => Box::new(input::sample(input_records, every)),
+            None => input_records,
+        };
+        let anonymizer = anonymizer.map(|anonymizer| Rc::new(RefCell::new(anonymizer)));
+        let record_anonymizer = anonymizer.clone();
+        let input_records = input_records.map(move |mut rec| {
+            if let Some(ref anonymizer) = record_anonymizer {
+                anonymizer.borrow_mut().anonymize(&mut rec);
+            }
+            rec
+        });
+        let fed = feed_input(input, input_records, probes, computation, config, queue_depth);
+        if let Some(anonymizer) = anonymizer {
+            write_mapping(&anonymizer.borrow());
+        }
+        print_clipped_messages(clipped);
+        fed?;
+        return status.result().map_err(DataflowError::from);
+    }
+    let mut input_records = input::read_sorted_trace_from_file_and_cut_messages(log_paths,
+                                                                                message_delay,
+                                                                                sample_every,
+                                                                                corrupt_record_policy)?;
+    print_clipped_messages(clipped);
+    if let Some(mut anonymizer) = anonymizer {
+        for rec in &mut input_records {
+            anonymizer.anonymize(rec);
+        }
+        write_mapping(&anonymizer);
+    }
+    warn_on_worker_mismatch(&input::workers_in_trace(&input_records), computation.peers());
+    feed_input(input, input_records, probes, computation, config, None)
+}
+
+
+/// Prints a `CLIPPED_MESSAGE` line with the original timestamps of every message clipped while
+/// reading the trace.
+fn print_clipped_messages(clipped: Option<ClippedMessages>) {
+    for message in clipped.map(|clipped| clipped.take()).unwrap_or_default() {
+        println!("CLIPPED_MESSAGE {},{},{},{},{},{}",
+                 message.sender,
+                 message.receiver,
+                 message.activity_type,
+                 message.sent.as_nanos(),
+                 message.received.as_nanos(),
+                 message.clipped.as_nanos());
+    }
+}
+
+/// Runs the analysis with the timely arguments from `config`.
+///
+/// The trace is read by worker 0 and distributed from there. The number of analysis workers
+/// (`-w`) should not exceed the number of workers in the captured computation, otherwise the
+/// additional workers stay idle (a warning is printed in that case).
+///
+/// An invalid configuration or a trace that does not exist is reported before any worker is
+/// started. Errors while reading the trace are returned by worker 0 when joining the guards.
+///
+/// With a log path of the form `tcp://host:port`, worker 0 instead listens on that address and
+/// analyzes the records sent by any number of connections as they arrive (see
+/// `input::read_trace_from_socket`), advancing the epochs by their timestamps. The input ends
+/// once no records arrived for `socket_idle_timeout_ms`.
+pub fn run_dataflow(mut config: Config) -> Result<WorkerGuards<Result<(), DataflowError>>, DataflowError> {
+    if config.window_size_ns == 0 {
+        return Err(DataflowError::Config("the window size must be positive".to_string()));
+    }
+    if config.sample_every == Some(0) {
+        return Err(DataflowError::Config("sample_every must be positive".to_string()));
+    }
+    if let Some(ref addr) = config.metrics_addr {
+        #[cfg(feature = "metrics")]
+        crate::metrics::serve(addr)
+            .map_err(|why| DataflowError::Config(format!("couldn't serve metrics on {}: {}", addr, why)))?;
+        #[cfg(not(feature = "metrics"))]
+        return Err(DataflowError::Config(format!("serving metrics on {} requires the metrics feature", addr)));
+    }
+    #[cfg(not(feature = "parquet-output"))]
+    {
+        if let Some(ref path) = config.summary_parquet_path {
+            return Err(DataflowError::Config(format!("writing summaries to {} requires the parquet-output feature",
+                                                     path)));
+        }
+    }
+    if input::socket_address(&config.log_path).is_some() {
+        if config.dry_run {
+            return Err(DataflowError::Config("records received over TCP cannot be dry run".to_string()));
+        }
+        if !config.log_paths.is_empty() {
+            return Err(DataflowError::Config("traces cannot be merged with records received over TCP".to_string()));
+        }
+    } else {
+        for log_path in config.trace_paths() {
+            input::trace_segments(&log_path)?;
+        }
+    }
+    if let Some(fraction) = config.bc_sample_fraction {
+        if !(fraction > 0.0 && fraction <= 1.0) {
+            return Err(DataflowError::Config(format!("the BC sample fraction {} is not in (0, 1]", fraction)));
+        }
+    }
+    if let Some(percentile) = config.threshold_percentile {
+        if !(percentile > 0.0 && percentile <= 100.0) {
+            return Err(DataflowError::Config(format!("the threshold percentile {} is not in (0, 100]", percentile)));
+        }
+        if config.threshold != 0 {
+            eprintln!("WARNING the unknown edge threshold {} takes precedence over the threshold percentile {}",
+                      config.threshold,
+                      percentile);
+        } else if input::socket_address(&config.log_path).is_some() {
+            return Err(DataflowError::Config("a threshold percentile cannot be computed for records received over TCP"
+                                                 .to_string()));
+        } else if config.trace_paths().iter().any(|path| path == input::STDIN_PATH) {
+            return Err(DataflowError::Config("a threshold percentile cannot be computed for a trace read from stdin"
+                                                 .to_string()));
+        } else if config.stream_input || config.input_queue_capacity.is_some() {
+            // The percentile is computed from all records, which streaming the input avoids
+            return Err(DataflowError::Config("a threshold percentile cannot be computed when streaming the input \
+                                              or reading it through the input queue"
+                                                 .to_string()));
+        } else {
+            // A first pass over the trace, without applying the message delay
+            let records = input::read_trace_from_file_and_cut_messages(&config.trace_paths(),
+                                                                       None,
+                                                                       config.sample_every,
+                                                                       config.corrupt_record_policy)?;
+            let threshold = input::gap_percentile(&records, percentile).unwrap_or_default();
+            eprintln!("Unknown edge threshold {:?} (percentile {} of the gaps between records)",
+                      threshold,
+                      percentile);
+            config.threshold = threshold.as_nanos() as u64;
+        }
+    }
+    // Open the SQLite database once the configuration is known to be valid
+    if let Some(ref path) = config.sqlite_path {
+        if config.sqlite_output.is_none() && !config.dry_run {
+            let sqlite = SqliteOutput::create(path)
+                .map_err(|why| DataflowError::Config(format!("couldn't open the SQLite database {}: {}", path, why)))?;
+            config.sqlite_output = Some(Arc::new(Mutex::new(sqlite)));
+        }
+    }
+    let started = std::time::SystemTime::now();
+    timely::execute_from_args(config.timely_args.clone().into_iter(), move |computation| {
+        let config = config.clone();
+        if config.dry_run {
+            return if computation.index() == 0 { dry_run(&config) } else { Ok(()) };
+        }
+        if computation.index() == 0 {
+            println!("Input parameters: threshold {}, window size {}ns, verbosity {}, 1+{} epochs",
+                     config.threshold,
+                     config.window_size_ns,
+                     config.verbose,
+                     config.epochs);
+            if let Some(sample_every) = config.sample_every {
+                println!("# SAMPLED 1/{} records, results are approximate", sample_every);
+            }
+            if !config.delay_injection.is_empty() {
+                let mut delays: Vec<_> = config.delay_injection.iter().collect();
+                delays.sort();
+                println!("# INJECTED DELAY {:?}, weights include synthetic delay and are not measured",
+                         delays);
+            }
+            for &(prefix, columns) in HEADERS {
+                print_header(prefix, columns);
+            }
+            if config.input_queue_capacity.is_some() {
+                print_header("QUEUE", QUEUE_COLUMNS);
+            }
+            if config.remove_degenerate_edges {
+                println!("# DEGENERATE_EDGES {}", DEGENERATE_EDGES_COLUMNS);
+            }
+            if config.report_skew {
+                print_header("SKEW", SKEW_COLUMNS);
+            }
+            if config.message_delay.is_some() {
+                println!("# CLIPPED_MESSAGE {}", CLIPPED_MESSAGE_COLUMNS);
+            }
+            if let Err(why) = write_output_manifest(&config, OUTPUT_MANIFEST_PATH) {
+                eprintln!("couldn't write {}: {:?}", OUTPUT_MANIFEST_PATH, why);
+            }
+            let run_manifest_path = config.run_manifest_path();
+            if let Err(why) = write_run_manifest(&config, &run_manifest_path, started) {
+                eprintln!("couldn't write {}: {:?}", run_manifest_path, why);
+            }
+        }
+
+        let (input, probes) = computation.dataflow(|scope| build_dataflow(config.clone(), scope));
+
+        if computation.index() == 0 {
+            let mut probes = ProbeWrapper::from_registry(probes);
+            for probe in &mut probes {
+                probe.set_quiet(config.quiet_epochs);
+            }
+            if config.report_skew {
+                if let Some(last) = probes.last_mut() {
+                    last.report_skew(config.window_size_ns, config.window_stride());
+                }
+            }
+            read_and_execute_trace_from_file(&config, input, probes, computation)
+        } else {
+            Ok(())
+        }
+    }).map_err(DataflowError::TimelyInit)
+}
+
+/// Decodes the trace of `config` without analyzing it, checks that its records are sorted by
+/// timestamp and prints the number of records in every window, see `Config::dry_run`.
+fn dry_run(config: &Config) -> Result<(), DataflowError> {
+    let records = input::read_trace_from_file_and_cut_messages(&config.trace_paths(),
+                                                               None,
+                                                               None,
+                                                               config.corrupt_record_policy)?;
+    let window_stride_ns = config.window_stride();
+    let mut per_window = BTreeMap::new();
+    for rec in &records {
+        let (first, last) = windows_containing(rec.timestamp, config.window_size_ns, window_stride_ns);
+        for epoch in first.as_nanos() as u64..=last.as_nanos() as u64 {
+            *per_window.entry(epoch).or_insert(0u64) += 1;
+        }
+    }
+
+    println!("# DRY_RUN {}", DRY_RUN_COLUMNS);
+    for (&epoch, count) in &per_window {
+        println!("DRY_RUN {:?},{}", Duration::from_nanos(epoch), count);
+    }
+    println!("# DRY_RUN {} records from workers {:?} in {} windows",
+             records.len(),
+             input::workers_in_trace(&records),
+             per_window.len());
+    match input::first_unsorted_record(&records) {
+        Some(index) => {
+            println!("# DRY_RUN NOT SORTED: record {} at {:?} is earlier than record {} at {:?}",
+                     index,
+                     records[index].timestamp,
+                     index - 1,
+                     records[index - 1].timestamp)
+        }
+        None => println!("# DRY_RUN sorted by timestamp"),
+    }
+    Ok(())
+}
+
+/// Sliding windows: copies each record from the first window containing it to the others.
+fn slide_windows<S>(config: &Config, stream: &Stream<S, LogRecord>) -> Stream<S, LogRecord>
+    where S: Scope<Timestamp = Duration>
+{
+    let window_size_ns = config.window_size_ns;
+    let window_stride_ns = config.window_stride();
+    if window_stride_ns < window_size_ns && config.logical_windows.is_none() {
+        stream
+            .flat_map(move |rec: LogRecord| {
+                let (first, last) = windows_containing(rec.timestamp, window_size_ns, window_stride_ns);
+                (first.as_nanos() as u64..=last.as_nanos() as u64)
+                    .map(move |window| (Duration::from_nanos(window), rec.clone()))
+            })
+            .delay(|&(window, _), _| window)
+            .map(|(_, rec)| rec)
+    } else {
+        stream.clone()
+    }
+}
+
+/// Builds the PAG from a stream of log records with the parameters from `config` and returns the
+/// changes of its edges from each window to the next: `(edge, 1)` for an edge of the window that
+/// is not in the previous window, `(edge, -1)` for an edge of the previous window that is not in
+/// it. With sliding windows, consecutive windows share most of their edges, so the deltas are
+/// much smaller than the PAG and can drive analyses that update their results incrementally
+/// instead of starting from scratch in every window, e.g. with differential dataflow.
+///
+/// `stream` contains the records as fed to the input of `build_dataflow`, i.e. in the first
+/// window containing them. The previous window of epoch `e` is `e - 1ns`; the deltas of a
+/// window following a window without edges are the complete PAG.
+pub fn edge_deltas<S>(config: &Config, stream: &Stream<S, LogRecord>) -> Stream<S, (PagEdge, i64)>
+    where S: Scope<Timestamp = Duration>
+{
+    let next_epoch = Duration::from_nanos(1);
+    let mut pending = HashMap::new();
+    let mut previous = HashMap::new();
+    let mut vector = Vec::new();
+    program_activity_graph(config, &slide_windows(config, stream))
+        .flat_map(|output| match output {
+                      PagOutput::Edge(edge) => Some(edge),
+                      _ => None,
+                  })
+        .unary_notify(pact::Exchange::new(|edge: &PagEdge| hash_code(edge)),
+                      "EdgeDeltas",
+                      vec![],
+                      move |input, output, notificator| {
+            input.for_each(|time, data| {
+                data.swap(&mut vector);
+                pending.entry(*time.time()).or_insert_with(HashSet::new).extend(vector.drain(..));
+                notificator.notify_at(time.retain());
+            });
+            notificator.for_each(|time, _count, notificator| {
+                let epoch = *time.time();
+                let current = pending.remove(&epoch).unwrap_or_default();
+                let before = match epoch.checked_sub(next_epoch) {
+                    Some(before) => previous.remove(&before).unwrap_or_default(),
+                    None => HashSet::new(),
+                };
+                let mut session = output.session(&time);
+                for edge in current.difference(&before) {
+                    session.give((edge.clone(), 1));
+                }
+                for edge in before.difference(&current) {
+                    session.give((edge.clone(), -1));
+                }
+                // The edges are removed again in the next window unless it contains them
+                if !current.is_empty() {
+                    previous.insert(epoch, current);
+                    notificator.notify_at(time.delayed(&(epoch + next_epoch)));
+                }
+            });
+        })
+}
+
+/// Builds the PAG from a stream of log records with the parameters from `config`.
+fn program_activity_graph<S>(config: &Config, stream: &Stream<S, LogRecord>) -> Stream<S, PagOutput>
+    where S: Scope<Timestamp = Duration>
+{
+    let pag_output = stream.build_program_activity_graph(PagOptions {
+        threshold: Duration::from_nanos(config.threshold),
+        delayed_message_threshold: config.waiting_message,
+        window_size_ns: config.window_size_ns,
+        window_stride_ns: config.window_stride(),
+        logical_windows: config.logical_windows.clone(),
+        insert_waiting_edges: config.insert_waiting_edges,
+        delay_injection: config.delay_injection.clone(),
+        remove_degenerate_edges: config.remove_degenerate_edges,
+    });
+    let pag_output = match config.include_edge_types {
+        Some(ref edge_types) => {
+            let edge_types = edge_types.clone();
+            pag_output.filter(move |output| match *output {
+                                  PagOutput::Edge(ref e) => edge_types.contains(&e.edge_type),
+                                  // Keep the nodes delimiting traversals
+                                  _ => true,
+                              })
+        }
+        None => pag_output,
+    };
+    match config.focus_operators {
+        Some(ref operators) => focus_operators(&pag_output, operators.iter().cloned().collect(), config.focus_unattributed),
+        None => pag_output,
+    }
+}
+
+/// The edges of an epoch's PAG without a predecessor or successor, see `Config::validate_pag`.
+fn orphan_edges(outputs: &[PagOutput]) -> Vec<&PagEdge> {
+    let mut sources = HashSet::new();
+    let mut destinations = HashSet::new();
+    for output in outputs {
+        match *output {
+            PagOutput::StartNode(ref node) => {
+                destinations.insert(*node);
+            }
+            PagOutput::EndNode(ref node) => {
+                sources.insert(*node);
+            }
+            PagOutput::Edge(ref e) => {
+                sources.insert(e.source);
+                destinations.insert(e.destination);
+            }
+        }
+    }
+    outputs.iter()
+        .filter_map(|output| match *output {
+                        PagOutput::Edge(ref e) => Some(e),
+                        _ => None,
+                    })
+        .filter(|e| !destinations.contains(&e.source) || !sources.contains(&e.destination))
+        .collect()
+}
+
+/// Prints an `ORPHAN` line for the edges `orphan_edges` finds in every epoch, sorted.
+fn validate_pag<S>(pag_output: &Stream<S, PagOutput>)
+    where S: Scope<Timestamp = Duration>
+{
+    let mut per_epoch = HashMap::new();
+    pag_output.unary_notify::<(), _, _>(pact::Exchange::new(|_| 0),
+                                        "ValidatePag",
+                                        vec![],
+                                        move |input, _output, notificator| {
+        input.for_each(|time, data| {
+            per_epoch.entry(*time.time()).or_insert_with(Vec::new).extend(data.drain(..));
+            notificator.notify_at(time.retain());
+        });
+
+        notificator.for_each(|time, _count, _notify| {
+            let outputs = per_epoch.remove(time.time()).unwrap_or_default();
+            let mut orphans = orphan_edges(&outputs);
+            orphans.sort();
+            for edge in orphans {
+                println!("ORPHAN {:?} {:?}", time.time(), edge);
+            }
+        });
+    });
+}
+
+/// Keeps the edges of `operators` and the edges sharing a node with them, per epoch on worker 0.
+/// Edges without an operator are only kept as neighbors, and only if `unattributed` is set.
+fn focus_operators<S>(pag_output: &Stream<S, PagOutput>,
+                      operators: HashSet<u64>,
+                      unattributed: bool)
+                      -> Stream<S, PagOutput>
+    where S: Scope<Timestamp = Duration>
+{
+    let mut per_epoch = HashMap::new();
+    pag_output.unary_notify(pact::Exchange::new(|_| 0),
+                            "FocusOperators",
+                            vec![],
+                            move |input, output, notificator| {
+        input.for_each(|time, data| {
+            per_epoch.entry(*time.time()).or_insert_with(Vec::new).extend(data.drain(..));
+            notificator.notify_at(time.retain());
+        });
+
+        notificator.for_each(|time, _count, _notify| {
+            let outputs = per_epoch.remove(time.time()).unwrap_or_default();
+            let focused = |output: &PagOutput| match *output {
+                PagOutput::Edge(ref e) => e.operator_id.map_or(false, |id| operators.contains(&id)),
+                _ => false,
+            };
+            let nodes: HashSet<_> = outputs.iter()
+                .filter(|output| focused(output))
+                .flat_map(|output| output.src().into_iter().chain(output.dst()))
+                .collect();
+            let neighbor = |output: &PagOutput| {
+                output.src().map_or(false, |n| nodes.contains(&n)) || output.dst().map_or(false, |n| nodes.contains(&n))
+            };
+            let kept: Vec<_> = outputs.iter()
+                .filter(|output| match **output {
+                            PagOutput::Edge(ref e) if e.operator_id.is_none() => unattributed && neighbor(output),
+                            PagOutput::Edge(_) => focused(output) || neighbor(output),
+                            // Keep the nodes delimiting traversals
+                            _ => true,
+                        })
+                .cloned()
+                .collect();
+            output.session(&time).give_iterator(kept.into_iter());
+        });
+    })
+}
+
+/// Computes the BC of every PAG edge, i.e. the number of paths from the start to the end
+/// nodes of its epoch passing through it.
+fn betweenness_centrality<S>(config: &Config,
+                             pag_output: &Stream<S, PagOutput>)
+                             -> Stream<S, (PagOutput, f64)>
+    where S: Scope<Timestamp = Duration>
+{
+    let index = pag_output.scope().index();
+
+    let forward = pag_output.filter(|output| match *output {
+                                        PagOutput::StartNode(_) => true,
+                                        _ => false,
+                                    });
+
+    forward
+        .exchange(|_| 0)
+        .count()
+        .inspect_batch(move |ts, c| {
+                           c.first()
+                               .map(|c| {
+                                        println!("COUNT {:?} {:?} forward {:?}", ts, index, c)
+                                    });
+                       });
+    if config.verbose > 1 {
+        forward.inspect_batch(move |ts, cs| for c in cs {
+                                  println!("CONTENT {:?} {:?} forward {:?}", ts, index, c)
+                              });
+    }
+
+    let backward = pag_output.filter(|output| match *output {
+                                         PagOutput::EndNode(_) => true,
+                                         _ => false,
+                                     });
+
+    if config.verbose > 0 {
+        backward
+            .count()
+            .inspect_batch(move |ts, c| {
+                c.first()
+                    .map(|c| println!("COUNT {:?} {:?} backward {:?}", ts, index, c));
+            });
+        if config.verbose > 1 {
+            backward.inspect_batch(move |ts, cs| for c in cs {
+                                       println!("CONTENT {:?} {:?} backward {:?}",
+                                                ts,
+                                                index,
+                                                c)
+                                   });
+        }
+    }
+
+    // Waiting edges are kept in the graph, whether they are traversed depends on the capacity
+    let graph = pag_output.filter(|rec| match *rec {
+                                      PagOutput::Edge(_) => true,
+                                      _ => false,
+                                  });
+
+    if config.verbose > 0 {
+        graph
+            .count()
+            .inspect_batch(move |ts, c| {
+                c.first()
+                    .map(|c| println!("COUNT {:?} {:?} graph {:?}", ts, index, c));
+            });
+        if config.verbose > 1 {
+            let labels = config.activity_labels.clone();
+            graph.inspect_batch(move |ts, cs| for c in cs {
+                                    println!("CONTENT {:?} {:?} graph {}", ts, index, describe_output(c, labels.as_ref()))
+                                });
+        }
+    }
+
+    // Every start and end node takes part in the traversal so that all edges are explored, but
+    // only the selected ones seed a path. Unselected endpoints contribute zero paths.
+    fn endpoint_count(predicate: &Option<NodePredicate>, node: Option<PagNode>) -> f64 {
+        match (predicate, node) {
+            (Some(predicate), Some(node)) if !predicate(&node) => 0.,
+            _ => 1.,
+        }
+    }
+    let bc_sources = config.bc_sources.clone();
+    let bc_sinks = config.bc_sinks.clone();
+    let sample = config.bc_sample_fraction;
+    let forward_count = forward.map(move |e| {
+        let count = match (sample, e.dst()) {
+            (Some(fraction), Some(node)) if !sampled_start_node(&node, fraction) => 0.,
+            _ => endpoint_count(&bc_sources, e.dst()),
+        };
+        (e, count)
+    });
+    let backward_count = backward.map(move |e| {
+        let count = endpoint_count(&bc_sinks, e.src());
+        (e, count)
+    });
+
+    // Perform edge ranking by counting all distinct paths within each PAG slice
+    let spill = config.bc_spill_threshold.map(SpillConfig::new);
+    let bc = match config.bc_traverse {
+        TraverseMode::NoWaiting => {
+            graph.betweenness_centrality_spilling::<TraverseNoWaiting, f64>(&forward_count,
+                                                                            &backward_count,
+                                                                            "bc",
+                                                                            spill)
+        }
+        TraverseMode::IncludeWaiting => {
+            graph.betweenness_centrality_spilling::<TraverseIncludingWaiting, f64>(&forward_count,
+                                                                                   &backward_count,
+                                                                                   "bc",
+                                                                                   spill)
+        }
+    };
+    // The paths of the sampled start nodes stand for the paths of all of them
+    match config.bc_sample_fraction {
+        Some(fraction) => bc.map(move |(edge, bc)| (edge, bc / fraction)),
+        None => bc,
+    }
+}
+
+/// Whether the start node `node` seeds paths when only a `fraction` of the start nodes are
+/// sampled, see `Config::bc_sample_fraction`. The pick only depends on the node, so every worker
+/// agrees on it.
+fn sampled_start_node(node: &PagNode, fraction: f64) -> bool {
+    (hash_code(node) as f64) < fraction * std::u64::MAX as f64
+}
+
+/// The number of sampled paths through an edge whose estimated BC is `bc`, and the standard
+/// error of the estimate, when a `fraction` of the start nodes is sampled.
+///
+/// Every path through the edge is counted iff its start node is sampled, so the number `n` of
+/// sampled paths is about binomial with probability `fraction`, and the estimate `n / fraction`
+/// has the standard error `sqrt(n * (1 - fraction)) / fraction`. This treats the paths as
+/// independent while the paths of a start node are sampled together, so the error of edges
+/// whose paths come from few start nodes is underestimated. Exact BC (`fraction` 1) has no error.
+fn bc_confidence(bc: f64, fraction: f64) -> (f64, f64) {
+    let samples = bc * fraction;
+    (samples, (samples * (1. - fraction)).sqrt() / fraction)
+}
+
+/// An edge ranked by BC for `top_k_edges`. Elements compare greater the lower they rank, so that
+/// the lowest ranked edge is at the top of a `BinaryHeap`.
+struct RankedEdge {
+    bc: f64,
+    /// Breaks ties by the edge's source, then destination
+    endpoints: (PagNode, PagNode),
+    edge: PagOutput,
+}
+
+impl RankedEdge {
+    fn new(edge: PagOutput, bc: f64) -> Self {
+        let endpoints = match edge {
+            PagOutput::Edge(ref e) => (e.source, e.destination),
+            ref et => panic!("Unknown input: {:?}", et),
+        };
+        RankedEdge { bc, endpoints, edge }
+    }
+}
+
+impl Ord for RankedEdge {
+    fn cmp(&self, other: &Self) -> ::std::cmp::Ordering {
+        other.bc
+            .partial_cmp(&self.bc)
+            .unwrap_or(::std::cmp::Ordering::Equal)
+            .then_with(|| self.endpoints.cmp(&other.endpoints))
+    }
+}
+
+impl PartialOrd for RankedEdge {
+    fn partial_cmp(&self, other: &Self) -> Option<::std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for RankedEdge {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == ::std::cmp::Ordering::Equal
+    }
+}
+
+impl Eq for RankedEdge {}
+
+/// Keeps the `k` edges with the highest BC of each epoch, collected on worker 0. Edges with the
+/// same BC are ranked by source and destination, so the same edges are kept in every run.
+fn top_k_edges<S>(bc: &Stream<S, (PagOutput, f64)>, k: usize) -> Stream<S, (PagOutput, f64)>
+    where S: Scope<Timestamp = Duration>
+{
+    let mut top_per_epoch = HashMap::new();
+    let mut vector = Vec::new();
+    bc.unary_notify(pact::Exchange::new(|_| 0), "TopK", vec![], move |input, output, notificator| {
+        input.for_each(|time, data| {
+            let top = top_per_epoch.entry(*time.time()).or_insert_with(BinaryHeap::new);
+            data.swap(&mut vector);
+            for (edge, bc) in vector.drain(..) {
+                top.push(RankedEdge::new(edge, bc));
+                if top.len() > k {
+                    top.pop();
+                }
+            }
+            notificator.notify_at(time.retain());
+        });
+        notificator.for_each(|time, _count, _notify| {
+            if let Some(top) = top_per_epoch.remove(time.time()) {
+                output.session(&time).give_iterator(top.into_sorted_vec()
+                                                        .into_iter()
+                                                        .map(|ranked| (ranked.edge, ranked.bc)));
+            }
+        });
+    })
+}
+
+/// Computes BC over the union of the PAGs of all epochs, i.e. the whole-trace graph.
+///
+/// All PAG elements are collected on worker 0, deduplicated, and released at the end of the
+/// input as a single graph at the time of the first epoch; memory use is therefore
+/// proportional to the size of the whole trace. Epochs are stitched at window boundaries: the
+/// `EndNode` of a slice and the `StartNode` of the next one are the same activity (worker and
+/// boundary timestamp), so their edges connect in the union. Boundary nodes that have edges on
+/// both sides take part in the traversal but do not seed paths; only the start and end nodes of
+/// the whole trace do.
+fn global_bc<S>(config: &Config, pag_output: &Stream<S, PagOutput>) -> Stream<S, (PagOutput, f64)>
+    where S: Scope<Timestamp = Duration>
+{
+    let mut outputs = HashSet::new();
+    let mut vector = Vec::new();
+    let union = pag_output.unary_frontier(pact::Exchange::new(|_| 0),
+                                          "UnionEpochs",
+                                          |cap, _info| {
+        let mut cap = Some(cap);
+        move |input, output| {
+            input.for_each(|_time, data| {
+                data.swap(&mut vector);
+                outputs.extend(vector.drain(..));
+            });
+            if !input.frontier().frontier().is_empty() {
+                return;
+            }
+            if let Some(cap) = cap.take() {
+                let mut sources = HashSet::new();
+                let mut destinations = HashSet::new();
+                for output in &outputs {
+                    if let PagOutput::Edge(ref e) = *output {
+                        sources.insert(e.source);
+                        destinations.insert(e.destination);
+                    }
+                }
+                let mut session = output.session(&cap);
+                for output in outputs.drain() {
+                    let seeds_paths = match output {
+                        PagOutput::StartNode(ref n) => !destinations.contains(n),
+                        PagOutput::EndNode(ref n) => !sources.contains(n),
+                        PagOutput::Edge(_) => false,
+                    };
+                    session.give((output, seeds_paths));
+                }
+            }
+        }
+    });
+
+    let graph = union.flat_map(|(output, _)| match output {
+                                   PagOutput::Edge(_) => Some(output),
+                                   _ => None,
+                               });
+    let forward = union.flat_map(|(output, seeds_paths)| match output {
+                                     PagOutput::StartNode(_) => {
+                                         Some((output, if seeds_paths { 1. } else { 0. }))
+                                     }
+                                     _ => None,
+                                 });
+    let backward = union.flat_map(|(output, seeds_paths)| match output {
+                                      PagOutput::EndNode(_) => {
+                                          Some((output, if seeds_paths { 1. } else { 0. }))
+                                      }
+                                      _ => None,
+                                  });
+    let spill = config.bc_spill_threshold.map(SpillConfig::new);
+    match config.bc_traverse {
+        TraverseMode::NoWaiting => {
+            graph.betweenness_centrality_spilling::<TraverseNoWaiting, f64>(&forward,
+                                                                            &backward,
+                                                                            "global_bc",
+                                                                            spill)
+        }
+        TraverseMode::IncludeWaiting => {
+            graph.betweenness_centrality_spilling::<TraverseIncludingWaiting, f64>(&forward,
+                                                                                   &backward,
+                                                                                   "global_bc",
+                                                                                   spill)
+        }
     }
 }
-impl ImpreciseFrom<u64> for f64 {
-    fn from(value: u64) -> f64 {
-        value as f64
-    }
+
+/// Runs the analysis as `run_dataflow` does, but passes every `SUMMARY` tuple to `sink` instead
+/// of printing it. The sink is called on worker 0 in the order the tuples would be printed.
+pub fn run_dataflow_with_sink(mut config: Config,
+                              sink: Box<dyn FnMut(SummaryRecord) + Send>)
+                              -> Result<WorkerGuards<Result<(), DataflowError>>, DataflowError> {
+    config.summary_sink = Some(Arc::new(Mutex::new(sink)));
+    run_dataflow(config)
 }
 
-/// Force a type to be the same type as the target.
+/// Runs the analysis on a dedicated thread and returns the results of each epoch as an
+/// asynchronous `futures::Stream`, for use from async services.
 ///
-/// This is useful when Rust cannot determine the appropriate target type for a computation.
-trait SameType {
-    fn same_type(&self, typed: Self) -> Self;
+/// The dataflow itself runs synchronously as in `run_dataflow`; the stream ends once the
+/// computation is complete. Errors starting the computation are printed and end the stream.
+pub fn epoch_results(mut config: Config) -> UnboundedReceiver<EpochResult> {
+    let (sender, receiver) = async_mpsc::unbounded();
+    config.epoch_results = Some(Arc::new(Mutex::new(sender)));
+    std::thread::spawn(move || match run_dataflow(config) {
+        Ok(guards) => {
+            for result in guards.join() {
+                match result {
+                    Ok(Ok(())) => (),
+                    Ok(Err(why)) => eprintln!("analysis failed: {}", why),
+                    Err(why) => eprintln!("worker failed: {}", why),
+                }
+            }
+        }
+        Err(why) => eprintln!("couldn't run dataflow: {}", why),
+    });
+    receiver
 }
 
-impl<T> SameType for T {
-    fn same_type(&self, typed: Self) -> Self {
-        typed
+/// Picks a random start node per epoch and follows a single path from it, which is a critical
+/// path of the epoch. With `config.sp_seed`, the same start node is picked in every run. The
+/// path only follows the edges `config.bc_traverse` traverses, picking a random one at every
+/// node unless `config.max_weight_path` has it follow the heaviest. With
+/// `config.dump_seed_edges`, the picked start node of every epoch is printed as a `SEED` line.
+fn critical_path<S>(config: &Config, pag_output: &Stream<S, PagOutput>) -> Stream<S, PagOutput>
+    where S: Scope<Timestamp = Duration>
+{
+    let forward = pag_output.filter(|output| match *output {
+                                        PagOutput::StartNode(_) => true,
+                                        _ => false,
+                                    });
+    let bc_traverse = config.bc_traverse;
+    let graph = pag_output.filter(move |rec| bc_traverse.traverses(rec));
+
+    // Pick a random seed
+    let mut accums = HashMap::new();
+    let max_buffered_epochs = config.max_buffered_epochs;
+    let max_buffered_edges = config.max_buffered_edges_per_epoch;
+    let sp_seed = config.sp_seed;
+    let mut sampling_rng = match sp_seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_rng(thread_rng()).expect("thread_rng cannot fail"),
+    };
+    let seed_edge = forward.unary_notify(pact::Exchange::new(|_| 0),
+                                         "SeedEdge",
+                                         vec![],
+                                         move |input, output, notificator| {
+        input.for_each(|time, data| {
+                           {
+                               let (accum, seen) = accums
+                                   .entry(*time.time())
+                                   .or_insert_with(|| (Vec::new(), 0));
+                               let before = *seen;
+                               sample_seed_edges(accum, seen, &data, max_buffered_edges, &mut sampling_rng);
+                               warn_on_large_epoch(before, *seen, max_buffered_edges, "SeedEdge", time.time());
+                           }
+                           evict_stalled_epochs(&mut accums, max_buffered_epochs, "SeedEdge");
+                           notificator.notify_at(time.retain());
+                       });
+
+        notificator.for_each(|time, _count, _notify| {
+            if let Some((mut accum, _seen)) = accums.remove(time.time()) {
+                // The output stream will contain either zero or one element.  In the common
+                // case, we pick a single random edge per epoch and emit it, however, some
+                // epochs are empty and we cannot randonly sample.
+                let elem = match sp_seed {
+                    Some(seed) => {
+                        // Start nodes arrive in any order; sort them and derive the RNG from
+                        // the epoch so that the pick does not depend on the schedule
+                        accum.sort();
+                        let mut rng = StdRng::seed_from_u64(seed ^ time.time().as_nanos() as u64);
+                        accum[..].choose(&mut rng)
+                    }
+                    None => accum[..].choose(&mut thread_rng()),
+                };
+                if let Some(elem) = elem {
+                    output.session(&time).give(elem.clone());
+                }
+            }
+        });
+    });
+
+    if config.dump_seed_edges {
+        seed_edge.inspect_batch(|epoch, seeds| for seed in seeds {
+                                    println!("SEED {:?} {:?}", epoch, seed);
+                                });
+        report_empty_epochs(pag_output, &seed_edge, "EmptySeedEdges", |epoch| {
+            println!("SEED {:?} NONE", epoch)
+        });
     }
-}
 
-impl<T: Abomonation + std::ops::Add<Output = T> + Copy> std::ops::AddAssign for Summary<T> {
-    fn add_assign(&mut self, other: Self) {
-        *self = Self {
-            bc: self.bc + other.bc,
-            weighted_bc: self.weighted_bc + other.weighted_bc,
-            weight: self.weight + other.weight,
-            count: self.count + other.count,
-        }
+    if config.max_weight_path {
+        graph.max_weight_path(&seed_edge)
+    } else {
+        graph.single_path(&seed_edge)
     }
 }
 
-/// Key for aggregation. Local indicates a worker-local activity, with its `Worker` ID.
-/// Remote indicates a cross-worker activity, with source and destination.
-#[derive(PartialEq, Eq, Hash, Abomonation, Clone)]
-enum ActivityWorkers {
-    Local(logformat::Worker),
-    Remote(logformat::Worker, logformat::Worker),
+/// Runs `build` over `records` with the timely arguments from `config` and collects its output
+/// from all workers, in no particular order.
+fn execute_collecting<D, F>(config: Config, records: Vec<LogRecord>, build: F) -> Vec<(Duration, D)>
+    where D: ExchangeData,
+          F: for<'a> Fn(&Config, &Stream<Child<'a, Worker<Generic>, Duration>, LogRecord>)
+                        -> Stream<Child<'a, Worker<Generic>, Duration>, D> + Send + Sync + 'static
+{
+    let (send, recv) = mpsc::channel();
+    let send = Arc::new(Mutex::new(send));
+    let records = Arc::new(records);
+    timely::execute_from_args(config.timely_args.clone().into_iter(), move |computation| {
+        let config = config.clone();
+        let send = send.lock().unwrap().clone();
+        let mut input = computation.dataflow(|scope| {
+            let (input, stream) = scope.new_input();
+            build(&config, &stream).inspect_batch(move |ts, results| for result in results {
+                send.send((*ts, result.clone())).unwrap();
+            });
+            input
+        });
+        if computation.index() == 0 {
+            for rec in records.iter() {
+                // Assign records to slices by rounding timestamps, as `feed_input` does
+                let (epoch, _) = windows_containing(rec.timestamp, config.window_size_ns, config.window_stride());
+                if input.epoch() < &epoch {
+                    input.advance_to(epoch);
+                }
+                input.send(rec.clone());
+            }
+        }
+        input.close();
+        while computation.step() {}
+    }).expect("computation failed");
+
+    recv.iter().collect()
 }
 
-struct ProbeWrapper {
-    probe: ProbeHandle<Duration>,
-    name: String,
-    current: Duration,
+/// Runs PAG construction and BC over `records` with the timely arguments from `config` and
+/// returns the BC of every edge, sorted by edge.
+///
+/// The result does not depend on the number of workers, so this is also useful to check
+/// that scaling the analysis does not change its results.
+pub fn collect_bc(config: Config, records: Vec<LogRecord>) -> Vec<(PagOutput, f64)> {
+    let mut bc: Vec<_> = execute_collecting(config, records, |config, stream| {
+            betweenness_centrality(config, &program_activity_graph(config, stream))
+        })
+        .into_iter()
+        .map(|(_, bc)| bc)
+        .collect();
+    bc.sort_by(|a, b| a.0.cmp(&b.0));
+    bc
 }
 
-impl ProbeWrapper {
-    pub fn new(name: String, probe: ProbeHandle<Duration>) -> Self {
-        ProbeWrapper {
-            probe,
-            name,
-            current: Duration::new(0,0),
-        }
-    }
+/// Runs PAG construction and the single-path (critical path) analysis over `records` and
+/// returns the edges on the picked paths, sorted. Set `config.sp_seed` for reproducible
+/// results.
+pub fn collect_critical_paths(config: Config, records: Vec<LogRecord>) -> Vec<PagOutput> {
+    let mut sp: Vec<_> = execute_collecting(config, records, |config, stream| {
+            critical_path(config, &program_activity_graph(config, stream))
+        })
+        .into_iter()
+        .map(|(_, edge)| edge)
+        .collect();
+    sp.sort();
+    sp
+}
 
-    pub fn print_and_advance(&mut self) {
-        while !self.probe.less_than(&self.current) {
-            println!("EPOCH {} {:?} {:?}",
-                     self.name,
-                     self.current,
-                     time::precise_time_ns());
-            // probe is past
-            self.current += Duration::new(0, 1);
-        }
+/// Reads the trace of `config` and returns the PAG of every epoch, i.e. its edges and the
+/// start and end nodes of its traversals, sorted. This is meant for analyses outside of this
+/// crate; the dataflow runs on a single worker regardless of `config.timely_args`.
+pub fn collect_pag(config: Config) -> Result<HashMap<Duration, Vec<PagOutput>>, DataflowError> {
+    let records = input::read_sorted_trace_from_file_and_cut_messages(&config.trace_paths(),
+                                                                      config.message_delay_with_mode(),
+                                                                      config.sample_every,
+                                                                      config.corrupt_record_policy)?;
+    let config = Config {
+        timely_args: Vec::new(),
+        ..config
+    };
+    let mut pag = HashMap::new();
+    let outputs = execute_collecting(config, records, |config, stream| {
+        program_activity_graph(config, stream).exchange(|_| 0)
+    });
+    for (epoch, output) in outputs {
+        pag.entry(epoch).or_insert_with(Vec::new).push(output);
     }
-
-    pub fn set_current(&mut self, new_current: Duration) {
-        self.current = new_current;
+    for outputs in pag.values_mut() {
+        outputs.sort();
     }
+    Ok(pag)
 }
 
-fn feed_input<A: Allocate>(mut input: InputHandle<Duration, LogRecord>,
-              input_records: Vec<LogRecord>,
-              mut probes: Vec<ProbeWrapper>,
-              computation: &mut Worker<A>,
-              window_size_ns: u32,
-              epochs: Duration) {
-    let mut last_probe = probes.pop().expect("last probe has to exist");
+/// The input of a dataflow built by `build_dataflow_pair`. Records are sent at `Pair` times and
+/// enter the dataflow at their logical epoch `Pair::first`; the event time interval of an epoch
+/// covers its records and extends to the wall-clock time `Pair::second` at which the next epoch
+/// starts.
+pub struct PairInput {
+    input: InputHandle<Duration, LogRecord>,
+    windows: LogicalWindows,
+    /// The current logical epoch, the wall-clock time it started at and the range of event
+    /// times of its records
+    current: Option<(u64, Duration, Duration, Duration)>,
+}
 
-    let mut old_epoch = Duration::new(0,0);
-    let mut node_count = 0;
-    let mut first = true;
-    for rec in input_records {
-        // Assign records to slices by rounding timestamps
-        let epoch = rec.timestamp / window_size_ns;
-        if first {
-            first = false;
-            for probe in &mut probes {
-                probe.set_current(epoch);
+impl PairInput {
+    /// Sends `rec` in the logical epoch `time.first`. Epochs must not decrease.
+    pub fn send(&mut self, time: &Pair<u64, Duration>, rec: LogRecord) {
+        match self.current {
+            Some((epoch, start, min_ts, max_ts)) if epoch == time.first => {
+                self.current = Some((epoch,
+                                     start,
+                                     std::cmp::min(min_ts, rec.timestamp),
+                                     std::cmp::max(max_ts, rec.timestamp)));
             }
-            last_probe.set_current(epoch);
-            old_epoch = epoch;
-            input.advance_to(epoch - Duration::new(0,1));
-        }
-        // Advance time (must increase monotonically)
-        if input.epoch() < &epoch {
-            println!("EPOCH input {:?} {:?}", epoch, time::precise_time_ns());
-
-            input.advance_to(epoch);
-            let timer = ::std::time::Instant::now();
-            // Allow the computation to run until all data has been processed.
-            // TODO: This will crash on timestamps < 3
-            while last_probe
-                      .probe
-                      .less_than(&(*input.time() - epochs)) {
-                for probe in &mut probes {
-                    probe.print_and_advance();
-                }
-                last_probe.print_and_advance();
-                computation.step();
+            _ => {
+                self.finish_epoch(Some(time.second));
+                self.current = Some((time.first, time.second, rec.timestamp, rec.timestamp));
+                self.input.advance_to(Duration::from_nanos(time.first));
             }
-            println!("Time: {:?}", timer.elapsed());
-        }
-        if epoch > old_epoch {
-            println!("COUNT {:?} {:?} nodes {:?}", old_epoch, 0, node_count);
-            node_count = 0;
-            old_epoch = epoch;
         }
-        input.send(rec);
-        node_count += 1;
+        self.input.send(rec);
     }
-    while last_probe
-              .probe
-              .less_than(&(input.time())) {
-        for probe in &mut probes {
-            probe.print_and_advance();
-        }
-        last_probe.print_and_advance();
-        computation.step();
+
+    /// Records the bounds of the last epoch and closes the input.
+    pub fn close(mut self) {
+        self.finish_epoch(None);
+        self.input.close();
     }
-    for probe in &mut probes {
-        probe.print_and_advance();
+
+    /// The logical epoch the input has advanced to, e.g. to step the worker until its probes
+    /// catch up.
+    pub fn time(&self) -> &Duration {
+        self.input.time()
     }
-    last_probe.print_and_advance();
-    println!("COUNT {:?} {:?} nodes {:?}", old_epoch, 0, node_count);
-}
 
-// Read and decode all log records from a log file and give them as input in a single epoch.  In a
-// real computation we'd read input in the background and allow the computation to progress by
-// continually making steps.
-fn read_and_execute_trace_from_file<A: Allocate>(log_path: &str,
-                                    input: InputHandle<Duration, LogRecord>,
-                                    probes: Vec<ProbeWrapper>,
-                                    computation: &mut Worker<A>,
-                                    window_size_ns: u32,
-                                    epochs: Duration,
-                                    message_delay: Option<u64>) {
-    let input_records = input::read_sorted_trace_from_file_and_cut_messages(log_path,
-                                                                            message_delay);
-    feed_input(input,
-               input_records,
-               probes,
-               computation,
-               window_size_ns,
-               epochs);
-}
-
-
-pub fn run_dataflow(config: Config) -> Result<WorkerGuards<()>, String> {
-    timely::execute_from_args(config.timely_args.clone().into_iter(), move |computation| {
-        let config = config.clone();
-        if computation.index() == 0 {
-            println!("Input parameters: threshold {}, window size {}ns, verbosity {}, 1+{} epochs",
-                     config.threshold,
-                     config.window_size_ns,
-                     config.verbose,
-                     config.epochs);
+    /// Inserts the bounds of the current epoch, which ends when the next epoch starts at `next`.
+    fn finish_epoch(&mut self, next: Option<Duration>) {
+        if let Some((epoch, start, min_ts, max_ts)) = self.current.take() {
+            let start = std::cmp::min(start, min_ts);
+            let end = std::cmp::max(next.unwrap_or(start), max_ts + Duration::from_nanos(1));
+            self.windows.insert(Duration::from_nanos(epoch), start, end);
         }
+    }
+}
 
-        let (input, probes) = computation.dataflow(|scope| build_dataflow(config.clone(), scope));
-
-        if computation.index() == 0 {
-            let names = vec!["pag", "bc", "sp", "summary", "sp_summary"];
-            let mut probe_wrappers = Vec::with_capacity(probes.len());
-            for (probe, name) in probes.into_iter().zip(names.into_iter()) {
-                probe_wrappers.push(ProbeWrapper::new(StdFrom::from(name), probe));
-            }
-            read_and_execute_trace_from_file(&config.log_path,
-                                             input,
-                                             probe_wrappers,
-                                             computation,
-                                             config.window_size_ns,
-                                             config.epochs,
-                                             config.message_delay);
-        }
-    })
+/// Builds the dataflow of `build_dataflow` for a computation whose records carry `Pair<u64,
+/// Duration>` timestamps, e.g. an iterative computation traced per logical epoch.
+///
+/// The epochs of the dataflow are the logical epochs `Pair::first` instead of windows of
+/// `window_size_ns`; each is bounded by the wall-clock times from `Pair::second` and the event
+/// times of its records, which `EPOCH_BOUNDS` prints. Sliding windows (`window_stride_ns`) and
+/// the edges crossing window boundaries still assume fixed windows, so keep the default stride and
+/// expect the crossing edge counts to be approximate. The bounds are shared through memory,
+/// hence all workers have to run in a single process.
+pub fn build_dataflow_pair<S>(mut config: Config, scope: &mut S) -> (PairInput, ProbeRegistry<Duration>)
+    where S: Scope<Timestamp = Duration> + Input
+{
+    let windows = LogicalWindows::default();
+    config.logical_windows = Some(windows.clone());
+    let (input, probes) = build_dataflow(config, scope);
+    (PairInput { input, windows, current: None }, probes)
 }
 
 pub fn build_dataflow<S>
     (config: Config,
      scope: &mut S)
-     -> (InputHandle<S::Timestamp, LogRecord>, Vec<ProbeHandle<S::Timestamp>>)
+     -> (InputHandle<S::Timestamp, LogRecord>, ProbeRegistry<S::Timestamp>)
     where S: Scope<Timestamp = Duration> + Input
 {
     let (input, stream) = scope.new_input();
-    if false {
-        stream.dump_histogram();
+    let mut probes = ProbeRegistry::new();
+    let window_size_ns = config.window_size_ns;
+    let window_stride_ns = config.window_stride();
+    let stream = slide_windows(&config, &stream);
+    // Map each epoch back to the absolute time range of the trace it covers. Logical windows
+    // are only bounded once they are complete.
+    if config.print_epoch_bounds {
+        if scope.index() == 0 {
+            print_header("EPOCH_BOUNDS", EPOCH_BOUNDS_COLUMNS);
+        }
+        let logical_windows = config.logical_windows.clone();
+        write_per_epoch(&stream.map(|_| ()), "EpochBounds", move |epoch, _| {
+            let (start, end) = window_bounds(*epoch, window_size_ns, window_stride_ns, &logical_windows);
+            println!("EPOCH_BOUNDS {} {} {}", epoch.as_nanos(), start.as_nanos(), end.as_nanos());
+        });
+    }
+
+    let pag_output = program_activity_graph(&config, &stream);
+
+    if config.dump_histogram {
+        if scope.index() == 0 {
+            println!("# HISTOGRAM {}", HISTOGRAM_COLUMNS);
+        }
+        pag_output.dump_histogram(config.histogram_buckets
+                                      .clone()
+                                      .unwrap_or_else(output::default_histogram_buckets));
     }
-    let pag_output = stream.build_program_activity_graph(Duration::from_nanos(config.threshold),
-                                                         config.waiting_message,
-                                                         config.window_size_ns as u32,
-                                                         config.insert_waiting_edges);
 
     let probe_pag = pag_output.filter(|_| false).exchange(|_| 0).probe();
+    probes.register("pag", probe_pag);
     // Dump all program activities to the console for debugging
     if config.dump_pag {
         pag_output
@@ -295,117 +3412,382 @@ pub fn build_dataflow<S>
                            });
     }
 
+    if config.validate_pag {
+        validate_pag(&pag_output);
+    }
+
     // Crete a DOT file of the graph for each epoch?
     if config.write_pag_dot {
-        pag_output.dump_graph("dot/pag");
+        pag_output.dump_graph("dot/pag", config.dump_options());
+    }
+    if config.write_pag_dot_combined {
+        pag_output.dump_graph_combined("dot/combined.dot", config.activity_labels.clone());
+    }
+    if config.write_pag_chrome {
+        pag_output.dump_chrome_trace("chrome/pag.json");
+    }
+    if config.write_pag_graphml {
+        pag_output.dump_graphml("graphml/pag", config.dump_options());
     }
 
     if config.write_pag_msgpack {
-        pag_output.dump_msgpack("msgpack_pag/output");
+        pag_output.dump_msgpack("msgpack_pag/output", config.dump_options());
+    }
+    if config.write_pag_msgpack_sharded {
+        pag_output.dump_msgpack_sharded("msgpack_pag/output", config.dump_options());
+    }
+
+    if config.write_pag_cbor {
+        pag_output.dump_cbor("cbor_pag/", config.dump_options());
+    }
+
+    // Create a single HTML file to step through the PAGs of all epochs?
+    if config.write_pag_html {
+        pag_output.dump_html("html/pag", config.activity_labels.clone());
+    }
+
+    // Export processing edges as a Chrome trace to convert for the Tracy profiler?
+    if config.write_pag_tracy {
+        pag_output.dump_tracy("tracy/pag");
     }
 
     let index = scope.index();
+
+    // The database is only used on worker 0, which all SQLite output is exchanged to
+    let sqlite = match config.sqlite_path {
+        Some(ref path) if index == 0 => match config.sqlite_output {
+            Some(ref sqlite) => Some(sqlite.clone()),
+            None => match SqliteOutput::create(path) {
+                Ok(sqlite) => Some(Arc::new(Mutex::new(sqlite))),
+                Err(why) => {
+                    eprintln!("ERROR couldn't open {:?}, not writing SQLite output: {:?}", path, why);
+                    None
+                }
+            },
+        },
+        _ => None,
+    };
+    let sqlite_retries = config.dump_write_retries;
+    if config.sqlite_path.is_some() {
+        let sqlite = sqlite.clone();
+        write_per_epoch(&pag_output, "SqliteEdges", move |epoch, outputs| {
+            let edges: Vec<_> = outputs
+                .into_iter()
+                .filter_map(|output| match output {
+                                PagOutput::Edge(edge) => Some(edge),
+                                _ => None,
+                            })
+                .collect();
+            if let Some(ref sqlite) = sqlite {
+                sqlite.lock()
+                    .unwrap()
+                    .insert_with_retries("edges", epoch, sqlite_retries, |sqlite| {
+                        sqlite.insert_edges(epoch, &edges)
+                    });
+            }
+        });
+    }
+    #[cfg(feature = "metrics")]
+    let metrics = config.metrics_addr.is_some();
     pag_output
         .exchange(|_| 0)
         .count()
         .inspect_batch(move |ts, c| {
-            c.first()
-                .map(|c| println!("COUNT {:?} {:?} pag_output {:?}", ts, index, c));
+            if let Some(c) = c.first() {
+                println!("COUNT {:?} {:?} pag_output {:?}", ts, index, c);
+                #[cfg(feature = "metrics")]
+                {
+                    if metrics {
+                        crate::metrics::set_pag_edges(ts, *c);
+                    }
+                }
+            }
         });
     if config.verbose > 1 {
+        let labels = config.activity_labels.clone();
         pag_output.inspect_batch(move |ts, cs| for c in cs {
-                                     println!("CONTENT {:?} {:?} pag_output {:?}",
+                                     println!("CONTENT {:?} {:?} pag_output {}",
                                               ts,
                                               index,
-                                              c)
+                                              describe_output(c, labels.as_ref()))
                                  });
     }
 
-    if config.disable_bc {
-        return (input, vec![probe_pag]);
-    }
-
-    let forward = pag_output.filter(|output| match *output {
-                                        PagOutput::StartNode(_) => true,
-                                        _ => false,
-                                    });
-
-    forward
-        .exchange(|_| 0)
-        .count()
-        .inspect_batch(move |ts, c| {
-                           c.first()
-                               .map(|c| {
-                                        println!("COUNT {:?} {:?} forward {:?}", ts, index, c)
-                                    });
-                       });
-    if config.verbose > 1 {
-        forward.inspect_batch(move |ts, cs| for c in cs {
-                                  println!("CONTENT {:?} {:?} forward {:?}", ts, index, c)
-                              });
+    // Characterize graph sizes only: count edges per epoch and print their distribution
+    if config.edge_distribution_only {
+        let mut edge_counts = Vec::new();
+        let mut printed = false;
+        let probe_edges = pag_output
+            .filter(|output| match *output {
+                        PagOutput::Edge(_) => true,
+                        _ => false,
+                    })
+            .exchange(|_| 0)
+            .count()
+            .unary_frontier::<(), _, _, _>(pact::Pipeline, "EdgeDistribution", move |_cap, _info| {
+                move |input, _output| {
+                    input.for_each(|time, data| for &count in data.iter() {
+                        println!("EDGES {:?} {}", time.time(), count);
+                        edge_counts.push(count);
+                    });
+                    if index == 0 && !printed && input.frontier().frontier().is_empty() {
+                        printed = true;
+                        print_edge_distribution(::std::mem::replace(&mut edge_counts, Vec::new()));
+                    }
+                }
+            })
+            .probe();
+        probes.register("edges", probe_edges);
+        return (input, probes);
     }
 
-    let backward = pag_output.filter(|output| match *output {
+    // The heaviest path through each epoch's PAG from a start node to an end node
+    if config.longest_path {
+        if index == 0 {
+            println!("# LONGEST_PATH {}", LONGEST_PATH_COLUMNS);
+        }
+        let starts = pag_output.filter(|output| match *output {
+                                           PagOutput::StartNode(_) => true,
+                                           _ => false,
+                                       });
+        let ends = pag_output.filter(|output| match *output {
                                          PagOutput::EndNode(_) => true,
                                          _ => false,
                                      });
-
-    if config.verbose > 0 {
-        backward
-            .count()
-            .inspect_batch(move |ts, c| {
-                c.first()
-                    .map(|c| println!("COUNT {:?} {:?} backward {:?}", ts, index, c));
+        let bc_traverse = config.bc_traverse;
+        pag_output
+            .filter(move |output| bc_traverse.traverses(output))
+            .critical_path(&starts, &ends)
+            .inspect_batch(|epoch, paths| for path in paths {
+                for (position, output) in path.iter().enumerate() {
+                    if let PagOutput::Edge(ref edge) = *output {
+                        println!("LONGEST_PATH {:?},{},{},{:?},{},{:?},{},{},{}",
+                                 epoch,
+                                 position,
+                                 edge.source.worker_id,
+                                 edge.source.timestamp,
+                                 edge.destination.worker_id,
+                                 edge.destination.timestamp,
+                                 edge.edge_type,
+                                 scalar_operator_id(&edge.operator_address),
+                                 edge.weight());
+                    }
+                }
             });
-        if config.verbose > 1 {
-            backward.inspect_batch(move |ts, cs| for c in cs {
-                                       println!("CONTENT {:?} {:?} backward {:?}",
-                                                ts,
-                                                index,
-                                                c)
-                                   });
+    }
+
+    // Compute per-edge slack over each epoch's DAG, which spans all workers
+    if config.compute_slack {
+        if index == 0 {
+            println!("# SLACK {}", SLACK_COLUMNS);
+        }
+        let edges = pag_output.flat_map(|output| match output {
+                                            PagOutput::Edge(edge) => Some(edge),
+                                            _ => None,
+                                        });
+        write_per_epoch(&edges, "Slack", |epoch, edges| {
+            for (edge, slack) in slack::compute_slack(&edges) {
+                println!("SLACK {:?},{},{:?},{},{:?},{},{},{},{},{}",
+                         epoch,
+                         edge.source.worker_id,
+                         edge.source.timestamp,
+                         edge.destination.worker_id,
+                         edge.destination.timestamp,
+                         edge.edge_type,
+                         scalar_operator_id(&edge.operator_address),
+                         slack.earliest_start,
+                         slack.latest_start,
+                         slack.slack());
+            }
+        });
+    }
+
+    // How the time of each operator splits into doing work, waiting and exchanging data
+    if config.state_profile {
+        if index == 0 {
+            println!("# STATE_PROFILE {}", STATE_PROFILE_COLUMNS);
         }
+        let state_weights = pag_output
+            .flat_map(|output| match output {
+                          PagOutput::Edge(edge) => {
+                              Some(((scalar_operator_id(&edge.operator_address),
+                                     OperatorState::of(edge.edge_type) as u8),
+                                    edge.weight()))
+                          }
+                          _ => None,
+                      })
+            .aggregate::<_, u64, _, _, _>(|_key, val, agg| *agg += val,
+                                         |key, agg| (key, agg),
+                                         |key| hash_code(key));
+        write_per_epoch(&state_weights, "StateProfile", |epoch, state_weights| {
+            let mut per_operator = HashMap::new();
+            for ((operator, state), weight) in state_weights {
+                per_operator.entry(operator).or_insert([0u64; 3])[state as usize] += weight;
+            }
+            let mut per_operator: Vec<_> = per_operator.into_iter().collect();
+            per_operator.sort();
+            for (operator, weights) in per_operator {
+                let total = weights.iter().sum::<u64>().max(1) as f64;
+                println!("STATE_PROFILE {:?},{},{},{},{},{:.4},{:.4},{:.4}",
+                         epoch,
+                         operator,
+                         weights[0],
+                         weights[1],
+                         weights[2],
+                         weights[0] as f64 / total,
+                         weights[1] as f64 / total,
+                         weights[2] as f64 / total);
+            }
+        });
+    }
+
+    // The distribution of edge weights per operator, which the summaries only give the sum of
+    if config.weight_stats {
+        if index == 0 {
+            println!("# WEIGHT_STATS {}", WEIGHT_STATS_COLUMNS);
+        }
+        let weight_stats = pag_output
+            .flat_map(|output| match output {
+                          PagOutput::Edge(edge) => Some((scalar_operator_id(&edge.operator_address), edge.weight())),
+                          _ => None,
+                      })
+            .aggregate::<_, WeightStats, _, _, _>(|_operator, weight, stats| stats.add(weight),
+                                                 |operator, stats| {
+                                                     (operator, stats.count, stats.min, stats.max, stats.mean(), stats.stddev())
+                                                 },
+                                                 |operator| hash_code(operator));
+        write_per_epoch(&weight_stats, "WeightStats", |epoch, mut weight_stats| {
+            weight_stats.sort_by_key(|stats| stats.0);
+            for (operator, count, min, max, mean, stddev) in weight_stats {
+                println!("WEIGHT_STATS {:?},{},{},{},{},{:.2},{:.2}", epoch, operator, count, min, max, mean, stddev);
+            }
+        });
     }
 
-    // We do not want to traverse Waiting edges so remove them from the PAG
-    let graph = pag_output.filter(|rec| match *rec {
-                                      PagOutput::Edge(_) => true,
-                                      _ => false,
-                                  });
+    if config.mode == AnalysisMode::PagOnly {
+        return (input, probes);
+    }
 
-    if config.verbose > 0 {
-        graph
-            .count()
-            .inspect_batch(move |ts, c| {
-                c.first()
-                    .map(|c| println!("COUNT {:?} {:?} graph {:?}", ts, index, c));
-            });
-        if config.verbose > 1 {
-            graph.inspect_batch(move |ts, cs| for c in cs {
-                                    println!("CONTENT {:?} {:?} graph {:?}", ts, index, c)
-                                });
+    let bc = betweenness_centrality(&config, &pag_output);
+    let bc = match config.bc_top_k {
+        Some(k) => top_k_edges(&bc, k),
+        None => bc,
+    };
+
+    // How precise each BC value is. Record sampling (`sample_every`) changes the PAG itself, so
+    // its error is not part of the standard error and its values are only marked approximate.
+    if config.bc_confidence {
+        if index == 0 {
+            println!("# BC_CONFIDENCE {}", BC_CONFIDENCE_COLUMNS);
         }
+        let fraction = config.bc_sample_fraction.unwrap_or(1.);
+        let approximate = config.bc_sample_fraction.is_some() || config.sample_every.is_some();
+        write_per_epoch(&bc, "BcConfidence", move |epoch, mut bc| {
+            bc.sort_by(|a, b| a.0.cmp(&b.0));
+            for (output, bc) in bc {
+                if let PagOutput::Edge(ref edge) = output {
+                    let (samples, stderr) = bc_confidence(bc, fraction);
+                    println!("BC_CONFIDENCE {:?},{},{:?},{},{:?},{},{},{},{:.0},{:.2},{:.2},{:.2},{}",
+                             epoch,
+                             edge.source.worker_id,
+                             edge.source.timestamp,
+                             edge.destination.worker_id,
+                             edge.destination.timestamp,
+                             edge.edge_type as u8,
+                             scalar_operator_id(&edge.operator_address),
+                             bc,
+                             samples,
+                             stderr,
+                             (bc - 1.96 * stderr).max(0.),
+                             bc + 1.96 * stderr,
+                             approximate);
+                }
+            }
+        });
     }
 
-    let forward_count = forward.map(|e| (e, From::from(1u8)));
-    let backward_count = backward.map(|e| (e, From::from(1u8)));
+    // Are the most central edges also the heaviest? Low correlation points at light edges
+    // many paths depend on
+    if config.compare_metrics {
+        if index == 0 {
+            println!("# CORR {}", CORR_COLUMNS);
+        }
+        let bc_weight = bc.map(|(edge, bc)| (bc, edge.weight() as f64));
+        write_per_epoch(&bc_weight, "CompareMetrics", |epoch, pairs| {
+            println!("CORR {:?},{},{},{}", epoch, pairs.len(), pearson(&pairs), spearman(&pairs));
+        });
+    }
 
-    // Perform edge ranking by counting all distinct paths within each PAG slice
-    let bc =
-        graph.betweenness_centrality::<TraverseNoWaiting, f64>(&forward_count,
-                                                               &backward_count,
-                                                               "bc");
+    if config.global_bc {
+        if index == 0 {
+            println!("# GLOBAL_SUMMARY {}", GLOBAL_SUMMARY_COLUMNS);
+        }
+        let operator_cost_multipliers = config.operator_cost_multipliers.clone();
+        let edge_identity = config.edge_identity.clone();
+        global_bc(&config, &pag_output)
+            .map(move |(edge, bc)| {
+                     let key = match edge {
+                         PagOutput::Edge(ref e) => match edge_identity {
+                             Some(ref edge_identity) => edge_identity(e),
+                             None => EdgeIdentity::of(e),
+                         },
+                         ref et => panic!("Unknown input: {:?}", et),
+                     };
+                     (key, edge_summary(&edge, bc, &operator_cost_multipliers))
+                 })
+            .aggregate::<_, Summary<_>, _, _, _>(|_key, val, agg| *agg += val,
+                                                 |key, agg| (key, agg),
+                                                 |key| hash_code(key))
+            .exchange(|_| 0)
+            .inspect_batch(|_ts, output| for &(ref identity, ref summary) in output {
+                               println!("GLOBAL_SUMMARY {},{},{},{},{},{},{},{},{}",
+                                        identity.edge_type,
+                                        identity.operator_id.unwrap_or(255),
+                                        identity.source_worker,
+                                        identity.destination_worker,
+                                        scope_name(&identity.scope),
+                                        summary.bc,
+                                        summary.weighted_bc,
+                                        summary.count,
+                                        summary.weight);
+                           });
+    }
+
+    if config.sqlite_path.is_some() {
+        let sqlite = sqlite.clone();
+        write_per_epoch(&bc, "SqliteBc", move |epoch, bc| {
+            if let Some(ref sqlite) = sqlite {
+                sqlite.lock()
+                    .unwrap()
+                    .insert_with_retries("bc", epoch, sqlite_retries, |sqlite| sqlite.insert_bc(epoch, &bc));
+            }
+        });
+    }
 
     // Crete a DOT file of the graph for each epoch?
     if config.write_bc_dot {
-        bc.map(|(e, _)| e).dump_graph("dot/bc");
+        bc.map(|(e, _)| e).dump_graph("dot/bc", config.dump_options());
+    }
+    if config.write_bc_msgpack {
+        bc.dump_msgpack_bc("msgpack_bc/output", config.dump_options());
+    }
+
+    // Create Cypher statements to import the graph for each epoch into Neo4j?
+    if config.write_pag_cypher {
+        let mut files = DumpFiles::new(config.dump_options());
+        write_per_epoch(&bc, "Cypher", move |epoch, bc| {
+            let path = format!("cypher/pag_{:?}.cypher", epoch);
+            let mut contents = Vec::new();
+            output::write_cypher(&mut contents, epoch, &bc).unwrap();
+            files.write(epoch, path.as_ref(), &contents);
+        });
     }
 
     let probe_bc_stream = bc.filter(|_| false).exchange(|_| 0);
-    let probe_bc = probe_bc_stream.probe();
+    probes.register("bc", probe_bc_stream.probe());
 
-    if config.disable_summary {
-        return (input, vec![probe_pag, probe_bc]);
+    if config.mode == AnalysisMode::PagAndBc {
+        return (input, probes);
     }
 
     bc.exchange(|_| 0)
@@ -422,39 +3804,20 @@ pub fn build_dataflow<S>
                          });
     }
 
-    // Pick a random seed
-    let mut accums = HashMap::new();
-    let seed_edge = forward.unary_notify(pact::Exchange::new(|_| 0),
-                                         "SeedEdge",
-                                         vec![],
-                                         move |input, output, notificator| {
-        input.for_each(|time, data| {
-                           accums
-                               .entry(*time.time())
-                               .or_insert_with(Vec::new)
-                               .extend_from_slice(&data);
-                           notificator.notify_at(time.retain());
-                       });
-
-        notificator.for_each(|time, _count, _notify| {
-            if let Some(accum) = accums.remove(time.time()) {
-                // The output stream will contain either zero or one element.  In the common
-                // case, we pick a single random edge per epoch and emit it, however, some
-                // epochs are empty and we cannot randonly sample.
-                let mut rng = thread_rng();
-                if let Some(elem) = accum[..].choose(&mut rng) {
-                    output.session(&time).give(elem.clone());
-                }
-            }
-        });
-    });
+    // The start nodes of the PAG, as traversed by `betweenness_centrality`
+    let forward = pag_output.filter(|output| match *output {
+                                        PagOutput::StartNode(_) => true,
+                                        _ => false,
+                                    });
 
     // Single-path bc
-    let sp = graph.single_path(&seed_edge); //.inspect_ts(move |ts, c| println!("{:?} {:?} Edge: {:?}", ts, index, c));
+    let sp = critical_path(&config, &pag_output); //.inspect_ts(move |ts, c| println!("{:?} {:?} Edge: {:?}", ts, index, c));
 
     let probe_sp_stream = sp.filter(|_| false).exchange(|_| 0);
-    let probe_sp = probe_sp_stream.probe();
+    probes.register("sp", probe_sp_stream.probe());
 
+    let max_buffered_epochs = config.max_buffered_epochs;
+    let max_buffered_edges = config.max_buffered_edges_per_epoch;
     let mut bc_map = HashMap::new();
     let mut forward_map = HashMap::new();
     let mut vector1 = Vec::new();
@@ -467,20 +3830,32 @@ pub fn build_dataflow<S>
                                  move |input1, input2, output, notificator| {
         input1.for_each(|time, data| {
             let bc_entry = bc_map.entry(*time.time()).or_insert_with(HashMap::new);
+            let before = bc_entry.len();
             data.swap(&mut vector1);
             for (d, count) in vector1.drain(..) {
                 *bc_entry
                      .entry(d.src().expect("edge w/o src"))
                      .or_insert(0u64) += count as u64;
             }
+            warn_on_large_epoch(before, bc_entry.len(), max_buffered_edges, "count (BC input)", time.time());
+            evict_stalled_epochs(&mut bc_map, max_buffered_epochs, "count (BC input)");
             notificator.notify_at(time.retain());
         });
         input2.for_each(|time, data| {
             data.swap(&mut vector2);
-                            forward_map
-                                .entry(*time.time())
-                                .or_insert_with(Vec::new)
-                                .extend(vector2.drain(..));
+                            {
+                                let forward_entry = forward_map.entry(*time.time()).or_insert_with(Vec::new);
+                                let before = forward_entry.len();
+                                forward_entry.extend(vector2.drain(..));
+                                warn_on_large_epoch(before,
+                                                    forward_entry.len(),
+                                                    max_buffered_edges,
+                                                    "count (forward input)",
+                                                    time.time());
+                            }
+                            evict_stalled_epochs(&mut forward_map,
+                                                 max_buffered_epochs,
+                                                 "count (forward input)");
                             notificator.notify_at(time.retain());
                         });
         notificator.for_each(|time, _count, _notificator| {
@@ -502,15 +3877,21 @@ pub fn build_dataflow<S>
     });
 
     count.inspect_batch(move |ts, c| {
-                            c.first()
-                                .map(|c| {
-                                         println!("COUNT {:?} {:?} paths {:?}", ts, index, c)
-                                     });
+                            if let Some(c) = c.first() {
+                                println!("COUNT {:?} {:?} paths {:?}", ts, index, c);
+                                #[cfg(feature = "metrics")]
+                                {
+                                    if metrics {
+                                        crate::metrics::set_bc_total(*c);
+                                    }
+                                }
+                            }
                         });
 
-    // group aggregates by (activity_type, operator_id, worker_id)
+    // group aggregates by (activity_type, operator_address, worker_id)
     let probe_summary = {
         let mut vector = Vec::new();
+        let operator_cost_multipliers = config.operator_cost_multipliers.clone();
         let edge_weight_stream_triples = bc.unary(pact::Pipeline,
                                                   "MapToSummary",
                                                          |_cap, _info| { move |input, output| {
@@ -520,12 +3901,11 @@ pub fn build_dataflow<S>
                     .session(&time)
                     .give_iterator(vector.drain(..)
                                        .map(|(edge, bc)| {
-                        let w = edge.weight();
-                        let window_size_ns = config.window_size_ns;
-                        let window_start_time = time.time();
-                        let crosses_start = edge.source_timestamp() == *window_start_time * window_size_ns; // @TODO bounds - 1);
+                        // Bounds of the window being summarized, which may overlap others
+                        let window_start = Duration::from_nanos(time.time().as_nanos() as u64 * window_stride_ns);
+                        let crosses_start = edge.source_timestamp() == window_start; // @TODO bounds - 1);
                         let crosses_end = edge.destination_timestamp() ==
-                            *window_start_time * window_size_ns + Duration::new(0, window_size_ns);
+                            window_start + Duration::from_nanos(window_size_ns);
                         let crosses = match (crosses_start, crosses_end) {
                             (true, true) => 'B',
                             (true, false) => 'S',
@@ -534,8 +3914,8 @@ pub fn build_dataflow<S>
                         };
                         let edge_type = match edge {
                             PagOutput::Edge(ref e) => {
-                                (e.edge_type as u8,
-                                 e.operator_id.unwrap_or(std::u16::MAX as u64) as u8,
+                                (e.edge_type,
+                                 e.operator_address.clone(),
                                  if e.edge_type.is_worker_local() {
                                      ActivityWorkers::Local(e.source.worker_id)
                                  } else {
@@ -546,13 +3926,7 @@ pub fn build_dataflow<S>
                             }
                             et => panic!("Unknown input: {:?}", et),
                         };
-                        let summary = Summary {
-                            weight: w,
-                            bc: bc,
-                            weighted_bc: bc * bc.same_type(ImpreciseFrom::from(w)),
-                            count: 1,
-                        };
-                        (edge_type, summary)
+                        (edge_type, edge_summary(&edge, bc, &operator_cost_multipliers))
                     }));
                 });
             }
@@ -562,38 +3936,244 @@ pub fn build_dataflow<S>
                                                  |key, agg| (key, agg),
                                                  |key| hash_code(key));
 
-        if index == 0 {
-            println!("# SUMMARY epoch,activity,operator,src,dst,crosses,bc,weighted_bc,count,weight",);
+        if index == 0 && config.summary_sink.is_none() {
+            println!("# SUMMARY {}", SUMMARY_COLUMNS);
         }
-        summary_triples
-            .exchange(|_| 0)
-            .inspect_batch(move |ts, output| for &((activity_type, operator_id, ref workers, crosses),
-                                                   ref summary) in output {
-                               let worker_csv = match *workers {
-                                   ActivityWorkers::Local(w_id) => format!("{},{}", w_id, w_id),
-                                   ActivityWorkers::Remote(src, dst) => format!("{},{}", src, dst),
-                               };
-                               let data = format!("{:?},{},{},{},{},{},{},{},{}",
-                                                  ts,
-                                                  activity_type,
-                                                  operator_id,
-                                                  worker_csv,
-                                                  crosses,
-                                                  summary.bc,
-                                                  summary.weighted_bc,
-                                                  summary.count,
-                                                  summary.weight);
-
-                               println!("SUMMARY {}", data.to_string());
+
+        if config.sqlite_path.is_some() {
+            let sqlite = sqlite.clone();
+            write_per_epoch(&summary_triples, "SqliteSummary", move |epoch, summaries| {
+                let rows: Vec<_> = summaries.into_iter().map(summary_row).collect();
+                if let Some(ref sqlite) = sqlite {
+                    sqlite.lock()
+                        .unwrap()
+                        .insert_with_retries("summary", epoch, sqlite_retries, |sqlite| {
+                            sqlite.insert_summary(epoch, &rows)
+                        });
+                }
+            });
+        }
+
+        // The file is only created on worker 0, which `write_per_epoch` collects the summaries on
+        #[cfg(feature = "parquet-output")]
+        {
+            if let Some(ref path) = config.summary_parquet_path {
+                let mut parquet = if index == 0 {
+                    Some(crate::parquet_summary::ParquetSummary::create(path)
+                             .unwrap_or_else(|why| panic!("couldn't create {:?}: {:?}", path, why)))
+                } else {
+                    None
+                };
+                write_per_epoch(&summary_triples, "ParquetSummary", move |epoch, summaries| {
+                    let rows: Vec<_> = summaries.into_iter().map(summary_row).collect();
+                    parquet.as_mut()
+                        .expect("Parquet output is written on worker 0")
+                        .write_epoch(epoch, &rows)
+                        .unwrap();
+                });
+            }
+        }
+
+        if let Some(ref sender) = config.epoch_results {
+            let sender = sender.clone();
+            write_per_epoch(&summary_triples, "EpochResults", move |epoch, summaries| {
+                let result = EpochResult {
+                    epoch: *epoch,
+                    summaries: summaries.into_iter().map(summary_row).collect(),
+                };
+                // The receiver may have stopped listening, which is fine
+                let _ = sender.lock().unwrap().unbounded_send(result);
+            });
+        }
+
+        // Accumulate the total weight per (epoch, operator) on worker 0 for heatmap plotting
+        if let Some(ref path) = config.heat_csv_path {
+            let mut heat_file = if index == 0 {
+                let mut file = File::create(path)
+                    .unwrap_or_else(|why| panic!("couldn't create {:?}: {:?}", path, why));
+                writeln!(file, "{}", HEAT_COLUMNS).unwrap();
+                Some(file)
+            } else {
+                None
+            };
+            let mut heat_per_epoch = HashMap::new();
+            summary_triples.unary_notify::<(), _, _>(pact::Exchange::new(|_| 0),
+                                                     "HeatCsv",
+                                                     vec![],
+                                                     move |input, _output, notificator| {
+                input.for_each(|time, data| {
+                    let heat = heat_per_epoch.entry(*time.time()).or_insert_with(HashMap::new);
+                    for &((_, ref operator_address, _, _), ref summary) in data.iter() {
+                        *heat.entry(scalar_operator_id(operator_address)).or_insert(0u64) +=
+                            summary.weight;
+                    }
+                    notificator.notify_at(time.retain());
+                });
+                notificator.for_each(|time, _count, _notify| {
+                    if let Some(heat) = heat_per_epoch.remove(time.time()) {
+                        let mut heat: Vec<_> = heat.into_iter().collect();
+                        heat.sort();
+                        let file = heat_file.as_mut().expect("heat CSV is written on worker 0");
+                        for (operator_id, total_weight) in heat {
+                            writeln!(file, "{:?},{},{}", time.time(), operator_id, total_weight)
+                                .unwrap();
+                        }
+                    }
+                });
+            });
+        }
+        if let Some(threshold) = config.alert_weighted_bc {
+            if index == 0 {
+                println!("# ALERT {}", ALERT_COLUMNS);
+                println!("# ALERT_CLEAR {}", ALERT_CLEAR_COLUMNS);
+            }
+            bottleneck_alerts(&summary_triples, threshold, config.alert_streak);
+        }
+
+        // Reshape the cross-worker summaries into a worker-by-worker matrix per epoch
+        if config.communication_matrix {
+            if index == 0 {
+                println!("# COMM_MATRIX {}", COMM_MATRIX_COLUMNS);
+            }
+            write_per_epoch(&summary_triples, "CommunicationMatrix", |epoch, summaries| {
+                let mut matrix = HashMap::new();
+                let mut workers = HashSet::new();
+                for ((_, _, activity_workers, _), summary) in summaries {
+                    let (src, dst) = match activity_workers {
+                        ActivityWorkers::Local(w_id) => {
+                            workers.insert(w_id);
+                            continue;
+                        }
+                        ActivityWorkers::Remote(src, dst) => (src, dst),
+                    };
+                    workers.insert(src);
+                    workers.insert(dst);
+                    let cell = matrix.entry((src, dst)).or_insert((0u64, 0u64));
+                    cell.0 += summary.count;
+                    cell.1 += summary.weight;
+                }
+                let mut workers: Vec<_> = workers.into_iter().collect();
+                workers.sort();
+                for &src in &workers {
+                    for &dst in &workers {
+                        let (count, weight) = matrix.get(&(src, dst)).cloned().unwrap_or((0, 0));
+                        println!("COMM_MATRIX {:?},{},{},{},{}", epoch, src, dst, count, weight);
+                    }
+                }
+            });
+        }
+
+        let summaries = if config.fill_absent_operators {
+            fill_absent_operators(&summary_triples)
+        } else {
+            summary_triples.exchange(|_| 0)
+        };
+        let summaries = critical_share(&summaries, &sp);
+        if config.mark_empty_epochs && config.summary_sink.is_none() {
+            let format = config.summary_format;
+            report_empty_epochs(&stream, &summaries, "EmptySummaries", move |epoch| match format {
+                SummaryFormat::Csv => println!("SUMMARY {:?} EMPTY", epoch),
+                SummaryFormat::Jsonl => {
+                    let line = object!{
+                        "epoch" => format!("{:?}", epoch),
+                        "empty" => true
+                    };
+                    println!("SUMMARY {}", line.dump());
+                }
+            });
+        }
+        let summaries = match config.partial_windows {
+            PartialWindows::Keep => summaries.map(|summary| (summary, false)),
+            PartialWindows::Drop => mark_partial_windows(&summaries).filter(|&(_, partial)| !partial),
+            PartialWindows::Tag => mark_partial_windows(&summaries),
+        };
+        // Print the summaries of each epoch sorted by their key, so that the output of two runs
+        // can be compared
+        let summaries = sort_per_epoch(&summaries, "SortSummaries", |&((ref summary, _), _)| summary.0.clone());
+        let mut writer = SummaryWriter::new(config.summary_format, config.activity_labels.clone());
+        let sink = config.summary_sink.clone();
+        summaries
+            .inspect_batch(move |ts, output| for &((ref summary, critical_share), partial) in output {
+                               let row = summary_row(summary.clone());
+                               #[cfg(feature = "metrics")]
+                               {
+                                   if metrics {
+                                       crate::metrics::add_activity_paths(ts, row.activity_type, row.bc);
+                                   }
+                               }
+                               match sink {
+                                   Some(ref sink) => (&mut *sink.lock().unwrap())(SummaryRecord {
+                                       epoch: *ts,
+                                       summary: row,
+                                       bc_critical_share: critical_share,
+                                       partial,
+                                   }),
+                                   None => writer.write(ts, &row, critical_share, partial),
+                               }
                            })
             .probe()
     };
+    probes.register("summary", probe_summary);
+
+    // Summaries keyed by a custom grouping
+    let grouped = match config.summary_grouping {
+        SummaryGrouping::Default => false,
+        _ => true,
+    };
+    if grouped {
+        if index == 0 {
+            println!("# GROUPED_SUMMARY {}", GROUPED_SUMMARY_COLUMNS);
+        }
+        let grouping = config.summary_grouping.clone();
+        let operator_cost_multipliers = config.operator_cost_multipliers.clone();
+        bc.map(move |(edge, bc)| {
+                   (grouping.key(&edge).expect("grouping is not the default"),
+                    edge_summary(&edge, bc, &operator_cost_multipliers))
+               })
+            .aggregate::<_, Summary<_>, _, _, _>(|_key, val, agg| *agg += val,
+                                                 |key, agg| (key, agg),
+                                                 |key| hash_code(key))
+            .exchange(|_| 0)
+            .inspect_batch(|ts, output| for &(ref key, ref summary) in output {
+                               println!("GROUPED_SUMMARY {:?},{},{},{},{},{}",
+                                        ts,
+                                        key,
+                                        summary.bc,
+                                        summary.weighted_bc,
+                                        summary.count,
+                                        summary.weight);
+                           });
+    }
+
+    // BC totaled per operator, across workers and activity types
+    if config.operator_summary {
+        if index == 0 {
+            println!("# OPERATOR_SUMMARY {}", OPERATOR_SUMMARY_COLUMNS);
+        }
+        bc.map(|(edge, bc)| {
+                   let operator_id = match edge {
+                       PagOutput::Edge(ref e) => scalar_operator_id(&e.operator_address),
+                       et => panic!("Unknown input: {:?}", et),
+                   };
+                   (operator_id, (bc, 1u64))
+               })
+            .aggregate::<_, (f64, u64), _, _, _>(|_key, (bc, count), agg| {
+                                                     agg.0 += bc;
+                                                     agg.1 += count;
+                                                 },
+                                                 |key, agg| (key, agg),
+                                                 |key| *key)
+            .exchange(|_| 0)
+            .inspect_batch(|ts, output| for &(operator_id, (bc, count)) in output {
+                               println!("OPERATOR_SUMMARY {:?},{},{},{}", ts, operator_id, bc, count);
+                           });
+    }
 
     // Generate random single-path summaries
     let e_weight = sp.map(|edge| {
         let w = edge.weight();
         let edge_type = match edge {
-            PagOutput::Edge(ref e) => (e.edge_type as u8, e.operator_id.unwrap_or(255) as u8),
+            PagOutput::Edge(ref e) => (e.edge_type as u8, e.operator_address.clone()),
             et => panic!("Unknown input: {:?}", et),
         };
         (edge_type,
@@ -609,24 +4189,622 @@ pub fn build_dataflow<S>
                                                      |key, agg| (key, agg),
                                                      |key| hash_code(key));
 
-    sp_summary.inspect_batch(move |ts, output| for &(t, ref summary) in output {
-                                 println!("SP_SUMMARY {:?} {:?} {} {} {} {} {} {}",
-                                          ts,
-                                          index,
-                                          t.0,
-                                          t.1,
-                                          summary.bc,
-                                          summary.weighted_bc,
-                                          summary.count,
-                                          summary.weight)
-                             });
-
-    let probe_sp_summary = sp_summary.probe();
-
-    (input,
-     vec![probe_pag,
-          probe_bc,
-          probe_sp,
-          probe_summary,
-          probe_sp_summary])
+    // Print the summaries of each epoch in a fixed order, so that runs with `sp_seed` produce
+    // identical output
+    let sp_summary_rows = sp_summary.map(move |(t, summary)| (index, t, summary));
+    write_per_epoch(&sp_summary_rows, "SpSummary", |ts, mut rows| {
+        rows.sort_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1)));
+        for (index, t, summary) in rows {
+            println!("SP_SUMMARY {:?} {:?} {} {} {} {} {} {}",
+                     ts,
+                     index,
+                     t.0,
+                     scalar_operator_id(&t.1),
+                     summary.bc,
+                     summary.weighted_bc,
+                     summary.count,
+                     summary.weight)
+        }
+    });
+
+    probes.register("sp_summary", sp_summary.probe());
+    if config.mark_empty_epochs {
+        report_empty_epochs(&stream, &sp_summary, "EmptySpSummaries", |epoch| {
+            println!("SP_SUMMARY {:?} EMPTY", epoch)
+        });
+    }
+
+    // Summarize each dataflow scope separately, e.g. to find the critical path of a region
+    if config.group_by_scope {
+        fn edge_scope(output: &PagOutput) -> Option<OperatorAddress> {
+            match *output {
+                PagOutput::Edge(ref e) => e.scope(),
+                ref et => panic!("Unknown input: {:?}", et),
+            }
+        }
+        if index == 0 {
+            println!("# SCOPE_SUMMARY {}", SCOPE_SUMMARY_COLUMNS);
+            println!("# SCOPE_SP_SUMMARY {}", SCOPE_SP_SUMMARY_COLUMNS);
+        }
+        let operator_cost_multipliers = config.operator_cost_multipliers.clone();
+        bc.map(move |(edge, bc)| {
+                   (edge_scope(&edge), edge_summary(&edge, bc, &operator_cost_multipliers))
+               })
+            .aggregate::<_, Summary<_>, _, _, _>(|_key, val, agg| *agg += val,
+                                                 |key, agg| (key, agg),
+                                                 |key| hash_code(key))
+            .exchange(|_| 0)
+            .inspect_batch(|ts, output| for &(ref scope, ref summary) in output {
+                               println!("SCOPE_SUMMARY {:?},{},{},{},{},{}",
+                                        ts,
+                                        scope_name(scope),
+                                        summary.bc,
+                                        summary.weighted_bc,
+                                        summary.count,
+                                        summary.weight);
+                           });
+        sp.map(|edge| {
+                   let w = edge.weight();
+                   (edge_scope(&edge),
+                    Summary {
+                        weight: w,
+                        bc: 1u64,
+                        weighted_bc: w,
+                        count: 1,
+                    })
+               })
+            .aggregate::<_, Summary<_>, _, _, _>(|_key, val, agg| *agg += val,
+                                                 |key, agg| (key, agg),
+                                                 |key| hash_code(key))
+            .exchange(|_| 0)
+            .inspect_batch(|ts, output| for &(ref scope, ref summary) in output {
+                               println!("SCOPE_SP_SUMMARY {:?},{},{},{}",
+                                        ts,
+                                        scope_name(scope),
+                                        summary.count,
+                                        summary.weight);
+                           });
+    }
+
+    // Rank operators by how much of the critical path they account for over the whole run
+    if config.critical_path_report {
+        let mut per_operator = HashMap::new();
+        let mut vector = Vec::new();
+        let mut printed = false;
+        sp.unary_frontier::<(), _, _, _>(pact::Exchange::new(|_| 0),
+                                         "CriticalPathReport",
+                                         move |_cap, _info| {
+            move |input, _output| {
+                input.for_each(|time, data| {
+                    data.swap(&mut vector);
+                    for edge in vector.drain(..) {
+                        if let PagOutput::Edge(ref e) = edge {
+                            let (epochs, weight) = per_operator
+                                .entry(e.operator_address.clone())
+                                .or_insert_with(|| (HashSet::new(), 0u64));
+                            epochs.insert(*time.time());
+                            *weight += e.weight();
+                        }
+                    }
+                });
+                if index == 0 && !printed && input.frontier().frontier().is_empty() {
+                    printed = true;
+                    print_critical_path_report(per_operator.drain().collect());
+                }
+            }
+        });
+    }
+
+    // Estimate how much parallelism each epoch offers: its total work over its critical path
+    if config.parallelism {
+        if index == 0 {
+            println!("# PARALLELISM {}", PARALLELISM_COLUMNS);
+            println!("# PARALLELISM_TOTAL {}", PARALLELISM_TOTAL_COLUMNS);
+        }
+        fn ratio(work: u64, span: u64) -> f64 {
+            if span > 0 {
+                work as f64 / span as f64
+            } else {
+                0.
+            }
+        }
+        let mut per_epoch = HashMap::new();
+        let mut total = (0u64, 0u64);
+        let mut printed = false;
+        let work = pag_output.flat_map(|output| match output {
+                                           PagOutput::Edge(edge) => Some((false, edge.weight())),
+                                           _ => None,
+                                       });
+        let span = sp.map(|edge| (true, edge.weight()));
+        work.concat(&span)
+            .unary_frontier::<(), _, _, _>(pact::Exchange::new(|_| 0),
+                                           "Parallelism",
+                                           move |_cap, _info| {
+                move |input, _output| {
+                    input.for_each(|time, data| {
+                        let weights = per_epoch.entry(*time.time()).or_insert((0u64, 0u64));
+                        for &(critical, weight) in data.iter() {
+                            if critical {
+                                weights.1 += weight;
+                            } else {
+                                weights.0 += weight;
+                            }
+                        }
+                    });
+                    let mut complete: Vec<_> = per_epoch
+                        .keys()
+                        .filter(|epoch| !input.frontier().less_equal(epoch))
+                        .cloned()
+                        .collect();
+                    complete.sort();
+                    for epoch in complete {
+                        let (work, span) = per_epoch.remove(&epoch).expect("epoch is buffered");
+                        total.0 += work;
+                        total.1 += span;
+                        println!("PARALLELISM {:?},{},{},{}", epoch, work, span, ratio(work, span));
+                    }
+                    if index == 0 && !printed && input.frontier().frontier().is_empty() {
+                        printed = true;
+                        println!("PARALLELISM_TOTAL {},{},{}", total.0, total.1, ratio(total.0, total.1));
+                    }
+                }
+            });
+    }
+
+    (input, probes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap, HashSet};
+    use std::time::Duration;
+
+    use logformat::{ActivityType, EventType, LogRecord};
+    use snailtrail::exploration::CriticalPath;
+    use snailtrail::graph::SrcDst;
+
+    use super::{build_dataflow, collect_bc, collect_critical_paths, collect_pag, edge_deltas, execute_collecting,
+                feed_input, program_activity_graph, slide_windows, utc_timestamp, Config, ProbeWrapper};
+    use super::{bc_confidence, busy_workers, orphan_edges, sample_seed_edges, windows_containing, TraverseMode,
+                WeightStats};
+    use crate::{PagEdge, PagOutput};
+
+    fn record(timestamp_ns: u64,
+              worker: u64,
+              activity_type: ActivityType,
+              event_type: EventType,
+              remote_worker: Option<u64>,
+              correlator_id: Option<u64>,
+              operator_id: Option<u64>)
+              -> LogRecord {
+        LogRecord {
+            timestamp: Duration::from_nanos(timestamp_ns),
+            local_worker: worker,
+            activity_type,
+            event_type,
+            correlator_id,
+            remote_worker,
+            operator_id,
+        }
+    }
+
+    /// Three epochs of two workers that process, exchange a message and process again.
+    fn synthetic_trace() -> Vec<LogRecord> {
+        let mut records = Vec::new();
+        for epoch in 0..3u64 {
+            let base = (epoch + 1) * 1_000_000_000;
+            for worker in 0..2u64 {
+                let offset = base + worker * 100;
+                let correlator = Some(epoch * 2 + worker);
+                records.push(record(offset + 10_000, worker, ActivityType::Processing,
+                                    EventType::Start, None, None, Some(1)));
+                records.push(record(offset + 20_000, worker, ActivityType::Processing,
+                                    EventType::End, None, None, Some(1)));
+                records.push(record(offset + 30_000, worker, ActivityType::DataMessage,
+                                    EventType::Sent, Some(1 - worker), correlator, None));
+                records.push(record(offset + 45_000, 1 - worker, ActivityType::DataMessage,
+                                    EventType::Received, Some(worker), correlator, None));
+                records.push(record(offset + 60_000, worker, ActivityType::Processing,
+                                    EventType::Start, None, None, Some(2)));
+                records.push(record(offset + 80_000, worker, ActivityType::Processing,
+                                    EventType::End, None, None, Some(2)));
+            }
+        }
+        records.sort_by_key(|rec| rec.timestamp);
+        records
+    }
+
+    fn config(workers: usize) -> Config {
+        Config {
+            timely_args: vec!["-w".to_string(), workers.to_string()],
+            threshold: 1_000,
+            window_size_ns: 1_000_000_000,
+            insert_waiting_edges: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn workers_without_records_are_counted_by_id() {
+        assert_eq!(busy_workers(&[0, 1], 2), 2);
+        assert_eq!(busy_workers(&[0, 1], 4), 2);
+        // As many captured workers as analysis workers, but two of them map to worker 0
+        assert_eq!(busy_workers(&[0, 2], 2), 1);
+        assert_eq!(busy_workers(&[1, 2, 3], 2), 2);
+        assert_eq!(busy_workers(&[], 2), 0);
+    }
+
+    #[test]
+    fn feeding_the_first_windows_does_not_underflow() {
+        let config = Config { epochs: 3, ..config(1) };
+        let window_size_ns = config.window_size_ns;
+        let records = vec![record(0, 0, ActivityType::Processing, EventType::Start, None, None, Some(1)),
+                           record(10, 0, ActivityType::Processing, EventType::End, None, None, Some(1)),
+                           // Received without a send, which is assumed to be before window 0
+                           record(20, 0, ActivityType::DataMessage, EventType::Received, Some(0), Some(1), None),
+                           record(window_size_ns + 10, 0, ActivityType::Processing,
+                                  EventType::Start, None, None, Some(1)),
+                           record(2 * window_size_ns - 10, 0, ActivityType::Processing,
+                                  EventType::End, None, None, Some(1))];
+        timely::execute_from_args(config.timely_args.clone().into_iter(), move |computation| {
+            let (input, probes) = computation.dataflow(|scope| build_dataflow(config.clone(), scope));
+            feed_input(input, records.clone(), ProbeWrapper::from_registry(probes), computation, &config, None)
+                .expect("the input is sorted");
+        }).expect("computation failed");
+    }
+
+    #[test]
+    fn unsorted_input_is_an_error_unless_reordered() {
+        let config = Config { epochs: 3, reorder_window_ns: 100, ..config(1) };
+        let records = vec![record(100, 0, ActivityType::Processing, EventType::Start, None, None, Some(1)),
+                           record(300, 0, ActivityType::Processing, EventType::End, None, None, Some(1)),
+                           record(200, 0, ActivityType::Processing, EventType::Start, None, None, Some(2)),
+                           record(400, 0, ActivityType::Processing, EventType::End, None, None, Some(2))];
+        let results = timely::execute_from_args(config.timely_args.clone().into_iter(), move |computation| {
+            [false, true]
+                .iter()
+                .map(|&reorder_input| {
+                    let config = Config { reorder_input, ..config.clone() };
+                    let (input, probes) = computation.dataflow(|scope| build_dataflow(config.clone(), scope));
+                    feed_input(input, records.clone(), ProbeWrapper::from_registry(probes), computation, &config, None)
+                        .map_err(|why| why.to_string())
+                })
+                .collect::<Vec<_>>()
+        }).expect("computation failed")
+            .join()
+            .pop()
+            .unwrap()
+            .unwrap();
+        assert_eq!(results,
+                   vec![Err("the input is not sorted: record 2 at 200ns is earlier than record 1 at 300ns"
+                            .to_string()),
+                        Ok(())]);
+    }
+
+    #[test]
+    fn sliding_windows_contain_each_record() {
+        let nanos = Duration::from_nanos;
+        // Tumbling windows of 10ns
+        assert_eq!(windows_containing(nanos(0), 10, 10), (nanos(0), nanos(0)));
+        assert_eq!(windows_containing(nanos(25), 10, 10), (nanos(2), nanos(2)));
+        // Windows of 10ns starting every 4ns: [16, 26), [20, 30) and [24, 34) contain 25
+        assert_eq!(windows_containing(nanos(3), 10, 4), (nanos(0), nanos(0)));
+        assert_eq!(windows_containing(nanos(25), 10, 4), (nanos(4), nanos(6)));
+        assert_eq!(windows_containing(nanos(26), 10, 4), (nanos(5), nanos(6)));
+    }
+
+    #[test]
+    fn builder_validates_window_and_log_path() {
+        assert!(Config::builder().log_path("trace.bin").build().is_err());
+        assert!(Config::builder().window_size_ns(1_000).build().is_err());
+        assert!(Config::builder()
+                    .log_path("trace.bin")
+                    .window_size_ns(1_000)
+                    .configure(|config| config.sample_every = Some(0))
+                    .build()
+                    .is_err());
+        let config = Config::builder()
+            .log_path("trace.bin")
+            .window_size_ns(1_000)
+            .with_waiting_edges(true)
+            .build()
+            .unwrap();
+        assert_eq!(config.window_size_ns, 1_000);
+        assert!(config.insert_waiting_edges);
+    }
+
+    #[test]
+    fn bc_is_independent_of_worker_count() {
+        let expected = collect_bc(config(1), synthetic_trace());
+        assert!(!expected.is_empty());
+        for &workers in &[2, 4] {
+            let bc = collect_bc(config(workers), synthetic_trace());
+            assert_eq!(bc.len(), expected.len(), "BC differs between 1 and {} workers", workers);
+            // The contributions to an edge are summed in arrival order, which depends on the
+            // partitioning, so the sums only agree up to rounding
+            for ((edge, bc), (expected_edge, expected_bc)) in bc.iter().zip(expected.iter()) {
+                assert_eq!(edge, expected_edge);
+                assert!((bc - expected_bc).abs() <= 1e-9 * expected_bc.abs().max(1.),
+                        "BC of {:?} is {} with {} workers, but {} with 1",
+                        edge,
+                        bc,
+                        workers,
+                        expected_bc);
+            }
+        }
+    }
+
+    #[test]
+    fn sampled_bc_reports_its_standard_error() {
+        assert_eq!(bc_confidence(10., 1.), (10., 0.));
+        let (samples, stderr) = bc_confidence(8., 0.5);
+        assert_eq!(samples, 4.);
+        assert!((stderr - 2f64.sqrt() / 0.5).abs() < 1e-9);
+        // Sampling all start nodes is exact
+        let sampled = collect_bc(Config {
+                                     bc_sample_fraction: Some(1.),
+                                     ..config(1)
+                                 },
+                                 synthetic_trace());
+        assert_eq!(sampled, collect_bc(config(1), synthetic_trace()));
+    }
+
+    #[test]
+    fn waiting_edges_are_only_traversed_when_included() {
+        fn waiting_bc(bc: Vec<(PagOutput, f64)>) -> Vec<f64> {
+            bc.into_iter()
+                .filter_map(|(output, bc)| match output {
+                    PagOutput::Edge(ref e) if e.edge_type == ActivityType::Waiting => Some(bc),
+                    _ => None,
+                })
+                .collect()
+        }
+        let excluded = waiting_bc(collect_bc(config(1), synthetic_trace()));
+        assert!(!excluded.is_empty());
+        assert!(excluded.iter().all(|&bc| bc == 0.));
+        let included = waiting_bc(collect_bc(Config {
+                                                 bc_traverse: TraverseMode::IncludeWaiting,
+                                                 ..config(1)
+                                             },
+                                             synthetic_trace()));
+        assert!(included.iter().any(|&bc| bc > 0.));
+    }
+
+    #[test]
+    fn collected_pag_has_an_entry_per_epoch() {
+        let path = std::env::temp_dir().join(format!("collect_pag_{}.bin", std::process::id()));
+        {
+            let mut file = std::fs::File::create(&path).unwrap();
+            for rec in synthetic_trace() {
+                rec.write(&mut file).unwrap();
+            }
+        }
+        let pag = collect_pag(Config {
+                                  log_path: path.to_str().unwrap().to_string(),
+                                  ..config(2)
+                              })
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut epochs: Vec<_> = pag.keys().cloned().collect();
+        epochs.sort();
+        assert_eq!(epochs, vec![Duration::new(0, 1), Duration::new(0, 2), Duration::new(0, 3)]);
+        for outputs in pag.values() {
+            assert!(outputs.iter().any(|output| match *output {
+                                           PagOutput::Edge(_) => true,
+                                           _ => false,
+                                       }));
+        }
+    }
+
+    #[test]
+    fn critical_paths_are_reproducible_with_seed() {
+        let seeded = |workers| Config {
+            sp_seed: Some(42),
+            ..config(workers)
+        };
+        let first = collect_critical_paths(seeded(2), synthetic_trace());
+        assert!(!first.is_empty());
+        for run in 0..3 {
+            let again = collect_critical_paths(seeded(2), synthetic_trace());
+            assert_eq!(format!("{:?}", again),
+                       format!("{:?}", first),
+                       "critical paths differ in run {}",
+                       run + 2);
+        }
+    }
+
+    #[test]
+    fn max_weight_paths_follow_the_heaviest_edge() {
+        let heaviest = Config {
+            sp_seed: Some(42),
+            max_weight_path: true,
+            ..config(2)
+        };
+        let path = collect_critical_paths(heaviest.clone(), synthetic_trace());
+        assert!(!path.is_empty());
+        let traverse = heaviest.bc_traverse;
+        let pag: Vec<_> = execute_collecting(heaviest, synthetic_trace(), |config, stream| {
+                program_activity_graph(config, stream)
+            })
+            .into_iter()
+            .map(|(_, output)| output)
+            .filter(|output| traverse.traverses(output))
+            .collect();
+        for edge in &path {
+            for other in pag.iter().filter(|other| other.src() == edge.src()) {
+                assert!(other.weight() <= edge.weight(), "{:?} is heavier than {:?}", other, edge);
+            }
+        }
+    }
+
+    #[test]
+    fn longest_paths_are_connected() {
+        let paths = execute_collecting(config(2), synthetic_trace(), |config, stream| {
+            let pag = program_activity_graph(config, stream);
+            let starts = pag.filter(|output| match *output {
+                                        PagOutput::StartNode(_) => true,
+                                        _ => false,
+                                    });
+            let ends = pag.filter(|output| match *output {
+                                      PagOutput::EndNode(_) => true,
+                                      _ => false,
+                                  });
+            pag.critical_path(&starts, &ends)
+        });
+        assert!(!paths.is_empty());
+        for (_, path) in paths {
+            assert!(!path.is_empty());
+            for pair in path.windows(2) {
+                assert_eq!(pair[0].dst(), pair[1].src());
+            }
+        }
+    }
+
+    #[test]
+    fn focus_keeps_the_neighbors_of_the_operators() {
+        let focused = Config {
+            focus_operators: Some(vec![2]),
+            ..config(2)
+        };
+        let pag: Vec<_> = execute_collecting(focused, synthetic_trace(), |config, stream| {
+                program_activity_graph(config, stream)
+            })
+            .into_iter()
+            .filter_map(|(_, output)| match output {
+                            PagOutput::Edge(edge) => Some(edge),
+                            _ => None,
+                        })
+            .collect();
+        assert!(pag.iter().any(|edge| edge.operator_id == Some(2)));
+        assert!(pag.iter().all(|edge| edge.operator_id.is_some()));
+        let nodes: HashSet<_> = pag.iter()
+            .filter(|edge| edge.operator_id == Some(2))
+            .flat_map(|edge| vec![edge.source, edge.destination])
+            .collect();
+        for edge in &pag {
+            assert!(edge.operator_id == Some(2) || nodes.contains(&edge.source) || nodes.contains(&edge.destination),
+                    "{:?} is not a neighbor",
+                    edge);
+        }
+    }
+
+    #[test]
+    fn orphan_edges_are_not_connected_to_a_boundary_or_edge() {
+        use crate::{PagNode, TraversalType};
+
+        let node = |timestamp: u64| PagNode {
+            timestamp: Duration::from_nanos(timestamp),
+            worker_id: 0,
+        };
+        let edge = |source: u64, destination: u64| PagEdge {
+            source: node(source),
+            destination: node(destination),
+            edge_type: ActivityType::Processing,
+            operator_id: None,
+            operator_address: None,
+            injected_delay_ns: 0,
+            traverse: TraversalType::Block,
+        };
+        let outputs = vec![PagOutput::StartNode(node(0)),
+                           PagOutput::Edge(edge(0, 10)),
+                           PagOutput::Edge(edge(10, 20)),
+                           PagOutput::EndNode(node(20)),
+                           PagOutput::Edge(edge(30, 40)),
+                           PagOutput::Edge(edge(10, 50))];
+        let orphans = orphan_edges(&outputs);
+        assert_eq!(orphans, vec![&edge(30, 40), &edge(10, 50)]);
+    }
+
+    #[test]
+    fn activity_labels_override_names() {
+        use crate::activity_label;
+
+        let mut labels = HashMap::new();
+        labels.insert(ActivityType::Processing as u8, "Compute".to_string());
+        assert_eq!(activity_label(ActivityType::Processing, Some(&labels)), "Compute");
+        assert_eq!(activity_label(ActivityType::Input, Some(&labels)), ActivityType::Input.name());
+        assert_eq!(activity_label(ActivityType::Processing, None), ActivityType::Processing.name());
+    }
+
+    #[test]
+    fn seed_edges_are_sampled_past_the_cap() {
+        use crate::PagNode;
+        use rand::SeedableRng;
+        use rand::rngs::StdRng;
+
+        let start = |timestamp: u64| PagOutput::StartNode(PagNode {
+            timestamp: Duration::from_nanos(timestamp),
+            worker_id: 0,
+        });
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut accum = Vec::new();
+        let mut seen = 0;
+        sample_seed_edges(&mut accum, &mut seen, &[start(1), start(2)], Some(3), &mut rng);
+        assert_eq!(accum, vec![start(1), start(2)]);
+        let batch: Vec<_> = (3..10).map(start).collect();
+        sample_seed_edges(&mut accum, &mut seen, &batch, Some(3), &mut rng);
+        sample_seed_edges(&mut accum, &mut seen, &[start(10)], Some(3), &mut rng);
+        assert_eq!(seen, 10);
+        assert_eq!(accum.len(), 1);
+    }
+
+    #[test]
+    fn weight_stats_are_population_statistics() {
+        let mut stats = WeightStats::default();
+        for &weight in &[2, 4, 4, 4, 5, 5, 7, 9] {
+            stats.add(weight);
+        }
+        assert_eq!((stats.count, stats.min, stats.max), (8, 2, 9));
+        assert_eq!(stats.mean(), 5.);
+        assert_eq!(stats.stddev(), 2.);
+    }
+
+    #[test]
+    fn run_manifest_is_written_to_the_output_dir() {
+        assert_eq!(config(1).run_manifest_path(), "manifest.json");
+        let in_dir = Config {
+            output_dir: Some("results".to_string()),
+            ..config(1)
+        };
+        assert_eq!(in_dir.run_manifest_path(), "results/manifest.json");
+        assert_eq!(utc_timestamp(std::time::UNIX_EPOCH + Duration::from_secs(86_400)),
+                   "1970-01-02T00:00:00Z");
+    }
+
+    #[test]
+    fn edge_deltas_add_up_to_the_edges_of_each_window() {
+        let sliding = Config {
+            window_size_ns: 2_000_000_000,
+            window_stride_ns: Some(1_000_000_000),
+            ..config(2)
+        };
+        let mut windows: HashMap<Duration, HashSet<PagEdge>> = HashMap::new();
+        for (epoch, output) in execute_collecting(sliding.clone(), synthetic_trace(), |config, stream| {
+            program_activity_graph(config, &slide_windows(config, stream))
+        }) {
+            if let PagOutput::Edge(edge) = output {
+                windows.entry(epoch).or_insert_with(HashSet::new).insert(edge);
+            }
+        }
+        let mut deltas: BTreeMap<Duration, Vec<(PagEdge, i64)>> = BTreeMap::new();
+        for (epoch, delta) in execute_collecting(sliding, synthetic_trace(), |config, stream| {
+            edge_deltas(config, stream)
+        }) {
+            deltas.entry(epoch).or_insert_with(Vec::new).push(delta);
+        }
+        assert!(!deltas.is_empty());
+
+        let mut edges = HashSet::new();
+        for (epoch, delta) in deltas {
+            for (edge, diff) in delta {
+                match diff {
+                    1 => assert!(edges.insert(edge)),
+                    -1 => assert!(edges.remove(&edge)),
+                    _ => panic!("unexpected diff {}", diff),
+                }
+            }
+            assert_eq!(edges, windows.remove(&epoch).unwrap_or_default(), "edges differ in {:?}", epoch);
+        }
+        assert!(windows.is_empty());
+    }
 }
@@ -6,72 +6,1117 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::fs::File;
-use std::io::BufReader;
+use std::cell::RefCell;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, sync_channel, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
 use std::time::Duration;
-use std::collections::{HashSet, HashMap};
-use logformat::{CorrelatorId, EventType, LogRecord, LogReadError, Timestamp, Worker};
-
-/// Read and decode all log records from a log file and give them as input in a single epoch.  In a
-/// real computation we'd read input in the background and allow the computation to progress by
-/// continually making steps.
-pub fn read_sorted_trace_from_file_and_cut_messages(log_path: &str,
-                                                    message_delay: Option<u64>)
-                                                    -> Vec<LogRecord> {
-    let file = File::open(log_path).expect("Unable to open input file");
-    let mut reader = BufReader::with_capacity(1 << 22, file);
-    let mut input_records = Vec::new();
-    loop {
-        match LogRecord::read(&mut reader) {
-            Ok(rec) => {
-                input_records.push(rec);
-            }
-            Err(LogReadError::Eof) => {
-                break;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashSet, HashMap, VecDeque};
+use flate2::read::GzDecoder;
+use timely::dataflow::operators::capture::{Event, EventIterator, EventReader};
+use timely::logging::{StartStop, TimelyEvent};
+
+use logformat::{ActivityType, CorrelatorId, EventType, LogRecord, LogReadError, Timestamp, Worker};
+
+/// Maximum number of bytes scanned for the next decodable record after a corrupt one.
+const MAX_RESYNC_BYTES: u64 = 1 << 20;
+
+/// What to do when a record in the middle of a trace cannot be decoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CorruptRecordPolicy {
+    /// Stop reading the trace (the default)
+    Abort,
+    /// Skip any number of corrupt records
+    Skip,
+    /// Skip at most the given number of corrupt records, abort on the next one
+    SkipN(usize),
+}
+
+impl Default for CorruptRecordPolicy {
+    fn default() -> Self {
+        CorruptRecordPolicy::Abort
+    }
+}
+
+impl FromStr for CorruptRecordPolicy {
+    type Err = String;
+
+    /// Parses `abort`, `skip` or `skip-<N>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "abort" => Ok(CorruptRecordPolicy::Abort),
+            "skip" => Ok(CorruptRecordPolicy::Skip),
+            _ if s.starts_with("skip-") => {
+                s["skip-".len()..]
+                    .parse()
+                    .map(CorruptRecordPolicy::SkipN)
+                    .map_err(|e| format!("invalid skip count in {:?}: {}", s, e))
             }
-            Err(LogReadError::DecodeError(msg)) => {
-                eprintln!("could not decode record: {:?}", msg);
+            _ => Err(format!("unknown corrupt record policy {:?}", s)),
+        }
+    }
+}
+
+impl CorruptRecordPolicy {
+    /// Whether another corrupt record may be skipped after `skipped` have been skipped already.
+    fn allows_skip(&self, skipped: usize) -> bool {
+        match *self {
+            CorruptRecordPolicy::Abort => false,
+            CorruptRecordPolicy::Skip => true,
+            CorruptRecordPolicy::SkipN(max) => skipped < max,
+        }
+    }
+}
+
+/// Why a trace could not be read.
+#[derive(Debug)]
+pub enum TraceError {
+    /// A trace file could not be opened or read
+    Io(io::Error),
+    /// A record could not be decoded and the `CorruptRecordPolicy` does not allow skipping it
+    Decode(String),
+}
+
+/// Records the first error while reading a trace whose records are consumed elsewhere, e.g.
+/// by `feed_input`, so that it can be reported once reading stopped.
+#[derive(Clone, Default)]
+pub struct ReadStatus {
+    error: Arc<Mutex<Option<TraceError>>>,
+}
+
+impl ReadStatus {
+    fn fail(&self, error: TraceError) {
+        let mut first = self.error.lock().expect("read status poisoned");
+        if first.is_none() {
+            *first = Some(error);
+        }
+    }
+
+    /// The first error, if reading stopped because of one.
+    pub fn result(&self) -> Result<(), TraceError> {
+        match self.error.lock().expect("read status poisoned").take() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Counts the bytes read so far so that we know where a corrupt record started.
+struct PositionedReader<R> {
+    inner: R,
+    position: u64,
+}
+
+impl<R: Read + Seek> PositionedReader<R> {
+    fn seek_to(&mut self, position: u64) -> io::Result<()> {
+        self.inner.seek(SeekFrom::Start(position))?;
+        self.position = position;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for PositionedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+/// How a message longer than its `MessageDelay` is clipped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageDelayMode {
+    /// Move the timestamp of the `Received` record (the default)
+    Shift,
+    /// Keep the timestamps and only record the message in `MessageDelay::clipped`
+    Annotate,
+}
+
+impl Default for MessageDelayMode {
+    fn default() -> Self {
+        MessageDelayMode::Shift
+    }
+}
+
+impl FromStr for MessageDelayMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "shift" => Ok(MessageDelayMode::Shift),
+            "annotate" => Ok(MessageDelayMode::Annotate),
+            _ => Err(format!("unknown message delay mode {:?}", s)),
+        }
+    }
+}
+
+/// A message longer than its delay, with the original timestamps of its records.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClippedMessage {
+    pub sender: Worker,
+    pub receiver: Worker,
+    pub correlator_id: Option<CorrelatorId>,
+    pub activity_type: ActivityType,
+    /// The timestamp of the `Sent` record
+    pub sent: Timestamp,
+    /// The original timestamp of the `Received` record
+    pub received: Timestamp,
+    /// The timestamp of the `Received` record after clipping, which is only applied with
+    /// `MessageDelayMode::Shift`
+    pub clipped: Timestamp,
+}
+
+/// Collects the messages clipped while reading a trace, shared by all clones.
+#[derive(Clone, Debug, Default)]
+pub struct ClippedMessages {
+    messages: Arc<Mutex<Vec<ClippedMessage>>>,
+}
+
+impl ClippedMessages {
+    fn push(&self, message: ClippedMessage) {
+        self.messages.lock().expect("clipped messages poisoned").push(message);
+    }
+
+    /// Removes and returns the messages clipped so far.
+    pub fn take(&self) -> Vec<ClippedMessage> {
+        ::std::mem::replace(&mut *self.messages.lock().expect("clipped messages poisoned"), Vec::new())
+    }
+}
+
+/// The maximum delay (ns) of messages, by the activity type of the message.
+///
+/// Clipping only moves the timestamp of the `Received` record of a message to at most the
+/// delay after its `Sent` record. The message edge of the PAG thus keeps its source timestamp
+/// (the send) and gets the clipped destination timestamp, and the receiving worker's
+/// timeline around the receive shifts accordingly. With `MessageDelayMode::Annotate` the
+/// timestamps are kept. Either way the original timestamps of every clipped message are
+/// recorded in `clipped`.
+#[derive(Clone, Debug, Default)]
+pub struct MessageDelay {
+    /// The delay of messages whose activity type has none of its own
+    pub uniform: Option<u64>,
+    pub per_activity: HashMap<ActivityType, u64>,
+    pub mode: MessageDelayMode,
+    pub clipped: ClippedMessages,
+}
+
+impl MessageDelay {
+    /// The delay applied to messages of `activity_type`, if any.
+    pub fn get(&self, activity_type: ActivityType) -> Option<u64> {
+        self.per_activity.get(&activity_type).cloned().or(self.uniform)
+    }
+
+    /// Clips the `Received` record `rec` of a message sent at `sent` if it is longer than its
+    /// delay, see `MessageDelayMode`.
+    fn clip(&self, sent: Timestamp, rec: &mut LogRecord) {
+        if let Some(delay) = self.get(rec.activity_type) {
+            let max_timestamp = sent + Duration::from_nanos(delay);
+            if rec.timestamp > max_timestamp {
+                self.clipped.push(ClippedMessage {
+                    sender: rec.remote_worker.unwrap(),
+                    receiver: rec.local_worker,
+                    correlator_id: rec.correlator_id,
+                    activity_type: rec.activity_type,
+                    sent,
+                    received: rec.timestamp,
+                    clipped: max_timestamp,
+                });
+                if self.mode == MessageDelayMode::Shift {
+                    rec.timestamp = max_timestamp;
+                }
             }
-        };
+        }
+    }
+}
+
+/// Delays are equal if they clip the same messages the same way, regardless of the messages
+/// clipped so far.
+impl PartialEq for MessageDelay {
+    fn eq(&self, other: &Self) -> bool {
+        self.uniform == other.uniform && self.per_activity == other.per_activity && self.mode == other.mode
+    }
+}
+
+/// The same delay for all messages.
+impl From<u64> for MessageDelay {
+    fn from(delay: u64) -> Self {
+        MessageDelay {
+            uniform: Some(delay),
+            ..Default::default()
+        }
     }
+}
+
+/// Parses a comma-separated list of `ACTIVITY=NS` entries and at most one plain `NS` applying
+/// to all other messages, e.g. `DataMessage=1000,ControlMessage=5000` or just `1000`.
+impl FromStr for MessageDelay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut delay = MessageDelay::default();
+        for entry in s.split(',').map(str::trim) {
+            let parse = |ns: &str| u64::from_str(ns).map_err(|why| format!("invalid delay {:?}: {}", ns, why));
+            match entry.find('=') {
+                Some(position) => {
+                    let activity_type = ActivityType::from_str(&entry[..position])?;
+                    delay.per_activity.insert(activity_type, parse(&entry[position + 1..])?);
+                }
+                None if delay.uniform.is_none() => delay.uniform = Some(parse(entry)?),
+                None => return Err(format!("more than one uniform delay in {:?}", s)),
+            }
+        }
+        Ok(delay)
+    }
+}
+
+impl std::fmt::Display for MessageDelay {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut entries: Vec<_> = self.per_activity.iter().collect();
+        entries.sort();
+        let mut entries: Vec<_> = entries.into_iter()
+            .map(|(activity_type, delay)| format!("{}={}", activity_type, delay))
+            .collect();
+        entries.extend(self.uniform.map(|delay| delay.to_string()));
+        f.write_str(&entries.join(","))
+    }
+}
+
+/// Read and decode all log records from the log files, sorted by timestamp. The records are
+/// then fed to the dataflow epoch by epoch, see `dataflow::feed_input`; `--stream-input` reads
+/// them in the background instead.
+///
+/// If `sample_every` is `Some(n)`, only about one in `n` records is retained (see
+/// `sample_records`), giving a fast but approximate PAG. Several traces are merged by
+/// timestamp, see `MergedRecords`. Messages longer than `message_delay` for their activity
+/// type are clipped, see `MessageDelay`.
+pub fn read_sorted_trace_from_file_and_cut_messages(log_paths: &[String],
+                                                    message_delay: Option<MessageDelay>,
+                                                    sample_every: Option<usize>,
+                                                    corrupt_record_policy: CorruptRecordPolicy)
+                                                    -> Result<Vec<LogRecord>, TraceError> {
+    let mut input_records = read_trace_from_file_and_cut_messages(log_paths,
+                                                                  message_delay,
+                                                                  sample_every,
+                                                                  corrupt_record_policy)?;
+    // Timely requires that time increases monotonically
+    input_records.sort_by_key(|rec| rec.timestamp);
+    Ok(input_records)
+}
 
-    // If `message_delay` is `Some`, clip messages to the contained value if longer
+/// Like `read_sorted_trace_from_file_and_cut_messages`, but keeps the records in the order they
+/// were read, see `first_unsorted_record`.
+pub fn read_trace_from_file_and_cut_messages(log_paths: &[String],
+                                             message_delay: Option<MessageDelay>,
+                                             sample_every: Option<usize>,
+                                             corrupt_record_policy: CorruptRecordPolicy)
+                                             -> Result<Vec<LogRecord>, TraceError> {
+    let status = ReadStatus::default();
+    let mut input_records: Vec<_> = MergedRecords::open(log_paths, corrupt_record_policy, status.clone())
+        .map_err(TraceError::Io)?
+        .collect();
+    status.result()?;
+
+    if let Some(sample_every) = sample_every {
+        input_records = sample_records(input_records, sample_every);
+    }
+
+    // If `message_delay` is `Some`, clip messages to the delay of their type if longer
     if let Some(message_delay) = message_delay {
         let mut send_stash: HashMap<(Worker, Worker, Option<CorrelatorId>), Timestamp> =
             HashMap::new();
 
-        // Find all sends
+        // Find all sends. Broadcasts, e.g. progress messages converted from a timely log, have
+        // no single receiver and are not clipped.
         for rec in &input_records {
-            if rec.event_type == EventType::Sent {
-                send_stash
-                    .insert((rec.local_worker, rec.remote_worker.unwrap(), rec.correlator_id),
-                            rec.timestamp);
+            if let (EventType::Sent, Some(remote_worker)) = (rec.event_type, rec.remote_worker) {
+                send_stash.insert((rec.local_worker, remote_worker, rec.correlator_id), rec.timestamp);
             }
         }
 
         // Match with receives
         for rec in &mut input_records {
-            if rec.event_type == EventType::Received {
-                let key = (rec.remote_worker.unwrap(), rec.local_worker, rec.correlator_id);
+            if let (EventType::Received, Some(remote_worker)) = (rec.event_type, rec.remote_worker) {
+                let key = (remote_worker, rec.local_worker, rec.correlator_id);
                 if let Some(timestamp) = send_stash.remove(&key) {
-                    let delta = if rec.timestamp > timestamp {
-                        rec.timestamp - timestamp
-                    } else {
-                        timestamp - rec.timestamp
-                    };
+                    message_delay.clip(timestamp, rec);
+                }
+            }
+        }
+    }
+
+    Ok(input_records)
+}
 
-                    if delta > Duration::from_nanos(message_delay) {
-                        let new_timestamp = timestamp + Duration::from_nanos(message_delay);
-                        rec.timestamp = new_timestamp;
+/// Like `read_sorted_trace_from_file_and_cut_messages`, but records are decoded lazily as the
+/// iterator is advanced instead of reading the whole trace into memory first.
+///
+/// Records are sorted by a reorder buffer holding the records of the last `reorder_window`:
+/// a record is handed out once a record at least `reorder_window` later has been read. With a
+/// zero window the trace must already be sorted by timestamp. Records that are still out of
+/// order are moved to the time of the last record handed out, and a warning is printed.
+/// Messages are only clipped if the send is read before the receive, and records are never
+/// sampled. Errors while reading end the records and are recorded in `status`.
+pub fn read_sorted_trace_from_file_iter(log_paths: &[String],
+                                        message_delay: Option<MessageDelay>,
+                                        corrupt_record_policy: CorruptRecordPolicy,
+                                        reorder_window: Duration,
+                                        status: ReadStatus)
+                                        -> io::Result<impl Iterator<Item = LogRecord>> {
+    let mut send_stash: HashMap<(Worker, Worker, Option<CorrelatorId>), Timestamp> = HashMap::new();
+    let records = MergedRecords::open(log_paths, corrupt_record_policy, status)?.map(move |mut rec| {
+        if let Some(ref message_delay) = message_delay {
+            // Broadcasts have no single receiver and are not clipped
+            match (rec.event_type, rec.remote_worker) {
+                (EventType::Sent, Some(remote_worker)) => {
+                    send_stash.insert((rec.local_worker, remote_worker, rec.correlator_id), rec.timestamp);
+                }
+                (EventType::Received, Some(remote_worker)) => {
+                    let key = (remote_worker, rec.local_worker, rec.correlator_id);
+                    if let Some(timestamp) = send_stash.remove(&key) {
+                        message_delay.clip(timestamp, &mut rec);
                     }
                 }
+                _ => (),
             }
         }
+        rec
+    });
+    Ok(ReorderBuffer::new(records, reorder_window))
+}
+
+/// Sorts `records` that are out of order by at most `window`. Records that are out of order by
+/// more than that are moved to the time of the last record handed out, with a warning.
+pub fn reorder<I: Iterator<Item = LogRecord>>(records: I, window: Duration) -> impl Iterator<Item = LogRecord> {
+    ReorderBuffer::new(records, window)
+}
+
+/// Sorts records that are out of order by at most `window`, see
+/// `read_sorted_trace_from_file_iter`.
+struct ReorderBuffer<I> {
+    inner: I,
+    window: Duration,
+    /// Records not handed out yet, sorted by timestamp
+    buffer: VecDeque<LogRecord>,
+    /// The latest timestamp read so far
+    newest: Duration,
+    last_emitted: Option<Duration>,
+    /// Number of records that were out of order by more than `window`
+    late: usize,
+}
+
+impl<I> ReorderBuffer<I> {
+    fn new(inner: I, window: Duration) -> Self {
+        ReorderBuffer {
+            inner,
+            window,
+            buffer: VecDeque::new(),
+            newest: Duration::new(0, 0),
+            last_emitted: None,
+            late: 0,
+        }
     }
 
-    // Timely requires that time increases monotonically
-    input_records.sort_by_key(|rec| rec.timestamp);
-    input_records
+    fn pop(&mut self) -> Option<LogRecord> {
+        let mut rec = self.buffer.pop_front()?;
+        match self.last_emitted {
+            Some(last_emitted) if rec.timestamp < last_emitted => {
+                self.late += 1;
+                rec.timestamp = last_emitted;
+            }
+            _ => self.last_emitted = Some(rec.timestamp),
+        }
+        Some(rec)
+    }
+}
+
+impl<I: Iterator<Item = LogRecord>> Iterator for ReorderBuffer<I> {
+    type Item = LogRecord;
+
+    fn next(&mut self) -> Option<LogRecord> {
+        loop {
+            let ready = self.buffer
+                .front()
+                .map_or(false, |front| self.newest - front.timestamp >= self.window);
+            if ready {
+                return self.pop();
+            }
+            match self.inner.next() {
+                Some(rec) => {
+                    if rec.timestamp > self.newest {
+                        self.newest = rec.timestamp;
+                    }
+                    let position = self.buffer
+                        .iter()
+                        .rposition(|buffered| buffered.timestamp <= rec.timestamp)
+                        .map_or(0, |position| position + 1);
+                    self.buffer.insert(position, rec);
+                }
+                None => return self.pop(),
+            }
+        }
+    }
+}
+
+impl<I> Drop for ReorderBuffer<I> {
+    fn drop(&mut self) {
+        if self.late > 0 {
+            eprintln!("WARNING {} records were out of order by more than the reorder window of {:?}",
+                      self.late,
+                      self.window);
+        }
+    }
+}
+
+/// The log path reading the trace from standard input.
+///
+/// Standard input can only be read once and not be seeked. It must not be gzip-compressed,
+/// corrupt records cannot be resynchronized (the rest of the input is skipped instead), and it
+/// can neither be read for `Config::threshold_percentile` nor be merged with itself. Like any
+/// trace, it is sorted in memory after reading, unless it is streamed (`Config::stream_input`,
+/// `Config::input_queue_capacity`): then its records must be sorted by timestamp, up to the
+/// reorder window.
+pub const STDIN_PATH: &str = "-";
+
+/// A trace file, decompressed on the fly if it is gzip-compressed.
+enum SegmentReader {
+    Plain(PositionedReader<BufReader<File>>),
+    Gzip(PositionedReader<BufReader<GzDecoder<File>>>),
+    Stdin(PositionedReader<BufReader<io::Stdin>>),
+}
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+impl SegmentReader {
+    /// Opens `path`, which is gzip-compressed if it ends in `.gz` or starts with the gzip magic
+    /// bytes.
+    fn open(path: &Path) -> io::Result<Self> {
+        if path == Path::new(STDIN_PATH) {
+            return Ok(SegmentReader::Stdin(PositionedReader {
+                inner: BufReader::with_capacity(1 << 22, io::stdin()),
+                position: 0,
+            }));
+        }
+        let mut file = File::open(path)?;
+        let mut magic = [0u8; 2];
+        let compressed = path.extension().map_or(false, |extension| extension == "gz") ||
+                         (file.read_exact(&mut magic).is_ok() && magic == GZIP_MAGIC);
+        file.seek(SeekFrom::Start(0))?;
+        Ok(if compressed {
+            SegmentReader::Gzip(PositionedReader {
+                inner: BufReader::with_capacity(1 << 22, GzDecoder::new(file)),
+                position: 0,
+            })
+        } else {
+            SegmentReader::Plain(PositionedReader {
+                inner: BufReader::with_capacity(1 << 22, file),
+                position: 0,
+            })
+        })
+    }
+
+    /// The number of (decompressed) bytes read so far.
+    fn position(&self) -> u64 {
+        match *self {
+            SegmentReader::Plain(ref reader) => reader.position,
+            SegmentReader::Gzip(ref reader) => reader.position,
+            SegmentReader::Stdin(ref reader) => reader.position,
+        }
+    }
+
+    fn read_record(&mut self) -> Result<LogRecord, LogReadError> {
+        match *self {
+            SegmentReader::Plain(ref mut reader) => LogRecord::read(reader),
+            SegmentReader::Gzip(ref mut reader) => LogRecord::read(reader),
+            SegmentReader::Stdin(ref mut reader) => LogRecord::read(reader),
+        }
+    }
+
+    /// See `resynchronize`. Compressed segments and standard input cannot be searched for the
+    /// next record, so their remainder is skipped.
+    fn resynchronize(&mut self, path: &Path, record_start: u64) -> io::Result<Option<LogRecord>> {
+        match *self {
+            SegmentReader::Plain(ref mut reader) => resynchronize(reader, record_start),
+            SegmentReader::Gzip(_) => {
+                eprintln!("WARNING cannot resynchronize in compressed trace {:?}, skipping the rest of it",
+                          path);
+                Ok(None)
+            }
+            SegmentReader::Stdin(_) => {
+                eprintln!("WARNING cannot resynchronize in standard input, skipping the rest of it");
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Decodes the records of the trace at `log_path` in file order.
+///
+/// If `log_path` is a directory, the trace is split into segment files (see `trace_segments`)
+/// which are read one after the other. A warning with the number of skipped corrupt records
+/// is printed when the iterator is dropped. Reading stops at the first error, which is
+/// recorded in the `ReadStatus`.
+pub struct TraceRecords {
+    log_path: String,
+    segments: ::std::vec::IntoIter<PathBuf>,
+    /// The segment being read
+    current: Option<(PathBuf, SegmentReader)>,
+    corrupt_record_policy: CorruptRecordPolicy,
+    skipped: usize,
+    status: ReadStatus,
+}
+
+impl TraceRecords {
+    /// Fails if the trace does not exist, or is a directory without segments.
+    pub fn open(log_path: &str,
+                corrupt_record_policy: CorruptRecordPolicy,
+                status: ReadStatus)
+                -> io::Result<Self> {
+        Ok(TraceRecords {
+            log_path: log_path.to_string(),
+            segments: trace_segments(log_path)?.into_iter(),
+            current: None,
+            corrupt_record_policy,
+            skipped: 0,
+            status,
+        })
+    }
+
+    fn fail(&mut self, error: TraceError) -> Option<LogRecord> {
+        self.status.fail(error);
+        self.segments = Vec::new().into_iter();
+        self.current = None;
+        None
+    }
+}
+
+impl Iterator for TraceRecords {
+    type Item = LogRecord;
+
+    fn next(&mut self) -> Option<LogRecord> {
+        loop {
+            if self.current.is_none() {
+                let path = self.segments.next()?;
+                match SegmentReader::open(&path) {
+                    Ok(reader) => self.current = Some((path, reader)),
+                    Err(why) => return self.fail(TraceError::Io(why)),
+                }
+            }
+            let rec = match self.current {
+                Some((ref path, ref mut reader)) => {
+                    let record_start = reader.position();
+                    match reader.read_record() {
+                        Ok(rec) => Ok(Some(rec)),
+                        Err(LogReadError::Eof) => Ok(None),
+                        Err(LogReadError::DecodeError(msg)) => {
+                            if self.corrupt_record_policy.allows_skip(self.skipped) {
+                                self.skipped += 1;
+                                eprintln!("skipping corrupt record at byte {} of {:?}: {:?}", record_start, path, msg);
+                                reader.resynchronize(path, record_start).map_err(TraceError::Io)
+                            } else {
+                                Err(TraceError::Decode(format!("could not decode record at byte {} of {:?} (policy {:?}, {} skipped): {:?}",
+                                                               record_start,
+                                                               path,
+                                                               self.corrupt_record_policy,
+                                                               self.skipped,
+                                                               msg)))
+                            }
+                        }
+                    }
+                }
+                None => unreachable!("a segment is open"),
+            };
+            match rec {
+                Ok(Some(rec)) => return Some(rec),
+                // Continue with the next segment
+                Ok(None) => self.current = None,
+                Err(error) => return self.fail(error),
+            }
+        }
+    }
+}
+
+impl Drop for TraceRecords {
+    fn drop(&mut self) {
+        if self.skipped > 0 {
+            eprintln!("WARNING skipped {} corrupt records in {}", self.skipped, self.log_path);
+        }
+    }
+}
+
+/// Merges the records of several traces, e.g. one written by each worker, by timestamp.
+///
+/// Every trace must be sorted by timestamp on its own. Records with the same timestamp are
+/// handed out by worker ID, and in file order within a trace. A single trace is read as is.
+pub struct MergedRecords {
+    traces: Vec<TraceRecords>,
+    /// The next record of every trace that has not ended, keyed by its position in the output
+    heads: BinaryHeap<Reverse<(Duration, Worker, usize)>>,
+    records: Vec<Option<LogRecord>>,
+}
+
+impl MergedRecords {
+    /// Fails if one of the traces does not exist, see `TraceRecords::open`.
+    pub fn open(log_paths: &[String],
+                corrupt_record_policy: CorruptRecordPolicy,
+                status: ReadStatus)
+                -> io::Result<Self> {
+        let traces = log_paths.iter()
+            .map(|log_path| TraceRecords::open(log_path, corrupt_record_policy, status.clone()))
+            .collect::<io::Result<Vec<_>>>()?;
+        let mut merged = MergedRecords {
+            records: traces.iter().map(|_| None).collect(),
+            traces,
+            heads: BinaryHeap::new(),
+        };
+        for index in 0..merged.traces.len() {
+            merged.advance(index);
+        }
+        Ok(merged)
+    }
+
+    /// Reads the next record of trace `index`.
+    fn advance(&mut self, index: usize) {
+        if let Some(rec) = self.traces[index].next() {
+            self.heads.push(Reverse((rec.timestamp, rec.local_worker, index)));
+            self.records[index] = Some(rec);
+        }
+    }
+}
+
+impl Iterator for MergedRecords {
+    type Item = LogRecord;
+
+    fn next(&mut self) -> Option<LogRecord> {
+        let Reverse((_, _, index)) = self.heads.pop()?;
+        let rec = self.records[index].take().expect("every head has a record");
+        self.advance(index);
+        Some(rec)
+    }
+}
+
+/// The files making up the trace at `log_path`: the file itself, or if `log_path` is a
+/// directory, the segment files in it as written by rotating loggers (`trace.000`,
+/// `trace.001`, ...), in the numeric order of their extensions. Compressed segments
+/// (`trace.000.gz`) are numbered by the extension before `.gz`. Files without a numeric
+/// extension are ignored and a warning is printed for every gap in the numbering.
+///
+/// `STDIN_PATH` is a single segment read from standard input.
+///
+/// Fails if the trace does not exist or a directory contains no segments.
+pub fn trace_segments(log_path: &str) -> io::Result<Vec<PathBuf>> {
+    let path = Path::new(log_path);
+    if log_path == STDIN_PATH || !fs::metadata(path)?.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let entries = fs::read_dir(path)?.collect::<io::Result<Vec<_>>>()?;
+    let mut segments: Vec<(u64, PathBuf)> = entries
+        .into_iter()
+        .filter_map(|entry| {
+            let segment = entry.path();
+            let uncompressed = match segment.extension() {
+                Some(extension) if extension == "gz" => PathBuf::from(segment.file_stem()?),
+                _ => segment.clone(),
+            };
+            let number = uncompressed.extension()
+                .and_then(|extension| extension.to_str())
+                .filter(|extension| extension.chars().all(|c| c.is_ascii_digit()))
+                .and_then(|extension| extension.parse().ok())?;
+            Some((number, segment))
+        })
+        .collect();
+    segments.sort();
+    if segments.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound,
+                                  format!("no trace segments (files with a numeric extension) in {:?}",
+                                          path)));
+    }
+    for pair in segments.windows(2) {
+        if pair[1].0 == pair[0].0 {
+            eprintln!("WARNING trace segments {:?} and {:?} have the same number", pair[0].1, pair[1].1);
+        } else if pair[1].0 != pair[0].0 + 1 {
+            eprintln!("WARNING trace segments {} to {} are missing in {:?}",
+                      pair[0].0 + 1,
+                      pair[1].0 - 1,
+                      path);
+        }
+    }
+    Ok(segments.into_iter().map(|(_, segment)| segment).collect())
+}
+
+/// Records read by a background thread through a queue of bounded capacity, see
+/// `read_trace_in_background`.
+pub struct BoundedRecords {
+    receiver: Receiver<LogRecord>,
+    depth: Arc<AtomicUsize>,
+}
+
+impl BoundedRecords {
+    /// The number of records read but not yet taken out of the queue.
+    pub fn depth(&self) -> usize {
+        self.depth.load(Ordering::SeqCst)
+    }
+
+    /// A handle to the queue depth that stays valid while the records are being consumed.
+    pub fn depth_handle(&self) -> Arc<AtomicUsize> {
+        self.depth.clone()
+    }
+}
+
+impl Iterator for BoundedRecords {
+    type Item = LogRecord;
+
+    fn next(&mut self) -> Option<LogRecord> {
+        let rec = self.receiver.recv().ok()?;
+        self.depth.fetch_sub(1, Ordering::SeqCst);
+        Some(rec)
+    }
+}
+
+/// Reads the trace at `log_path` on a background thread, buffering at most `capacity` records.
+///
+/// The reader blocks while the queue is full, so memory use does not grow if the dataflow falls
+/// behind. Records are handed out in file order without the sampling, message clipping and
+/// sorting of `read_sorted_trace_from_file_and_cut_messages`, so the trace must already be
+/// sorted by timestamp, also across the boundaries of trace segments. Several traces are
+/// merged by timestamp (see `MergedRecords`). Errors while reading end the records and are
+/// recorded in `status`.
+pub fn read_trace_in_background(log_paths: &[String],
+                                capacity: usize,
+                                corrupt_record_policy: CorruptRecordPolicy,
+                                status: ReadStatus)
+                                -> io::Result<BoundedRecords> {
+    let records = MergedRecords::open(log_paths, corrupt_record_policy, status)?;
+    let (sender, receiver) = sync_channel(capacity);
+    let depth = Arc::new(AtomicUsize::new(0));
+    let reader_depth = depth.clone();
+    thread::spawn(move || {
+        for rec in records {
+            reader_depth.fetch_add(1, Ordering::SeqCst);
+            // Stop reading once the dataflow is gone
+            if sender.send(rec).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(BoundedRecords { receiver, depth })
+}
+
+/// The address to listen on if `log_path` is of the form `tcp://host:port`.
+pub fn socket_address(log_path: &str) -> Option<&str> {
+    if log_path.starts_with("tcp://") {
+        Some(&log_path["tcp://".len()..])
+    } else {
+        None
+    }
+}
+
+/// Records received over TCP, see `read_trace_from_socket`.
+pub struct SocketRecords {
+    receiver: Receiver<LogRecord>,
+    idle_timeout: Duration,
+    started: bool,
+}
+
+impl Iterator for SocketRecords {
+    type Item = LogRecord;
+
+    fn next(&mut self) -> Option<LogRecord> {
+        // Wait as long as it takes for the traced computation to connect
+        if !self.started {
+            self.started = true;
+            return self.receiver.recv().ok();
+        }
+        self.receiver.recv_timeout(self.idle_timeout).ok()
+    }
+}
+
+/// Listens on `addr` and decodes the records sent over every incoming connection, encoded as
+/// in a trace file. Connections are read concurrently, so their records are sorted by a
+/// reorder buffer as in `read_sorted_trace_from_file_iter`; every connection must send its
+/// records in timestamp order.
+///
+/// The records end once none arrived for `idle_timeout` after the first one, which completes
+/// the last window. The records of a connection end at the first record that cannot be
+/// decoded, which is recorded in `status`. This expects `LogRecord`s; the raw `TimelyEvent`s
+/// logged with `TIMELY_WORKER_LOG_ADDR` have to be converted first, see `convert_timely_log`.
+pub fn read_trace_from_socket(addr: &str,
+                              idle_timeout: Duration,
+                              reorder_window: Duration,
+                              status: ReadStatus)
+                              -> io::Result<impl Iterator<Item = LogRecord>> {
+    let listener = TcpListener::bind(addr)?;
+    let (sender, receiver) = channel();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(why) => {
+                    eprintln!("WARNING couldn't accept trace connection: {:?}", why);
+                    continue;
+                }
+            };
+            let peer = stream.peer_addr().ok();
+            let sender = sender.clone();
+            let status = status.clone();
+            thread::spawn(move || {
+                let mut reader = BufReader::new(stream);
+                loop {
+                    match LogRecord::read(&mut reader) {
+                        Ok(rec) => {
+                            // Stop reading once the dataflow is gone
+                            if sender.send(rec).is_err() {
+                                break;
+                            }
+                        }
+                        Err(LogReadError::Eof) => break,
+                        Err(LogReadError::DecodeError(msg)) => {
+                            status.fail(TraceError::Decode(format!("could not decode record from {:?}: {:?}",
+                                                                   peer,
+                                                                   msg)));
+                            break;
+                        }
+                    }
+                }
+            });
+        }
+    });
+    let records = SocketRecords {
+        receiver,
+        idle_timeout,
+        started: false,
+    };
+    Ok(ReorderBuffer::new(records, reorder_window))
+}
+
+/// The events logged by timely with `TIMELY_WORKER_LOG_ADDR`.
+type TimelyLogEvent = Event<Duration, (Duration, usize, TimelyEvent)>;
+
+/// Tells `TimelyLogRecords` where the log ends, or why reading it failed, neither of which
+/// `EventReader` reports.
+struct EofReader<R> {
+    inner: R,
+    end: Rc<RefCell<Option<io::Result<()>>>>,
+}
+
+impl<R: Read> Read for EofReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.inner.read(buf) {
+            Ok(0) if !buf.is_empty() => {
+                *self.end.borrow_mut() = Some(Ok(()));
+                Ok(0)
+            }
+            Err(why) => {
+                let kind = why.kind();
+                // An interrupted read is retried by the next call
+                if kind != io::ErrorKind::Interrupted {
+                    *self.end.borrow_mut() = Some(Err(why));
+                }
+                Err(kind.into())
+            }
+            read => read,
+        }
+    }
+}
+
+/// Log records converted from a raw timely event log, see `convert_timely_log`.
+struct TimelyLogRecords<R: Read> {
+    events: EventReader<Duration, (Duration, usize, TimelyEvent), EofReader<R>>,
+    end: Rc<RefCell<Option<io::Result<()>>>>,
+    records: VecDeque<LogRecord>,
+    /// The number of events without a corresponding record, by variant
+    skipped: BTreeMap<String, u64>,
+}
+
+impl<R: Read> TimelyLogRecords<R> {
+    fn convert(&mut self, time: Duration, worker: usize, event: &TimelyEvent) -> Option<LogRecord> {
+        let record = |activity_type, event_type, remote_worker, correlator_id, operator_id| LogRecord {
+            timestamp: time,
+            local_worker: worker as Worker,
+            activity_type,
+            event_type,
+            correlator_id,
+            remote_worker,
+            operator_id,
+        };
+        let sent_or_received = |is_send| if is_send { EventType::Sent } else { EventType::Received };
+        match *event {
+            TimelyEvent::Schedule(ref event) => {
+                let event_type = if event.start_stop == StartStop::Start {
+                    EventType::Start
+                } else {
+                    EventType::End
+                };
+                Some(record(ActivityType::Scheduling, event_type, None, None, Some(event.id as u64)))
+            }
+            TimelyEvent::Messages(ref event) => {
+                let remote_worker = if event.is_send { event.target } else { event.source };
+                Some(record(ActivityType::DataMessage,
+                            sent_or_received(event.is_send),
+                            Some(remote_worker as Worker),
+                            Some(event.seq_no as CorrelatorId),
+                            None))
+            }
+            TimelyEvent::Progress(ref event) => {
+                // Outgoing progress messages are broadcasts, their receivers are unknown
+                let remote_worker = if event.is_send { None } else { Some(event.source as Worker) };
+                Some(record(ActivityType::ControlMessage,
+                            sent_or_received(event.is_send),
+                            remote_worker,
+                            Some(event.seq_no as CorrelatorId),
+                            None))
+            }
+            ref other => {
+                let name = format!("{:?}", other);
+                let name = name.split(|c: char| !c.is_alphanumeric()).next().unwrap_or_default();
+                *self.skipped.entry(name.to_string()).or_insert(0) += 1;
+                None
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for TimelyLogRecords<R> {
+    type Item = LogRecord;
+
+    fn next(&mut self) -> Option<LogRecord> {
+        while self.records.is_empty() {
+            let batch = match self.events.next() {
+                Some(&Event::Messages(_, ref batch)) => batch.clone(),
+                Some(&Event::Progress(_)) => continue,
+                // `EventReader` returns `None` after every read that did not complete an event,
+                // including failed ones
+                None if self.end.borrow().is_some() => return None,
+                None => continue,
+            };
+            for (time, worker, event) in batch {
+                if let Some(record) = self.convert(time, worker, &event) {
+                    self.records.push_back(record);
+                }
+            }
+        }
+        self.records.pop_front()
+    }
+}
+
+impl<R: Read> Drop for TimelyLogRecords<R> {
+    fn drop(&mut self) {
+        for (name, count) in &self.skipped {
+            eprintln!("WARNING skipped {} {} events without a log record", count, name);
+        }
+        if let Some(Err(ref why)) = *self.end.borrow() {
+            eprintln!("WARNING stopped reading the timely log: {}", why);
+        }
+    }
+}
+
+/// Reads a raw timely event log, as sent by timely to `TIMELY_WORKER_LOG_ADDR`, and converts
+/// its scheduling, data message and progress message events to log records like
+/// `timely-adapter`. Other events are skipped and counted in a warning once the log ends.
+///
+/// The records are in the order they were logged, which is by timestamp for the log of a
+/// single worker. The records end at the first error reading the log, with a warning.
+pub fn convert_timely_log<R: Read>(reader: R) -> impl Iterator<Item = LogRecord> {
+    let end = Rc::new(RefCell::new(None));
+    TimelyLogRecords {
+        events: EventReader::new(EofReader {
+            inner: reader,
+            end: end.clone(),
+        }),
+        end,
+        records: VecDeque::new(),
+        skipped: BTreeMap::new(),
+    }
+}
+
+/// Finds the next record boundary after a corrupt record starting at `record_start`.
+///
+/// The record format has no sync markers, so we try to decode a record at every following byte
+/// offset and take the first one that succeeds. Returns that record, or `None` if the trace ends
+/// (or no record could be decoded within `MAX_RESYNC_BYTES`), in which case reading stops. Fails
+/// if the trace cannot be searched.
+fn resynchronize<R: Read + Seek>(reader: &mut PositionedReader<R>,
+                                 record_start: u64)
+                                 -> io::Result<Option<LogRecord>> {
+    for offset in 1..=MAX_RESYNC_BYTES {
+        reader.seek_to(record_start + offset)?;
+        match LogRecord::read(reader) {
+            Ok(rec) => return Ok(Some(rec)),
+            Err(LogReadError::Eof) => return Ok(None),
+            Err(LogReadError::DecodeError(_)) => (),
+        }
+    }
+    eprintln!("WARNING no decodable record within {} bytes of byte {}, stopping",
+              MAX_RESYNC_BYTES,
+              record_start);
+    Ok(None)
+}
+
+/// Retain roughly one in `every` records for fast, approximate previews of large traces.
+///
+/// Records that belong together (the send and receive side of a message, or the start and end
+/// of an activity) are sampled by their common key, so pairs are either kept or dropped as a
+/// whole. Activities without a correlator are keyed by their worker and the ordinal of their
+/// start record on that worker, matching each end to the innermost open start. Any other record
+/// without a correlator is sampled by position. An `every` of 0 or 1 keeps all records.
+pub fn sample_records(records: Vec<LogRecord>, every: usize) -> Vec<LogRecord> {
+    sample(records.into_iter(), every).collect()
+}
+
+/// Samples `records` as they are read, like `sample_records`. The records of each worker must
+/// be in the order they were logged.
+pub fn sample<I: Iterator<Item = LogRecord>>(records: I, every: usize) -> impl Iterator<Item = LogRecord> {
+    let every = every.max(1) as u64;
+    let mut pair_ordinals: HashMap<Worker, usize> = HashMap::new();
+    let mut open_pairs: HashMap<Worker, Vec<usize>> = HashMap::new();
+    records
+        .enumerate()
+        .filter(move |&(index, ref rec)| {
+            let key = match (rec.activity_type, rec.correlator_id) {
+                (ActivityType::ControlMessage, Some(_)) |
+                (ActivityType::DataMessage, Some(_)) => {
+                    let sender_id = match rec.event_type {
+                        EventType::Received => rec.remote_worker.unwrap_or(rec.local_worker),
+                        _ => rec.local_worker,
+                    };
+                    default_hash(&(sender_id, rec.correlator_id))
+                }
+                (_, Some(_)) => default_hash(&(rec.local_worker, rec.correlator_id)),
+                (_, None) => {
+                    let worker = rec.local_worker;
+                    let ordinal = match rec.event_type {
+                        EventType::Start => {
+                            let next = pair_ordinals.entry(worker).or_insert(0);
+                            *next += 1;
+                            open_pairs.entry(worker).or_insert_with(Vec::new).push(*next);
+                            Some(*next)
+                        }
+                        EventType::End => open_pairs.get_mut(&worker).and_then(|open| open.pop()),
+                        _ => None,
+                    };
+                    match ordinal {
+                        Some(ordinal) => default_hash(&(worker, ordinal)),
+                        None => index as u64,
+                    }
+                }
+            };
+            key % every == 0
+        })
+        .map(|(_, rec)| rec)
+}
+
+/// The index of the first record that is earlier than the record before it, if any.
+pub fn first_unsorted_record(records: &[LogRecord]) -> Option<usize> {
+    records.windows(2)
+        .position(|pair| pair[1].timestamp < pair[0].timestamp)
+        .map(|index| index + 1)
 }
 
 /// Return the ids of the workers found in the trace, sorted.
@@ -93,6 +1138,27 @@ pub fn workers_in_trace(records: &[LogRecord]) -> Vec<Worker> {
     workers
 }
 
+/// Returns the `percentile`-th percentile (nearest rank, `0 < percentile <= 100`) of the gaps
+/// between consecutive records of the same worker, i.e. the durations the unknown edge
+/// threshold is compared against. Returns `None` if no worker has two records.
+pub fn gap_percentile(records: &[LogRecord], percentile: f64) -> Option<Duration> {
+    let mut timestamps = HashMap::new();
+    for record in records {
+        timestamps.entry(record.local_worker).or_insert_with(Vec::new).push(record.timestamp);
+    }
+    let mut gaps = Vec::new();
+    for (_, mut timestamps) in timestamps {
+        timestamps.sort();
+        gaps.extend(timestamps.windows(2).map(|pair| pair[1] - pair[0]));
+    }
+    if gaps.is_empty() {
+        return None;
+    }
+    gaps.sort();
+    let rank = (percentile / 100.0 * gaps.len() as f64).ceil() as usize;
+    Some(gaps[rank.max(1).min(gaps.len()) - 1])
+}
+
 fn default_hash<T>(obj: &T) -> u64
     where T: ::std::hash::Hash
 {
@@ -136,3 +1202,335 @@ pub fn infer_correlator_ids(_workers: &[u32], records: &mut [LogRecord]) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    use logformat::{ActivityType, EventType, LogRecord};
+
+    use super::{convert_timely_log, first_unsorted_record, gap_percentile, read_trace_from_file_and_cut_messages,
+                resynchronize, sample, sample_records, trace_segments, CorruptRecordPolicy, MergedRecords,
+                MessageDelay, MessageDelayMode, PositionedReader, ReadStatus, TraceRecords, STDIN_PATH};
+
+    fn write_trace<W: Write>(writer: &mut W, records: &[LogRecord]) {
+        for record in records {
+            record.write(writer).unwrap();
+        }
+    }
+
+    #[test]
+    fn gzipped_traces_decode_like_plain_ones() {
+        let records: Vec<_> = (0..20u64)
+            .map(|i| LogRecord {
+                     timestamp: Duration::from_nanos(i * 1_000),
+                     local_worker: i % 2,
+                     activity_type: ActivityType::Processing,
+                     event_type: if i % 2 == 0 { EventType::Start } else { EventType::End },
+                     correlator_id: None,
+                     remote_worker: None,
+                     operator_id: Some(i / 4),
+                 })
+            .collect();
+        let dir = std::env::temp_dir();
+        let plain = dir.join(format!("gzip_trace_{}.bin", std::process::id()));
+        // Without a `.gz` suffix, so that the compression is detected by its magic bytes
+        let compressed = dir.join(format!("gzip_trace_{}.bin.z", std::process::id()));
+        write_trace(&mut File::create(&plain).unwrap(), &records);
+        {
+            let mut encoder = GzEncoder::new(File::create(&compressed).unwrap(), Compression::default());
+            write_trace(&mut encoder, &records);
+            encoder.finish().unwrap();
+        }
+
+        let read = |path: &std::path::Path| {
+            TraceRecords::open(path.to_str().unwrap(), CorruptRecordPolicy::Abort, ReadStatus::default())
+                .unwrap()
+                .collect::<Vec<_>>()
+        };
+        let from_plain = read(&plain);
+        let from_compressed = read(&compressed);
+        fs::remove_file(&plain).unwrap();
+        fs::remove_file(&compressed).unwrap();
+        assert_eq!(from_plain, records);
+        assert_eq!(from_compressed, records);
+    }
+
+    #[test]
+    fn traces_are_merged_by_timestamp_and_worker() {
+        let record = |timestamp: u64, local_worker: u64| LogRecord {
+            timestamp: Duration::from_nanos(timestamp),
+            local_worker,
+            activity_type: ActivityType::Processing,
+            event_type: EventType::Start,
+            correlator_id: None,
+            remote_worker: None,
+            operator_id: None,
+        };
+        let dir = std::env::temp_dir();
+        let paths: Vec<_> = (0..2)
+            .map(|worker| dir.join(format!("merged_trace_{}_{}.bin", std::process::id(), worker)))
+            .collect();
+        write_trace(&mut File::create(&paths[0]).unwrap(), &[record(10, 1), record(30, 1)]);
+        write_trace(&mut File::create(&paths[1]).unwrap(), &[record(5, 0), record(10, 0), record(40, 0)]);
+
+        // The trace of worker 1 comes first, but worker 0 wins ties
+        let log_paths: Vec<_> = paths.iter().map(|path| path.to_str().unwrap().to_string()).collect();
+        let merged: Vec<_> = MergedRecords::open(&log_paths, CorruptRecordPolicy::Abort, ReadStatus::default())
+            .unwrap()
+            .map(|rec| (rec.timestamp.as_nanos() as u64, rec.local_worker))
+            .collect();
+        for path in &paths {
+            fs::remove_file(path).unwrap();
+        }
+        assert_eq!(merged, vec![(5, 0), (10, 0), (10, 1), (30, 1), (40, 0)]);
+    }
+
+    #[test]
+    fn first_unsorted_record_is_found() {
+        let records: Vec<_> = [10u64, 20, 20, 15, 30, 5]
+            .iter()
+            .map(|&timestamp| LogRecord {
+                     timestamp: Duration::from_nanos(timestamp),
+                     local_worker: 0,
+                     activity_type: ActivityType::Processing,
+                     event_type: EventType::Start,
+                     correlator_id: None,
+                     remote_worker: None,
+                     operator_id: None,
+                 })
+            .collect();
+        assert_eq!(first_unsorted_record(&records), Some(3));
+        assert_eq!(first_unsorted_record(&records[..3]), None);
+        assert_eq!(first_unsorted_record(&[]), None);
+    }
+
+    #[test]
+    fn sampled_activities_keep_their_start_and_end() {
+        // Each worker runs nested activities without correlators: an outer Scheduling around
+        // two inner Processing activities
+        let mut records = Vec::new();
+        for step in 0..50u64 {
+            for worker in 0..2 {
+                let events = [(ActivityType::Scheduling, EventType::Start),
+                              (ActivityType::Processing, EventType::Start),
+                              (ActivityType::Processing, EventType::End),
+                              (ActivityType::Processing, EventType::Start),
+                              (ActivityType::Processing, EventType::End),
+                              (ActivityType::Scheduling, EventType::End)];
+                for (offset, &(activity_type, event_type)) in events.iter().enumerate() {
+                    records.push(LogRecord {
+                        timestamp: Duration::from_nanos(step * 100 + offset as u64 * 10),
+                        local_worker: worker,
+                        activity_type,
+                        event_type,
+                        correlator_id: None,
+                        remote_worker: None,
+                        operator_id: None,
+                    });
+                }
+            }
+        }
+        let sampled = sample_records(records.clone(), 3);
+        assert!(!sampled.is_empty() && sampled.len() < records.len());
+        for worker in 0..2 {
+            let mut open = Vec::new();
+            for record in sampled.iter().filter(|rec| rec.local_worker == worker) {
+                match record.event_type {
+                    EventType::Start => open.push(record.activity_type),
+                    EventType::End => assert_eq!(open.pop(), Some(record.activity_type)),
+                    _ => unreachable!(),
+                }
+            }
+            assert!(open.is_empty(), "unmatched starts on worker {}: {:?}", worker, open);
+        }
+    }
+
+    #[test]
+    fn sampling_while_reading_matches_sampling_all_records() {
+        let records: Vec<_> = (0..100u64)
+            .map(|i| LogRecord {
+                     timestamp: Duration::from_nanos(i * 10),
+                     local_worker: i % 2,
+                     activity_type: ActivityType::Processing,
+                     event_type: if (i / 2) % 2 == 0 { EventType::Start } else { EventType::End },
+                     correlator_id: None,
+                     remote_worker: None,
+                     operator_id: None,
+                 })
+            .collect();
+        let sampled: Vec<_> = sample(records.clone().into_iter(), 4).collect();
+        assert_eq!(sampled, sample_records(records.clone(), 4));
+        assert_eq!(sample_records(records.clone(), 0), records);
+    }
+
+    #[test]
+    fn message_delays_apply_per_activity_type() {
+        let delay: MessageDelay = "DataMessage=1000, 50".parse().unwrap();
+        assert_eq!(delay.get(ActivityType::DataMessage), Some(1000));
+        assert_eq!(delay.get(ActivityType::ControlMessage), Some(50));
+        assert_eq!(delay.to_string(), "DataMessage=1000,50");
+        assert_eq!("70".parse::<MessageDelay>().unwrap(), MessageDelay::from(70));
+        assert!("1,2".parse::<MessageDelay>().is_err());
+        assert!("NoSuchActivity=1".parse::<MessageDelay>().is_err());
+    }
+
+    #[test]
+    fn clipped_messages_keep_their_original_timestamps() {
+        let received = || LogRecord {
+            timestamp: Duration::from_nanos(500),
+            local_worker: 1,
+            activity_type: ActivityType::DataMessage,
+            event_type: EventType::Received,
+            correlator_id: Some(7),
+            remote_worker: Some(0),
+            operator_id: None,
+        };
+        let shift = MessageDelay::from(100);
+        let mut rec = received();
+        shift.clip(Duration::from_nanos(200), &mut rec);
+        assert_eq!(rec.timestamp, Duration::from_nanos(300));
+
+        let annotate = MessageDelay {
+            mode: MessageDelayMode::Annotate,
+            ..MessageDelay::from(100)
+        };
+        let mut rec = received();
+        annotate.clip(Duration::from_nanos(200), &mut rec);
+        assert_eq!(rec.timestamp, Duration::from_nanos(500));
+
+        for delay in &[shift, annotate] {
+            let clipped = delay.clipped.take();
+            assert_eq!(clipped.len(), 1);
+            assert_eq!((clipped[0].sender, clipped[0].receiver), (0, 1));
+            assert_eq!(clipped[0].received, Duration::from_nanos(500));
+            assert_eq!(clipped[0].clipped, Duration::from_nanos(300));
+        }
+        let mut rec = received();
+        MessageDelay::from(1000).clip(Duration::from_nanos(200), &mut rec);
+        assert_eq!(rec.timestamp, Duration::from_nanos(500));
+    }
+
+    #[test]
+    fn broadcasts_are_not_clipped() {
+        let message = |timestamp: u64, local_worker, event_type, remote_worker| LogRecord {
+            timestamp: Duration::from_nanos(timestamp),
+            local_worker,
+            activity_type: ActivityType::ControlMessage,
+            event_type,
+            correlator_id: Some(3),
+            remote_worker,
+            operator_id: None,
+        };
+        // A progress broadcast as converted from a timely log, and its receive on worker 1
+        let records = [message(100, 0, EventType::Sent, None), message(900, 1, EventType::Received, Some(0))];
+        let path = std::env::temp_dir().join(format!("broadcast_trace_{}.bin", std::process::id()));
+        write_trace(&mut File::create(&path).unwrap(), &records);
+        let read = read_trace_from_file_and_cut_messages(&[path.to_str().unwrap().to_string()],
+                                                         Some(MessageDelay::from(100)),
+                                                         None,
+                                                         CorruptRecordPolicy::Abort)
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(read, records.to_vec());
+    }
+
+    #[test]
+    fn stdin_is_a_single_segment() {
+        assert_eq!(trace_segments(STDIN_PATH).unwrap(), vec![PathBuf::from("-")]);
+    }
+
+    #[test]
+    fn gap_percentiles_are_per_worker() {
+        let records: Vec<_> = [(0u64, 0u64), (5, 1), (10, 0), (25, 1), (40, 0), (45, 1)]
+            .iter()
+            .map(|&(timestamp, local_worker)| LogRecord {
+                     timestamp: Duration::from_nanos(timestamp),
+                     local_worker,
+                     activity_type: ActivityType::Processing,
+                     event_type: EventType::Start,
+                     correlator_id: None,
+                     remote_worker: None,
+                     operator_id: None,
+                 })
+            .collect();
+        // The gaps are 10 and 30 on worker 0, 20 and 20 on worker 1
+        assert_eq!(gap_percentile(&records, 50.0), Some(Duration::from_nanos(20)));
+        assert_eq!(gap_percentile(&records, 100.0), Some(Duration::from_nanos(30)));
+        assert_eq!(gap_percentile(&records, 1.0), Some(Duration::from_nanos(10)));
+        assert_eq!(gap_percentile(&records[..1], 50.0), None);
+    }
+
+    #[test]
+    fn timely_logs_are_converted_to_records() {
+        use timely::dataflow::operators::capture::{Event, EventPusher, EventWriter};
+        use timely::logging::{MessagesEvent, ScheduleEvent, TimelyEvent};
+
+        let path = std::env::temp_dir().join(format!("timely_log_{}.bin", std::process::id()));
+        {
+            let mut writer = EventWriter::new(File::create(&path).unwrap());
+            let at = Duration::from_nanos;
+            writer.push(Event::Messages(at(0), vec![
+                (at(10), 0, TimelyEvent::Schedule(ScheduleEvent::start(3))),
+                (at(20), 0, TimelyEvent::Text("ignored".to_string())),
+                (at(30), 0, TimelyEvent::Messages(MessagesEvent {
+                    is_send: true,
+                    channel: 1,
+                    source: 0,
+                    target: 1,
+                    seq_no: 7,
+                    length: 1,
+                })),
+            ]));
+            writer.push(Event::Progress(vec![(at(0), -1)]));
+            writer.push(Event::Messages(at(0), vec![(at(40), 0, TimelyEvent::Schedule(ScheduleEvent::stop(3)))]));
+        }
+        let records: Vec<_> = convert_timely_log(File::open(&path).unwrap())
+            .map(|rec| (rec.timestamp.as_nanos() as u64, rec.activity_type, rec.event_type, rec.remote_worker))
+            .collect();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(records,
+                   vec![(10, ActivityType::Scheduling, EventType::Start, None),
+                        (30, ActivityType::DataMessage, EventType::Sent, Some(1)),
+                        (40, ActivityType::Scheduling, EventType::End, None)]);
+    }
+
+    #[test]
+    fn timely_log_read_errors_end_the_records() {
+        struct FailingReader;
+
+        impl std::io::Read for FailingReader {
+            fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::new(std::io::ErrorKind::Other, "disk on fire"))
+            }
+        }
+
+        assert_eq!(convert_timely_log(FailingReader).count(), 0);
+    }
+
+    #[test]
+    fn seek_errors_while_resynchronizing_are_reported() {
+        struct UnseekableReader;
+
+        impl std::io::Read for UnseekableReader {
+            fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+                Ok(0)
+            }
+        }
+
+        impl std::io::Seek for UnseekableReader {
+            fn seek(&mut self, _pos: std::io::SeekFrom) -> std::io::Result<u64> {
+                Err(std::io::Error::new(std::io::ErrorKind::Other, "disk on fire"))
+            }
+        }
+
+        let mut reader = PositionedReader { inner: UnseekableReader, position: 0 };
+        assert!(resynchronize(&mut reader, 0).is_err());
+    }
+}
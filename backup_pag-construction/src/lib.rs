@@ -8,27 +8,38 @@
 
 #[macro_use]
 extern crate abomonation_derive;
+#[macro_use]
+extern crate json;
 
 use logformat::{LogRecord, ActivityType, EventType, Worker, OperatorId};
 
-use snailtrail::graph::{Partitioning, SrcDst};
+use snailtrail::graph::{Partitioning, SrcDst, Weight};
 use snailtrail::exploration::Capacity;
 
 use std::collections::HashMap;
 use std::hash::Hash;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use timely::ExchangeData;
 use timely::dataflow::channels::pact::{Exchange, Pipeline};
 use timely::dataflow::operators::aggregation::Aggregate;
 use timely::dataflow::operators::generic::operator::Operator;
-use timely::dataflow::operators::{Concat, Filter, Map, Partition};
+use timely::dataflow::operators::{Concat, Filter, Inspect, Map, Partition};
 use timely::dataflow::{Scope, Stream};
 use snailtrail::hash_code;
 
+pub mod anonymize;
+pub mod cbor;
 pub mod dataflow;
 pub mod input;
+#[cfg(feature = "metrics")]
+pub mod metrics;
 pub mod output;
+#[cfg(feature = "parquet-output")]
+pub mod parquet_summary;
+pub mod slack;
+pub mod sqlite;
 
 /// A node in the activity graph
 #[derive(Abomonation, Clone, Debug, PartialEq, Hash, Eq, Copy, Ord, PartialOrd)]
@@ -53,7 +64,7 @@ impl<'a> From<&'a LogRecord> for PagNode {
 }
 
 /// Elements of a complete activity graph, including ingress/egress points
-#[derive(Abomonation, Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(Abomonation, Clone, Debug, Eq, Hash, PartialEq, PartialOrd, Ord)]
 pub enum PagOutput {
     // Entry point into the graph
     StartNode(PagNode),
@@ -63,6 +74,12 @@ pub enum PagOutput {
     Edge(PagEdge),
 }
 
+impl Weight for PagOutput {
+    fn weight(&self) -> u64 {
+        PagOutput::weight(self)
+    }
+}
+
 impl SrcDst<PagNode> for PagOutput {
     fn src(&self) -> Option<PagNode> {
         match *self {
@@ -81,6 +98,77 @@ impl SrcDst<PagNode> for PagOutput {
     }
 }
 
+/// The address of an operator in a (possibly nested) dataflow.
+///
+/// An address is the path of operator IDs from the outermost scope down to the operator itself;
+/// e.g. `[2, 5]` is operator `5` nested in the region formed by operator `2`. The scalar ID is
+/// always the last component of the address (see `operator_id`). Grouping by the full address
+/// keeps operators apart that live in different scopes.
+///
+/// Log records only carry the scalar ID. Timely schedules the operators of a scope while the
+/// operator of the scope itself is scheduled, so the address of a worker-local edge is the path
+/// of the activities open around it on its worker timeline (see `nested_address`). Scopes whose
+/// activity started before the window are not seen, so the address of an operator is cut at the
+/// window start in that case.
+#[derive(Abomonation, Clone, Debug, PartialEq, Hash, Eq, PartialOrd, Ord)]
+pub struct OperatorAddress(pub Vec<OperatorId>);
+
+impl OperatorAddress {
+    /// Creates an address from its path of operator IDs. The path must not be empty.
+    pub fn new(path: Vec<OperatorId>) -> Self {
+        assert!(!path.is_empty(), "operator addresses must not be empty");
+        OperatorAddress(path)
+    }
+
+    /// The scalar operator ID, i.e. the last component of the address.
+    pub fn operator_id(&self) -> OperatorId {
+        *self.0.last().expect("operator addresses must not be empty")
+    }
+
+    /// The address of the scope containing this operator, or `None` for top-level operators.
+    pub fn scope(&self) -> Option<OperatorAddress> {
+        if self.0.len() > 1 {
+            Some(OperatorAddress(self.0[..self.0.len() - 1].to_vec()))
+        } else {
+            None
+        }
+    }
+}
+
+/// The address of `operator_id` when the activities on `stack` are open, outermost first.
+///
+/// Activities of the same operator are nested in each other as well (e.g. processing while the
+/// operator is scheduled), so repeated IDs are merged. The operator's own activity may be the top
+/// of `stack`.
+fn nested_address(stack: &[LogRecord], operator_id: Option<OperatorId>) -> Option<OperatorAddress> {
+    let operator_id = operator_id?;
+    let mut path: Vec<_> = stack.iter().filter_map(|record| record.operator_id).collect();
+    path.dedup();
+    if path.last() != Some(&operator_id) {
+        path.push(operator_id);
+    }
+    Some(OperatorAddress::new(path))
+}
+
+/// The address of the operator of a message edge. Messages are paired up apart from the worker
+/// timelines, so the scopes open around them are unknown and the address is the scalar ID.
+fn message_address(record: &LogRecord) -> Option<OperatorAddress> {
+    record.operator_id.map(OperatorAddress::from)
+}
+
+impl From<OperatorId> for OperatorAddress {
+    fn from(operator_id: OperatorId) -> Self {
+        OperatorAddress(vec![operator_id])
+    }
+}
+
+impl std::fmt::Display for OperatorAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let path: Vec<_> = self.0.iter().map(|id| id.to_string()).collect();
+        write!(f, "{}", path.join("."))
+    }
+}
+
 /// Information on how traverse an edge
 #[derive(Abomonation, Hash, Clone, Eq, Ord, PartialEq, PartialOrd, Debug)]
 pub enum TraversalType {
@@ -100,12 +188,22 @@ pub struct PagEdge {
     pub edge_type: ActivityType,
     /// An optional operator ID
     pub operator_id: Option<OperatorId>,
+    /// The full address of the operator, if known (see `OperatorAddress`)
+    pub operator_address: Option<OperatorAddress>,
+    /// Synthetic delay added for what-if analyses (see `delay_injection`), included in `weight`
+    pub injected_delay_ns: u64,
     /// Edge dependency information
     pub traverse: TraversalType,
 }
 
 impl PagEdge {
+    /// The weight used for analysis, i.e. the measured weight plus any injected delay.
     pub fn weight(&self) -> u64 {
+        self.measured_weight() + self.injected_delay_ns
+    }
+
+    /// The weight as measured in the trace, excluding injected delay.
+    pub fn measured_weight(&self) -> u64 {
         if self.destination.timestamp > self.source.timestamp {
             (self.destination.timestamp - self.source.timestamp).as_nanos() as u64
         } else {
@@ -116,6 +214,63 @@ impl PagEdge {
     pub fn is_message(&self) -> bool {
         self.source.worker_id != self.destination.worker_id
     }
+
+    /// The scope (region) containing the edge's operator, or `None` for the root scope.
+    ///
+    /// Edges without an operator address, e.g. messages, are attributed to the root scope.
+    pub fn scope(&self) -> Option<OperatorAddress> {
+        self.operator_address.as_ref().and_then(OperatorAddress::scope)
+    }
+}
+
+/// The name of `activity_type` in human-facing output: its entry in `labels`, which is keyed by
+/// the numeric activity type, or else `ActivityType::name`.
+pub fn activity_label(activity_type: ActivityType, labels: Option<&HashMap<u8, String>>) -> &str {
+    labels
+        .and_then(|labels| labels.get(&(activity_type as u8)))
+        .map_or(activity_type.name(), |label| &label[..])
+}
+
+/// Identifies "the same" edge across epochs.
+///
+/// Edges of different epochs never share timestamps, so features relating epochs to each other
+/// (e.g. global BC summaries) group edges by an identity instead. The default identity of an edge
+/// (`EdgeIdentity::of`) is its `(operator_id, edge_type, source_worker, destination_worker)`;
+/// with nested dataflows, operators with the same ID in different scopes can be kept apart
+/// with `EdgeIdentity::with_scope`. Custom identities are functions from edges to
+/// `EdgeIdentity` (see `EdgeIdentityFn`).
+#[derive(Abomonation, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct EdgeIdentity {
+    pub operator_id: Option<OperatorId>,
+    pub edge_type: ActivityType,
+    pub source_worker: Worker,
+    pub destination_worker: Worker,
+    /// The scope of the edge's operator, `None` unless the identity includes the scope
+    pub scope: Option<OperatorAddress>,
+}
+
+/// Computes the identity of an edge, see `EdgeIdentity`.
+pub type EdgeIdentityFn = std::sync::Arc<dyn Fn(&PagEdge) -> EdgeIdentity + Send + Sync>;
+
+impl EdgeIdentity {
+    /// The default identity, ignoring scopes.
+    pub fn of(edge: &PagEdge) -> Self {
+        EdgeIdentity {
+            operator_id: edge.operator_address.as_ref().map(OperatorAddress::operator_id),
+            edge_type: edge.edge_type,
+            source_worker: edge.source.worker_id,
+            destination_worker: edge.destination.worker_id,
+            scope: None,
+        }
+    }
+
+    /// The default identity, additionally distinguishing the scope of the edge's operator.
+    pub fn with_scope(edge: &PagEdge) -> Self {
+        EdgeIdentity {
+            scope: edge.scope(),
+            ..EdgeIdentity::of(edge)
+        }
+    }
 }
 
 impl PagOutput {
@@ -184,6 +339,25 @@ impl<V: From<u8>> Capacity<PagOutput, V> for TraverseNoWaiting {
     }
 }
 
+/// Traverses waiting edges like any other edge, so that centrality propagates through blocked
+/// time as well.
+pub struct TraverseIncludingWaiting;
+impl<V: From<u8>> Capacity<PagOutput, V> for TraverseIncludingWaiting {
+    fn apply_capacity(edge: &PagOutput, value: V) -> V {
+        match *edge {
+            PagOutput::StartNode(_) |
+            PagOutput::EndNode(_) => From::from(0),
+            PagOutput::Edge(ref e) => {
+                match e.traverse {
+                    TraversalType::Undefined => panic!("Undefined traversal capacity!"),
+                    TraversalType::Block |
+                    TraversalType::Unbounded => value,
+                }
+            }
+        }
+    }
+}
+
 pub struct TraverseWaitingOne;
 impl<V: From<u8>> Capacity<PagOutput, V> for TraverseWaitingOne {
     fn apply_capacity(edge: &PagOutput, value: V) -> V {
@@ -273,18 +447,57 @@ impl<S: Scope, D: ExchangeData> MapEpoch<S, D> for Stream<S, D>
     }
 }
 
+/// The event time intervals of windows that are not derived from a window size, e.g. the
+/// logical epochs of `dataflow::build_dataflow_pair`. The intervals are shared by the workers
+/// of a process and have to be inserted before the window completes.
+#[derive(Clone, Default)]
+pub struct LogicalWindows(Arc<Mutex<HashMap<Duration, (Duration, Duration)>>>);
+
+impl LogicalWindows {
+    /// Records that the window `epoch` covers the event times `[start, end)`, widening the
+    /// interval inserted for it before, e.g. by another worker.
+    pub fn insert(&self, epoch: Duration, start: Duration, end: Duration) {
+        let mut windows = self.0.lock().expect("logical windows poisoned");
+        let bounds = windows.entry(epoch).or_insert((start, end));
+        *bounds = (std::cmp::min(bounds.0, start), std::cmp::max(bounds.1, end));
+    }
+
+    fn get(&self, epoch: Duration) -> Option<(Duration, Duration)> {
+        self.0.lock().expect("logical windows poisoned").get(&epoch).cloned()
+    }
+}
+
+/// Returns the event time interval `[start, end)` covered by the window `epoch`. Windows start
+/// every `window_stride_ns` nanoseconds and overlap if the stride is smaller than their size,
+/// unless they are `logical_windows`.
+pub fn window_bounds(epoch: Duration,
+                     window_size_ns: u64,
+                     window_stride_ns: u64,
+                     logical_windows: &Option<LogicalWindows>)
+                     -> (Duration, Duration) {
+    if let Some(logical_windows) = logical_windows {
+        return logical_windows.get(epoch)
+            .unwrap_or_else(|| panic!("bounds of logical window {:?} are unknown", epoch));
+    }
+    let start = Duration::from_nanos(epoch.as_nanos() as u64 * window_stride_ns);
+    (start, start + Duration::from_nanos(window_size_ns))
+}
+
 fn create_initial_pag_edges(worker_id: Worker,
                             mut timeline: Vec<LogRecord>,
-                            window_size_ns: u32,
+                            window_size_ns: u64,
+                            window_stride_ns: u64,
+                            logical_windows: &Option<LogicalWindows>,
                             window_start_time: Duration)
                             -> Vec<Timeline> {
+    let (window_start, window_end) = window_bounds(window_start_time, window_size_ns, window_stride_ns, logical_windows);
     // We insert two records just before and after the window boundaries.
     // This will cause the analysis later on to include potential gaps between
     // the window boundary and first/last activity in the wait state analysis
     // to insert Unknown/Waiting activities accordingly.
     timeline.push(LogRecord {
         // @TODO: for first this crashes
-        timestamp: window_start_time * window_size_ns, // - Duration::new(0, 1),
+        timestamp: window_start, // - Duration::new(0, 1),
         local_worker: worker_id,
         activity_type: ActivityType::Unknown,
         event_type: EventType::Bogus,
@@ -294,7 +507,7 @@ fn create_initial_pag_edges(worker_id: Worker,
         channel_id: None,
     });
     timeline.push(LogRecord {
-        timestamp: window_start_time * window_size_ns + Duration::new(0, window_size_ns),
+        timestamp: window_end,
         local_worker: worker_id,
         activity_type: ActivityType::Unknown,
         event_type: EventType::Bogus,
@@ -344,6 +557,8 @@ fn create_initial_pag_edges(worker_id: Worker,
                                                               PagNode::from(&record.clone()),
                                                           edge_type: top.activity_type,
                                                           operator_id: top.operator_id,
+                                                          operator_address: nested_address(&stack, top.operator_id),
+                                                          injected_delay_ns: 0,
                                                           traverse: TraversalType::Undefined,
                                                       }));
                     //assert_eq!(prev.activity_type, record.activity_type, "mismatch activity type: prev={:?}, record={:?}", prev, record);
@@ -382,6 +597,9 @@ fn create_initial_pag_edges(worker_id: Worker,
                                                                           record.activity_type,
                                                                       operator_id:
                                                                           record.operator_id,
+                                                                      operator_address:
+                                                                          nested_address(&stack, record.operator_id),
+                                                                      injected_delay_ns: 0,
                                                                       traverse:
                                                                           TraversalType::Undefined,
                                                                   }));
@@ -396,7 +614,7 @@ fn create_initial_pag_edges(worker_id: Worker,
                     // Terminate the activity and set start to last emitted time
                     let prev = if last_end.is_none() {
                         LogRecord {
-                            timestamp: window_start_time * window_size_ns,
+                            timestamp: window_start,
                             ..record
                         }
                     } else {
@@ -407,6 +625,8 @@ fn create_initial_pag_edges(worker_id: Worker,
                                                           destination: PagNode::from(&record),
                                                           edge_type: record.activity_type,
                                                           operator_id: record.operator_id,
+                                                          operator_address: nested_address(&stack, record.operator_id),
+                                                          injected_delay_ns: 0,
                                                           traverse: TraversalType::Undefined,
                                                       }));
                     last_end = Some(record);
@@ -425,6 +645,8 @@ fn create_initial_pag_edges(worker_id: Worker,
                                                           destination: PagNode::from(&record),
                                                           edge_type: top.activity_type,
                                                           operator_id: top.operator_id,
+                                                          operator_address: nested_address(&stack, top.operator_id),
+                                                          injected_delay_ns: 0,
                                                           traverse: TraversalType::Undefined,
                                                       }));
                 }
@@ -442,7 +664,7 @@ fn create_initial_pag_edges(worker_id: Worker,
         let prev = last_end.as_ref().unwrap_or(top);
 
         let record = LogRecord {
-            timestamp: window_start_time * window_size_ns + Duration::new(0, window_size_ns),
+            timestamp: window_end,
             ..*top
         };
         if prev.timestamp < record.timestamp {
@@ -453,6 +675,8 @@ fn create_initial_pag_edges(worker_id: Worker,
                                                   destination: PagNode::from(&record.clone()),
                                                   edge_type: top.activity_type,
                                                   operator_id: top.operator_id,
+                                                  operator_address: nested_address(&stack, top.operator_id),
+                                                  injected_delay_ns: 0,
                                                   traverse: TraversalType::Undefined,
                                               }));
         }
@@ -593,6 +817,8 @@ fn connect_pag_and_apply_wait_analysis(new_timeline: Vec<Timeline>,
                                     },
                                     edge_type: edge_type,
                                     operator_id: None,
+                                    operator_address: None,
+                                    injected_delay_ns: 0,
                                     traverse: TraversalType::Undefined,
                                 });
         }
@@ -629,7 +855,9 @@ trait WorkerTimelines<S: Scope> {
     // reasonable starting value.
     fn build_worker_timelines(&self,
                               unknown_threshold: Duration,
-                              window_size_ns: u32,
+                              window_size_ns: u64,
+                              window_stride_ns: u64,
+                              logical_windows: Option<LogicalWindows>,
                               insert_waitig_edges: bool)
                               -> Stream<S, PagOutput>;
 }
@@ -637,7 +865,9 @@ trait WorkerTimelines<S: Scope> {
 impl<S: Scope<Timestamp = Duration>> WorkerTimelines<S> for Stream<S, LogRecord> {
     fn build_worker_timelines(&self,
                               unknown_threshold: Duration,
-                              window_size_ns: u32,
+                              window_size_ns: u64,
+                              window_stride_ns: u64,
+                              logical_windows: Option<LogicalWindows>,
                               insert_waitig_edges: bool)
                               -> Stream<S, PagOutput> {
         let mut timelines_per_epoch = HashMap::new();
@@ -664,7 +894,7 @@ impl<S: Scope<Timestamp = Duration>> WorkerTimelines<S> for Stream<S, LogRecord>
                         // (quantization) to eliminate gaps and merge log records which are in
                         // close proximity in terms of event time.
 
-                        let initial_timeline = create_initial_pag_edges(worker_id, raw_timeline, window_size_ns, *time.time());
+                        let initial_timeline = create_initial_pag_edges(worker_id, raw_timeline, window_size_ns, window_stride_ns, &logical_windows, *time.time());
 
                         let final_timeline = connect_pag_and_apply_wait_analysis(initial_timeline, unknown_threshold, insert_waitig_edges);
 
@@ -690,26 +920,30 @@ trait PairUpEvents<S: Scope> {
     fn pair_up_events(&self,
                       start_type: EventType,
                       end_type: EventType,
-                      window_size_ns: u32)
+                      window_size_ns: u64,
+                      window_stride_ns: u64,
+                      logical_windows: Option<LogicalWindows>)
                       -> Stream<S, Timeline>;
 
     fn pair_up_events_and_check<F>(&self,
                                    start_type: EventType,
                                    end_type: EventType,
-                                   window_size_ns: u32,
+                                   window_size_ns: u64,
+                                   window_stride_ns: u64,
+                                   logical_windows: Option<LogicalWindows>,
                                    assert_fn: F)
                                    -> Stream<S, Timeline>
         where F: Fn(&LogRecord, &LogRecord) -> () + 'static;
 }
 
 impl<S: Scope<Timestamp = Duration>, K: ExchangeData+Eq+Hash> PairUpEvents<S> for Stream<S, (K, LogRecord)> {
-    fn pair_up_events(&self, start_type: EventType, end_type: EventType, window_size_ns: u32) -> Stream<S, Timeline> {
-self.pair_up_events_and_check(start_type, end_type, window_size_ns, |_sent, _recv| {
+    fn pair_up_events(&self, start_type: EventType, end_type: EventType, window_size_ns: u64, window_stride_ns: u64, logical_windows: Option<LogicalWindows>) -> Stream<S, Timeline> {
+self.pair_up_events_and_check(start_type, end_type, window_size_ns, window_stride_ns, logical_windows, |_sent, _recv| {
 /* no assertion */
 })
     }
 
-    fn pair_up_events_and_check<F>(&self, start_type: EventType, end_type: EventType, window_size_ns: u32, assert_fn: F) -> Stream<S, Timeline>
+    fn pair_up_events_and_check<F>(&self, start_type: EventType, end_type: EventType, window_size_ns: u64, window_stride_ns: u64, logical_windows: Option<LogicalWindows>, assert_fn: F) -> Stream<S, Timeline>
         where F: Fn(&LogRecord, &LogRecord) -> () + 'static
     {
         let paired = self.aggregate::<_, (Option<LogRecord>, Vec<LogRecord>), _, _, _>(
@@ -730,6 +964,10 @@ self.pair_up_events_and_check(start_type, end_type, window_size_ns, |_sent, _rec
         let mut vector = Vec::new();
         paired.unary_notify(Pipeline, "assemble messages", vec!(), move |input, output, _| {
             input.for_each(|time, data| {    // emit
+                let (window_start, window_end) = window_bounds(*time.time(), window_size_ns, window_stride_ns, &logical_windows);
+                // Messages received without a send in this window are sent just before it. The
+                // first window of a trace may start at 0, there they are sent at its start.
+                let before_window = window_start.checked_sub(Duration::new(0, 1)).unwrap_or(window_start);
                 let mut session = output.session(&time);
                 data.swap(&mut vector);
                 for (_, agg) in vector.drain(..) {
@@ -744,15 +982,17 @@ self.pair_up_events_and_check(start_type, end_type, window_size_ns, |_sent, _rec
                                         worker_id: start.local_worker,
                                     },
                                     destination: PagNode {
-                                        timestamp: *time.time() * window_size_ns + Duration::new(0, window_size_ns),
+                                        timestamp: window_end,
                                         worker_id: start.remote_worker.expect("comm w/o remote worker"),
                                     },
                                     edge_type: start.activity_type,
                                     operator_id: start.operator_id,
+                                    operator_address: message_address(&start),
+                                    injected_delay_ns: 0,
                                     traverse: TraversalType::Undefined,
                                 }));
                                 session.give(Timeline::Remote(LogRecord {
-                                    timestamp: *time.time() * window_size_ns  + Duration::new(0, window_size_ns),
+                                    timestamp: window_end,
                                     local_worker: start.remote_worker.unwrap(),
                                     remote_worker: Some(start.local_worker),
                                     ..start
@@ -773,6 +1013,8 @@ self.pair_up_events_and_check(start_type, end_type, window_size_ns, |_sent, _rec
                                         },
                                         edge_type: start.activity_type,
                                         operator_id: start.operator_id,
+                                        operator_address: message_address(&start),
+                                        injected_delay_ns: 0,
                                         traverse: TraversalType::Undefined,
                                     }));
                                 }
@@ -791,7 +1033,7 @@ self.pair_up_events_and_check(start_type, end_type, window_size_ns, |_sent, _rec
                             for end in ends {
                                 session.give(Timeline::Local(PagEdge {
                                     source: PagNode {
-                                        timestamp: *time.time() * window_size_ns - Duration::new(0, 1),
+                                        timestamp: before_window,
                                         worker_id: end.remote_worker.expect("comm w/o remote worker"),
                                     },
                                     destination: PagNode {
@@ -800,10 +1042,12 @@ self.pair_up_events_and_check(start_type, end_type, window_size_ns, |_sent, _rec
                                     },
                                     edge_type: end.activity_type,
                                     operator_id: end.operator_id,
+                                    operator_address: message_address(&end),
+                                    injected_delay_ns: 0,
                                     traverse: TraversalType::Undefined,
                                 }));
                                 session.give(Timeline::Remote(LogRecord {
-                                    timestamp: *time.time() * window_size_ns  - Duration::new(0, 1),
+                                    timestamp: before_window,
                                     local_worker: end.remote_worker.unwrap(),
                                     remote_worker: Some(end.local_worker),
                                     ..end
@@ -818,29 +1062,48 @@ self.pair_up_events_and_check(start_type, end_type, window_size_ns, |_sent, _rec
 }
 
 
+/// The parameters of `build_program_activity_graph`.
+#[derive(Clone, Default)]
+pub struct PagOptions {
+    /// Gaps between the activities of a worker shorter than this are unknown, longer ones waiting
+    pub threshold: Duration,
+    /// Messages taking longer than this many ns are waiting edges, 0 disables this
+    pub delayed_message_threshold: u64,
+    pub window_size_ns: u64,
+    /// The distance between the starts of consecutive windows, see `window_bounds`
+    pub window_stride_ns: u64,
+    /// The event time intervals of the epochs if they are logical epochs instead of windows
+    pub logical_windows: Option<LogicalWindows>,
+    pub insert_waiting_edges: bool,
+    /// Delay in ns added to the weight of edges of each activity type
+    pub delay_injection: HashMap<ActivityType, u64>,
+    /// Remove self-loops and zero-duration edges, printing how many per epoch
+    pub remove_degenerate_edges: bool,
+}
+
 /// Main entry point which converts a stream of events produced from instrumentation into a Program
 /// Activity Graph (PAG).  This method expects log records which are batched into disjoint windows
 /// of event time (epoch) and the output will contain a time-ordered stream of edges which include
 /// both ends of an activity (e.g. start/end or send/receive pairs).
 
 pub trait BuildProgramActivityGraph<S: Scope> {
-    fn build_program_activity_graph(&self,
-                                    threshold: Duration,
-                                    delayed_message_threshold: u64,
-                                    window_size_ns: u32,
-                                    insert_waitig_edges: bool)
-                                    -> Stream<S, PagOutput>;
+    fn build_program_activity_graph(&self, options: PagOptions) -> Stream<S, PagOutput>;
 }
 
 impl<S> BuildProgramActivityGraph<S> for Stream<S, LogRecord>
      where S: Scope<Timestamp = Duration>
 {
-    fn build_program_activity_graph(&self,
-                                    threshold: Duration,
-                                    delayed_message_threshold: u64,
-                                    window_size_ns: u32,
-                                    insert_waitig_edges: bool)
-                                    -> Stream<S, PagOutput> {
+    fn build_program_activity_graph(&self, options: PagOptions) -> Stream<S, PagOutput> {
+        let PagOptions {
+            threshold,
+            delayed_message_threshold,
+            window_size_ns,
+            window_stride_ns,
+            logical_windows,
+            insert_waiting_edges,
+            delay_injection,
+            remove_degenerate_edges,
+        } = options;
         let input = self;
         // Check worker timelines for completeness
 
@@ -861,7 +1124,7 @@ impl<S> BuildProgramActivityGraph<S> for Stream<S, LogRecord>
             })
         // This matching logic largely duplicates the aggregation operator above but is
         // parameterized slightly differently -> make this into a reusable operator.
-        .pair_up_events_and_check(EventType::Sent, EventType::Received, window_size_ns, |sent, recv| {
+        .pair_up_events_and_check(EventType::Sent, EventType::Received, window_size_ns, window_stride_ns, logical_windows.clone(), |sent, recv| {
             assert!((sent.local_worker == recv.remote_worker.unwrap()) &&
                     (sent.remote_worker.is_none() || sent.remote_worker.unwrap() == recv.local_worker));
         });
@@ -891,7 +1154,9 @@ impl<S> BuildProgramActivityGraph<S> for Stream<S, LogRecord>
         let worker_timelines = worker_timeline_input
             .build_worker_timelines(threshold,
                                     window_size_ns,
-                                    insert_waitig_edges)
+                                    window_stride_ns,
+                                    logical_windows,
+                                    insert_waiting_edges)
             .filter(|pag| if let PagOutput::Edge(ref e) = *pag {
                         e.source.worker_id != e.destination.worker_id ||
                         e.source.timestamp < e.destination.timestamp
@@ -902,10 +1167,38 @@ impl<S> BuildProgramActivityGraph<S> for Stream<S, LogRecord>
         // Step 3: merge the contents of both streams and sort according to event time
         let result = worker_timelines.concat(&communication_edges);
 
+        // Optionally remove self-loops and zero-duration edges. Worker-local zero-duration edges
+        // are already gone, so these are messages received at the time they were sent. Removal
+        // does not reconnect their endpoints: paths crossing workers over such a message are
+        // split, and the receiving side is then only reachable along its own timeline. As the
+        // edges have no weight, the weight of all remaining paths is unchanged but fewer paths
+        // are counted for BC.
+        let result = if remove_degenerate_edges {
+            let partitions = result.partition(2, |e| match e {
+                PagOutput::Edge(ref edge) if edge.source == edge.destination ||
+                                             edge.measured_weight() == 0 => (1, e),
+                _ => (0, e),
+            });
+            // Counted on worker 0, epochs without degenerate edges are not printed
+            partitions[1]
+                .map(|_| ((), 1u64))
+                .aggregate::<_, u64, _, _, _>(|_key, val, agg| *agg += val,
+                                             |_key, agg| agg,
+                                             |_key| 0)
+                .inspect_batch(|ts, counts| {
+                    for count in counts {
+                        println!("DEGENERATE_EDGES {:?},{}", ts, count);
+                    }
+                });
+            partitions[0].clone()
+        } else {
+            result
+        };
+
         let exchange = Exchange::new(|e: &PagOutput| u64::from(e.destination_worker()));
         let mut timelines_per_epoch = HashMap::new();
         let mut vector = Vec::new();
-        result.unary_notify(exchange, "add traversal info", vec![], move |input, output, notificator| {
+        let pag = result.unary_notify(exchange, "add traversal info", vec![], move |input, output, notificator| {
             // Organize all data by time and then according to worker ID
             input.for_each(|time, data| {
                 let epoch_slot = timelines_per_epoch.entry(*time.time())
@@ -951,6 +1244,64 @@ impl<S> BuildProgramActivityGraph<S> for Stream<S, LogRecord>
                     }
                 }
             });
-        })
+        });
+
+        // Step 4: model a slower system by adding synthetic delay to selected activity types
+        if delay_injection.is_empty() {
+            pag
+        } else {
+            pag.map(move |mut output| {
+                if let PagOutput::Edge(ref mut edge) = output {
+                    if let Some(delay) = delay_injection.get(&edge.edge_type) {
+                        edge.injected_delay_ns += delay;
+                    }
+                }
+                output
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use logformat::{ActivityType, EventType, LogRecord};
+
+    use super::{nested_address, OperatorAddress};
+
+    fn scheduled(operator_id: u64) -> LogRecord {
+        LogRecord {
+            timestamp: Duration::from_nanos(0),
+            local_worker: 0,
+            activity_type: ActivityType::Scheduling,
+            event_type: EventType::Start,
+            correlator_id: None,
+            remote_worker: None,
+            operator_id: Some(operator_id),
+        }
+    }
+
+    #[test]
+    fn operators_with_the_same_id_in_different_scopes_stay_apart() {
+        // Operator 5 scheduled in the scope of operator 2 and then in the scope of operator 3
+        let in_two = nested_address(&[scheduled(2), scheduled(5)], Some(5));
+        let in_three = nested_address(&[scheduled(3)], Some(5));
+        assert_eq!(in_two, Some(OperatorAddress::new(vec![2, 5])));
+        assert_eq!(in_three, Some(OperatorAddress::new(vec![3, 5])));
+        assert_eq!(in_two.map(|address| address.operator_id()), Some(5));
+        // Nested activities of the same operator do not nest the operator in itself
+        assert_eq!(nested_address(&[scheduled(2), scheduled(2)], Some(2)),
+                   Some(OperatorAddress::new(vec![2])));
+        assert_eq!(nested_address(&[scheduled(2)], None), None);
+    }
+
+    #[test]
+    fn nested_operators_are_in_the_scope_of_their_enclosing_operator() {
+        let nested = nested_address(&[scheduled(2), scheduled(5)], Some(5)).unwrap();
+        assert_eq!(nested.scope(), Some(OperatorAddress::new(vec![2])));
+        // The enclosing operator is in the root scope
+        let enclosing = nested_address(&[scheduled(2)], Some(2)).unwrap();
+        assert_eq!(enclosing.scope(), None);
     }
 }
@@ -0,0 +1,82 @@
+// Copyright 2017 ETH Zurich. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Exposes the per-epoch counts of the analysis as Prometheus metrics (feature `metrics`).
+//!
+//! The gauges are updated next to the corresponding `COUNT` and `SUMMARY` lines and served in
+//! the Prometheus text format by `serve`. Epochs are formatted as in the other outputs.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use prometheus::{register_gauge, register_gauge_vec, register_int_gauge_vec};
+use prometheus::{Encoder, Gauge, GaugeVec, IntGaugeVec, TextEncoder};
+
+use logformat::ActivityType;
+
+lazy_static! {
+    static ref PAG_EDGES: IntGaugeVec =
+        register_int_gauge_vec!("snailtrail_pag_edges", "Number of PAG elements per epoch", &["epoch"])
+            .expect("the metric is registered once");
+    static ref BC_TOTAL: Gauge =
+        register_gauge!("snailtrail_bc_total", "Number of paths through the PAG of the latest epoch")
+            .expect("the metric is registered once");
+    static ref ACTIVITY_PATHS: GaugeVec =
+        register_gauge_vec!("snailtrail_activity_paths",
+                            "Number of paths through the edges of an activity type per epoch",
+                            &["epoch", "activity_type"])
+            .expect("the metric is registered once");
+}
+
+/// Sets the number of PAG elements of `epoch`.
+pub fn set_pag_edges(epoch: &Duration, count: usize) {
+    PAG_EDGES.with_label_values(&[&format!("{:?}", epoch)]).set(count as i64);
+}
+
+/// Sets the number of paths of the latest complete epoch.
+pub fn set_bc_total(paths: u64) {
+    BC_TOTAL.set(paths as f64);
+}
+
+/// Adds the BC of a summary of `activity_type` edges to the path count of `epoch`.
+pub fn add_activity_paths(epoch: &Duration, activity_type: ActivityType, bc: f64) {
+    ACTIVITY_PATHS.with_label_values(&[&format!("{:?}", epoch), activity_type.name()]).add(bc);
+}
+
+fn respond(stream: TcpStream) -> io::Result<()> {
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let mut stream = stream;
+    if !request_line.starts_with("GET /metrics") {
+        return write!(stream, "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+    }
+    let encoder = TextEncoder::new();
+    let mut body = Vec::new();
+    encoder.encode(&prometheus::gather(), &mut body)
+        .map_err(|why| io::Error::new(io::ErrorKind::Other, why))?;
+    write!(stream,
+           "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+           encoder.format_type(),
+           body.len())?;
+    stream.write_all(&body)
+}
+
+/// Serves `GET /metrics` on `addr` from a background thread for the rest of the process.
+pub fn serve(addr: &str) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    thread::spawn(move || for stream in listener.incoming() {
+        let result = stream.and_then(respond);
+        if let Err(why) = result {
+            eprintln!("WARNING couldn't answer metrics request: {:?}", why);
+        }
+    });
+    Ok(())
+}
@@ -7,28 +7,172 @@
 // except according to those terms.
 
 use std;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::hash::Hash;
 use std::time::Duration;
 
 use timely::dataflow::{Scope, Stream};
-use timely::dataflow::operators::{Filter, Map, Inspect};
 use timely::dataflow::operators::generic::operator::Operator;
-use timely::dataflow::operators::aggregation::Aggregate;
-use timely::dataflow::channels::pact::Exchange;
+use timely::dataflow::channels::pact::{Exchange, Pipeline};
+
+use json::JsonValue;
 
 use logformat::{LogRecord, ActivityType, EventType};
 
-use crate::{PagOutput, PagEdge, PagNode};
+use crate::{activity_label, PagOutput, PagEdge, PagNode};
 
 use std::fs::File;
 use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+/// Options of dumps writing a file per epoch.
+#[derive(Clone, Debug, Default)]
+pub struct DumpOptions {
+    /// Only dump epochs whose number is a multiple of this stride (default: every epoch)
+    pub stride: Option<usize>,
+    /// Maximum number of files to write (default: unbounded)
+    pub max_files: Option<usize>,
+    /// Once `max_files` are written, delete the oldest file for every new one instead of
+    /// stopping to dump
+    pub rotate: bool,
+    /// How often a failed write is retried, with exponential backoff starting at
+    /// `RETRY_BACKOFF`, before the epoch's file is skipped
+    pub write_retries: u32,
+    /// Names of activity types in DOT labels, see `Config::activity_labels`
+    pub activity_labels: Option<HashMap<u8, String>>,
+}
+
+/// Backoff before the first retry of a failed dump write (see `DumpOptions::write_retries`).
+const RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Writes `contents` to `path`, creating its directory if needed, and retries failed attempts.
+/// Transient errors are common on network filesystems (e.g. `EAGAIN` or `ESTALE` on NFS).
+fn write_with_retries(path: &Path, contents: &[u8], retries: u32) -> std::io::Result<()> {
+    let write = || -> std::io::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::DirBuilder::new().recursive(true).create(dir)?;
+        }
+        let mut file = File::create(path)?;
+        file.write_all(contents)?;
+        file.flush()
+    };
+    let mut backoff = RETRY_BACKOFF;
+    let mut attempt = 0;
+    loop {
+        match write() {
+            Ok(()) => return Ok(()),
+            Err(ref why) if attempt < retries => {
+                eprintln!("WARNING couldn't write {:?} ({:?}), retrying in {:?}", path, why, backoff);
+                std::thread::sleep(backoff);
+                backoff *= 2;
+                attempt += 1;
+            }
+            Err(why) => return Err(why),
+        }
+    }
+}
+
+/// Writes the files of a dump according to its `DumpOptions`.
+pub struct DumpFiles {
+    options: DumpOptions,
+    written: VecDeque<PathBuf>,
+    warned: bool,
+}
+
+impl DumpFiles {
+    pub fn new(options: DumpOptions) -> Self {
+        DumpFiles {
+            options,
+            written: VecDeque::new(),
+            warned: false,
+        }
+    }
+
+    /// Whether the dump of `epoch` should be written to `path`. Deletes the oldest file if
+    /// rotating.
+    fn admit(&mut self, epoch: &Duration, path: &Path) -> bool {
+        if let Some(stride) = self.options.stride {
+            if epoch.as_nanos() % stride.max(1) as u128 != 0 {
+                return false;
+            }
+        }
+        if let Some(max_files) = self.options.max_files {
+            if self.written.len() >= max_files {
+                if !self.options.rotate || max_files == 0 {
+                    if !self.warned {
+                        self.warned = true;
+                        eprintln!("WARNING wrote {} dump files, not dumping {:?} and later epochs",
+                                  max_files,
+                                  path);
+                    }
+                    return false;
+                }
+                let oldest = self.written.pop_front().expect("files were written");
+                if let Err(why) = std::fs::remove_file(&oldest) {
+                    eprintln!("couldn't remove {:?}: {:?}", oldest, why);
+                }
+            }
+        }
+        true
+    }
+
+    /// Writes the dump of `epoch` to `path` unless the options exclude it. A write that still
+    /// fails after all retries is reported and skipped rather than aborting the analysis.
+    pub fn write(&mut self, epoch: &Duration, path: &Path, contents: &[u8]) {
+        if !self.admit(epoch, path) {
+            return;
+        }
+        match write_with_retries(path, contents, self.options.write_retries) {
+            Ok(()) => {
+                if self.options.max_files.is_some() {
+                    self.written.push_back(path.to_path_buf());
+                }
+            }
+            Err(why) => {
+                eprintln!("ERROR couldn't write {:?} after {} retries, skipping epoch {:?}: {:?}",
+                          path,
+                          self.options.write_retries,
+                          epoch,
+                          why)
+            }
+        }
+    }
+}
 
 /// Pairs up events local to a single worker timeline and closes gaps by merging events or adding
 /// filler edges.
 pub trait DumpPAG<S: Scope> {
-    fn dump_graph(&self, prefix: &str) -> Stream<S, PagOutput>;
-    fn dump_msgpack(&self, prefix: &str) -> Stream<S, PagOutput>;
+    fn dump_graph(&self, prefix: &str, options: DumpOptions) -> Stream<S, PagOutput>;
+    /// Writes the PAGs of all epochs to a single DOT file at `path`, each epoch in a
+    /// `subgraph cluster_<epoch>` labeled with the epoch. Node IDs are qualified with the epoch
+    /// so that nodes at epoch boundaries do not merge. The file is written once the input is
+    /// complete.
+    fn dump_graph_combined(&self, path: &str, activity_labels: Option<HashMap<u8, String>>) -> Stream<S, PagOutput>;
+    fn dump_msgpack(&self, prefix: &str, options: DumpOptions) -> Stream<S, PagOutput>;
+    /// Like `dump_msgpack`, but every worker writes the edges it holds to its own shard
+    /// `<prefix>pag_<epoch>-w<index>.msgpack` instead of sending them to worker 0. The PAG of
+    /// an epoch is only complete after reading all of its shards, so readers have to glob for
+    /// `<prefix>pag_<epoch>-w*.msgpack` and merge the files.
+    fn dump_msgpack_sharded(&self, prefix: &str, options: DumpOptions) -> Stream<S, PagOutput>;
+    /// Writes the PAG of each epoch to `<prefix>_<epoch>.graphml`, e.g. for yEd or Gephi. Node
+    /// IDs are the same as in `dump_graph`.
+    fn dump_graphml(&self, prefix: &str, options: DumpOptions) -> Stream<S, PagOutput>;
+    /// Writes the PAG of each epoch to `<prefix>pag_<epoch>.cbor` as a CBOR sequence of
+    /// `PagOutput`s (see `cbor`).
+    fn dump_cbor(&self, prefix: &str, options: DumpOptions) -> Stream<S, PagOutput>;
+    /// Writes a single HTML file `<prefix>.html` containing the PAGs of all epochs and a viewer
+    /// with a slider to step through them. The file is written once the input is complete.
+    fn dump_html(&self, prefix: &str, activity_labels: Option<HashMap<u8, String>>) -> Stream<S, PagOutput>;
+    /// Writes the processing edges to `<prefix>.json` as Chrome trace events, one thread per
+    /// worker. Tracy's own file format is not stable, so open the file in Tracy after
+    /// converting it with Tracy's `import-chrome` tool.
+    fn dump_tracy(&self, prefix: &str) -> Stream<S, PagOutput>;
+    /// Writes all edges to `path` in the Chrome trace event format, for `chrome://tracing` or
+    /// Perfetto. Local edges are duration events with one process per worker and one thread per
+    /// operator; edges without an operator are on thread -1. Remote edges are duration events
+    /// on the sending worker, with a flow event to the receiving worker. The file is written as
+    /// the input arrives.
+    fn dump_chrome_trace(&self, path: &str) -> Stream<S, PagOutput>;
 }
 
 pub trait DumpPAGFormatting {
@@ -46,13 +190,219 @@ impl DumpPAGFormatting for PagNode {
 impl DumpPAGFormatting for PagEdge {
     // Converts a PagLabel to an endge description
     fn format(&self) -> String {
-        format!("{:?}", self.edge_type)
+        format_edge(self, None)
+    }
+}
+
+/// Describes `edge` in a DOT label, naming its activity type after `labels`.
+fn format_edge(edge: &PagEdge, labels: Option<&HashMap<u8, String>>) -> String {
+    let name = activity_label(edge.edge_type, labels);
+    if edge.injected_delay_ns > 0 {
+        format!("{} (+{}ns injected)", name, edge.injected_delay_ns)
+    } else {
+        name.to_string()
+    }
+}
+
+/// Writes the nodes and edges of an epoch's PAG in DOT syntax, without the enclosing graph.
+/// Worker-local edges are grouped into one cluster per worker.
+///
+/// If `epoch` is given, node IDs and cluster names are qualified with it, so that the PAGs
+/// of several epochs can be written to the same graph.
+fn write_dot_body<W: Write>(writer: &mut W,
+                            mut timelines: Vec<PagEdge>,
+                            epoch: Option<&Duration>,
+                            labels: Option<&HashMap<u8, String>>)
+                            -> std::io::Result<()> {
+    let node_id = |node: &PagNode| match epoch {
+        Some(epoch) => format!("{:?} {}", epoch, node.format()),
+        None => node.format(),
+    };
+    let cluster = |worker: u64| match epoch {
+        Some(epoch) => format!("cluster_{}_{}", epoch.as_nanos(), worker),
+        None => format!("cluster{}", worker),
+    };
+    writer.write_all(b" node[shape=\"box\"];\n")?;
+
+    let mut local_timeline_per_worker = HashMap::new();
+    let mut communication = vec![];
+
+    for e in timelines.drain(..) {
+        if e.source.worker_id == e.destination.worker_id {
+            local_timeline_per_worker.entry(e.source.worker_id).or_insert_with(Vec::new)
+                .push(e);
+        } else {
+            communication.push(e);
+        }
     }
+
+    for (worker, mut timeline) in local_timeline_per_worker.drain() {
+        timeline.sort_by(|a, b| a.source.timestamp.cmp(&b.source.timestamp));
+        writer.write_all(format!("subgraph {} {{\n", cluster(worker)).as_bytes())?;
+        for e in timeline {
+            writer.write_all(format!("  {:?} -> {:?} [label = {:?}];\n",
+                node_id(&e.source),
+                node_id(&e.destination),
+                format_edge(&e, labels)).as_bytes())?;
+        }
+        writer.write_all(b"}\n")?;
+    }
+    for e in communication.drain(..) {
+        writer.write_all(format!("  {:?} -> {:?} [label = {:?}];\n",
+            node_id(&e.source),
+            node_id(&e.destination),
+            format_edge(&e, labels)).as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Escapes the characters that may not appear in XML attribute values.
+fn xml_escape(value: &str) -> String {
+    value.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Writes an epoch's PAG as a GraphML document. Nodes carry their worker, timestamp (ns) and
+/// the operator of the first edge leaving (or else entering) them, if any; edges carry their
+/// activity type and weight.
+fn write_graphml<W: Write>(writer: &mut W, timelines: Vec<PagEdge>) -> std::io::Result<()> {
+    let mut nodes = BTreeMap::new();
+    for e in &timelines {
+        let source = nodes.entry(e.source).or_insert(None);
+        *source = source.or(e.operator_id);
+    }
+    for e in &timelines {
+        let destination = nodes.entry(e.destination).or_insert(None);
+        *destination = destination.or(e.operator_id);
+    }
+
+    writer.write_all(b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n")?;
+    writer.write_all(b"<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n")?;
+    writer.write_all(b" <key id=\"worker_id\" for=\"node\" attr.name=\"worker_id\" attr.type=\"long\"/>\n")?;
+    writer.write_all(b" <key id=\"timestamp\" for=\"node\" attr.name=\"timestamp\" attr.type=\"long\"/>\n")?;
+    writer.write_all(b" <key id=\"operator_id\" for=\"node\" attr.name=\"operator_id\" attr.type=\"long\"/>\n")?;
+    writer.write_all(b" <key id=\"edge_type\" for=\"edge\" attr.name=\"edge_type\" attr.type=\"string\"/>\n")?;
+    writer.write_all(b" <key id=\"weight\" for=\"edge\" attr.name=\"weight\" attr.type=\"long\"/>\n")?;
+    writer.write_all(b" <graph edgedefault=\"directed\">\n")?;
+    for (node, operator_id) in &nodes {
+        writer.write_all(format!("  <node id=\"{}\">\n", xml_escape(&node.format())).as_bytes())?;
+        writer.write_all(format!("   <data key=\"worker_id\">{}</data>\n", node.worker_id).as_bytes())?;
+        writer.write_all(format!("   <data key=\"timestamp\">{}</data>\n", node.timestamp.as_nanos()).as_bytes())?;
+        if let Some(operator_id) = *operator_id {
+            writer.write_all(format!("   <data key=\"operator_id\">{}</data>\n", operator_id).as_bytes())?;
+        }
+        writer.write_all(b"  </node>\n")?;
+    }
+    for e in &timelines {
+        writer.write_all(format!("  <edge source=\"{}\" target=\"{}\">\n",
+                                 xml_escape(&e.source.format()),
+                                 xml_escape(&e.destination.format())).as_bytes())?;
+        writer.write_all(format!("   <data key=\"edge_type\">{:?}</data>\n", e.edge_type).as_bytes())?;
+        writer.write_all(format!("   <data key=\"weight\">{}</data>\n", e.weight()).as_bytes())?;
+        writer.write_all(b"  </edge>\n")?;
+    }
+    writer.write_all(b" </graph>\n</graphml>\n")
+}
+
+/// Viewer for `dump_html`. `{{EPOCHS}}` is replaced by a JSON array of `{epoch, dot}` objects.
+const HTML_VIEWER: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>SnailTrail PAG</title>
+<script src="https://d3js.org/d3.v5.min.js"></script>
+<script src="https://unpkg.com/viz.js@1.8.1/viz.js"></script>
+<script src="https://unpkg.com/d3-graphviz@2.6.1/build/d3-graphviz.js"></script>
+</head>
+<body>
+<input type="range" id="slider" min="0" value="0">
+<span id="label"></span>
+<div id="graph"></div>
+<script>
+var epochs = {{EPOCHS}};
+var slider = document.getElementById("slider");
+slider.max = Math.max(epochs.length - 1, 0);
+function render() {
+    var epoch = epochs[slider.value];
+    document.getElementById("label").textContent = "epoch " + epoch.epoch;
+    d3.select("#graph").graphviz().renderDot(epoch.dot);
+}
+slider.addEventListener("input", render);
+if (epochs.length > 0) {
+    render();
+}
+</script>
+</body>
+</html>
+"##;
+
+/// Creates the file of a trace dump at `path`, along with its directory.
+fn create_trace_file(path: &str) -> std::io::BufWriter<File> {
+    let path = Path::new(path);
+    if let Some(dir) = path.parent() {
+        std::fs::DirBuilder::new().recursive(true).create(dir).unwrap();
+    }
+    match File::create(path) {
+        Err(why) => panic!("couldn't open {:?}: {:?}", path, why),
+        Ok(file) => std::io::BufWriter::new(file),
+    }
+}
+
+/// The timestamp of `node` in a Chrome trace, in microseconds with nanosecond precision.
+fn trace_timestamp(node: &PagNode) -> f64 {
+    node.timestamp.as_nanos() as f64 / 1000.
+}
+
+/// Writes the events `logic` returns for each edge of `stream` to `path` as a JSON array of
+/// Chrome trace events. Worker 0 writes the file as the input arrives and closes the array once
+/// the input is complete.
+fn write_chrome_trace<S, L>(stream: &Stream<S, PagOutput>, path: &str, name: &str, mut logic: L)
+                            -> Stream<S, PagOutput>
+    where S: Scope<Timestamp = Duration>,
+          L: FnMut(&PagEdge) -> Vec<JsonValue> + 'static
+{
+    // Only worker 0 receives data and writes the file
+    let mut writer = if stream.scope().index() == 0 {
+        Some(create_trace_file(path))
+    } else {
+        None
+    };
+    let mut vector = Vec::new();
+    let mut events = 0;
+    stream.unary_frontier(Exchange::new(|_| 0), name, move |_cap, _info| {
+        move |input, _output| {
+            input.for_each(|_time, data| {
+                data.swap(&mut vector);
+                let writer = writer.as_mut().expect("Chrome traces are written on worker 0");
+                for pag in vector.drain(..) {
+                    let edge = match pag {
+                        PagOutput::Edge(e) => e,
+                        _ => continue,
+                    };
+                    for event in logic(&edge) {
+                        writer.write_all(if events == 0 { b"[" } else { b"," }).unwrap();
+                        event.write(writer).unwrap();
+                        events += 1;
+                    }
+                }
+            });
+            if input.frontier().frontier().is_empty() {
+                if let Some(mut writer) = writer.take() {
+                    writer.write_all(if events == 0 { b"[]" } else { b"]" }).unwrap();
+                    writer.flush().unwrap();
+                }
+            }
+        }
+    })
 }
 
 impl<S: Scope<Timestamp = Duration>> DumpPAG<S> for Stream<S, PagOutput> {
-    fn dump_graph(&self, prefix: &str) -> Stream<S, PagOutput> {
+    fn dump_graph(&self, prefix: &str, options: DumpOptions) -> Stream<S, PagOutput> {
         let prefix = prefix.to_owned();
+        let labels = options.activity_labels.clone();
+        let mut files = DumpFiles::new(options);
         let mut pag_per_epoch = HashMap::new();
         let mut vector = Vec::new();
         self.unary_notify(Exchange::new(|_| 0), "Dump graph", vec![], move |input, _output, notificator| {
@@ -70,9 +420,52 @@ impl<S: Scope<Timestamp = Duration>> DumpPAG<S> for Stream<S, PagOutput> {
             });
             // Sequentially assemble the edges for each worker timeline by pairing up log records
             notificator.for_each(|time, _count, _notify| {
-                if let Some(mut timelines) = pag_per_epoch.remove(time.time()) {
+                if let Some(timelines) = pag_per_epoch.remove(time.time()) {
                     let path = format!("{}graph_{:?}.dot", prefix, time.time());
-                    let path = std::path::Path::new(&path);
+                    let mut contents = Vec::new();
+                    contents.write_all(b"digraph G {\n").unwrap();
+                    write_dot_body(&mut contents, timelines, None, labels.as_ref()).unwrap();
+                    contents.write_all(b"}").unwrap();
+                    files.write(time.time(), Path::new(&path), &contents);
+                }
+            });
+
+        })
+    }
+
+    fn dump_graph_combined(&self, path: &str, activity_labels: Option<HashMap<u8, String>>) -> Stream<S, PagOutput> {
+        let path = path.to_owned();
+        let mut pag_per_epoch = HashMap::new();
+        let mut vector = Vec::new();
+        let mut written = false;
+        self.unary_frontier(Exchange::new(|_| 0), "Dump combined graph", move |_cap, _info| {
+            move |input, _output| {
+                input.for_each(|time, data| {
+                    let epoch_slot = pag_per_epoch.entry(*time.time())
+                        .or_insert_with(Vec::new);
+                    data.swap(&mut vector);
+                    for pag in vector.drain(..) {
+                        if let PagOutput::Edge(record) = pag {
+                            epoch_slot.push(record);
+                        }
+                    }
+                });
+                if !written && input.frontier().frontier().is_empty() {
+                    written = true;
+                    let mut epochs: Vec<_> = pag_per_epoch.drain().collect();
+                    epochs.sort_by_key(|&(time, _)| time);
+                    let mut contents = Vec::new();
+                    contents.write_all(b"digraph G {\n").unwrap();
+                    for (time, timelines) in epochs {
+                        contents.write_all(format!("subgraph cluster_{} {{\n label = \"epoch {:?}\";\n",
+                                                   time.as_nanos(),
+                                                   time).as_bytes()).unwrap();
+                        write_dot_body(&mut contents, timelines, Some(&time), activity_labels.as_ref()).unwrap();
+                        contents.write_all(b"}\n").unwrap();
+                    }
+                    contents.write_all(b"}").unwrap();
+
+                    let path = Path::new(&path);
                     if let Some(dir) = path.parent() {
                         std::fs::DirBuilder::new().recursive(true).create(dir).unwrap();
                     }
@@ -80,47 +473,148 @@ impl<S: Scope<Timestamp = Duration>> DumpPAG<S> for Stream<S, PagOutput> {
                         Err(why) => panic!("couldn't open {:?}: {:?}", path, why),
                         Ok(file) => file,
                     };
-                    file.write_all(b"digraph G {\n").unwrap();
-                    file.write_all(b" node[shape=\"box\"];\n").unwrap();
-
-                    let mut local_timeline_per_worker = HashMap::new();
-                    let mut communication = vec![];
-
-                    for e in timelines.drain(..) {
-                        if e.source.worker_id == e.destination.worker_id {
-                            local_timeline_per_worker.entry(e.source.worker_id).or_insert_with(Vec::new)
-                                .push(e);
-                        } else {
-                            communication.push(e);
-                        }
-                    }
+                    file.write_all(&contents).unwrap();
+                }
+            }
+        })
+    }
 
-                    for (worker, mut timeline) in local_timeline_per_worker.drain() {
-                        timeline.sort_by(|a, b| a.source.timestamp.cmp(&b.source.timestamp));
-                        file.write_all(format!("subgraph cluster{} {{\n", worker).as_bytes()).unwrap();
-                        for e in timeline {
-                            file.write_all(format!("  {:?} -> {:?} [label = {:?}];\n",
-                                e.source.format(),
-                                e.destination.format(),
-                                e.format()).as_bytes()).unwrap();
+    fn dump_html(&self, prefix: &str, activity_labels: Option<HashMap<u8, String>>) -> Stream<S, PagOutput> {
+        let path = format!("{}.html", prefix);
+        let mut pag_per_epoch = HashMap::new();
+        let mut vector = Vec::new();
+        let mut written = false;
+        self.unary_frontier(Exchange::new(|_| 0), "Dump graph to HTML", move |_cap, _info| {
+            move |input, _output| {
+                input.for_each(|time, data| {
+                    let epoch_slot = pag_per_epoch.entry(*time.time())
+                        .or_insert_with(Vec::new);
+                    data.swap(&mut vector);
+                    for pag in vector.drain(..) {
+                        if let PagOutput::Edge(record) = pag {
+                            epoch_slot.push(record);
                         }
-                        file.write_all(b"}\n").unwrap();
                     }
-                    for e in communication.drain(..) {
-                        file.write_all(format!("  {:?} -> {:?} [label = {:?}];\n",
-                            e.source.format(),
-                            e.destination.format(),
-                            e.format()).as_bytes()).unwrap();
+                });
+                // All epochs are needed for the viewer, so wait until the input is complete
+                if !written && input.frontier().frontier().is_empty() {
+                    written = true;
+                    let mut epochs: Vec<_> = pag_per_epoch.drain().collect();
+                    epochs.sort_by_key(|&(time, _)| time);
+                    let epochs: Vec<JsonValue> = epochs
+                        .into_iter()
+                        .map(|(time, timelines)| {
+                            let mut dot = b"digraph G {\n".to_vec();
+                            write_dot_body(&mut dot, timelines, None, activity_labels.as_ref()).unwrap();
+                            dot.extend_from_slice(b"}");
+                            object!{
+                                "epoch" => format!("{:?}", time),
+                                "dot" => String::from_utf8(dot).expect("DOT is valid UTF-8")
+                            }
+                        })
+                        .collect();
+                    let html = HTML_VIEWER.replace("{{EPOCHS}}", &json::stringify(epochs));
+
+                    let path = std::path::Path::new(&path);
+                    if let Some(dir) = path.parent() {
+                        std::fs::DirBuilder::new().recursive(true).create(dir).unwrap();
                     }
-                    file.write_all(b"}").unwrap();
+                    let mut file = match File::create(path) {
+                        Err(why) => panic!("couldn't open {:?}: {:?}", path, why),
+                        Ok(file) => file,
+                    };
+                    file.write_all(html.as_bytes()).unwrap();
                 }
-            });
+            }
+        })
+    }
+
+    fn dump_tracy(&self, prefix: &str) -> Stream<S, PagOutput> {
+        write_chrome_trace(self, &format!("{}.json", prefix), "Dump Tracy zones", |edge| {
+            if edge.edge_type != ActivityType::Processing {
+                return vec![];
+            }
+            let name = match edge.operator_address {
+                Some(ref address) => format!("operator {}", address),
+                None => edge.edge_type.name().to_string(),
+            };
+            vec![object!{
+                "name" => name,
+                "ph" => "X",
+                "pid" => 0,
+                "tid" => edge.source.worker_id,
+                "ts" => trace_timestamp(&edge.source),
+                "dur" => edge.weight() as f64 / 1000.
+            }]
+        })
+    }
 
+    fn dump_chrome_trace(&self, path: &str) -> Stream<S, PagOutput> {
+        let mut flows = 0u64;
+        write_chrome_trace(self, path, "Dump Chrome trace", move |edge| {
+            let tid = edge.operator_id.map_or(-1, |id| id as i64);
+            let duration = object!{
+                "name" => edge.edge_type.name(),
+                "ph" => "X",
+                "pid" => edge.source.worker_id,
+                "tid" => tid,
+                "ts" => trace_timestamp(&edge.source),
+                "dur" => edge.weight() as f64 / 1000.
+            };
+            if edge.source.worker_id == edge.destination.worker_id {
+                return vec![duration];
+            }
+            // Remote edges are also drawn as an arrow to the receiving worker
+            flows += 1;
+            vec![duration,
+                 object!{
+                     "name" => edge.edge_type.name(),
+                     "cat" => "remote",
+                     "ph" => "s",
+                     "id" => flows,
+                     "pid" => edge.source.worker_id,
+                     "tid" => tid,
+                     "ts" => trace_timestamp(&edge.source)
+                 },
+                 object!{
+                     "name" => edge.edge_type.name(),
+                     "cat" => "remote",
+                     "ph" => "f",
+                     "bp" => "e",
+                     "id" => flows,
+                     "pid" => edge.destination.worker_id,
+                     "tid" => tid,
+                     "ts" => trace_timestamp(&edge.destination)
+                 }]
+        })
+    }
+
+    fn dump_cbor(&self, prefix: &str, options: DumpOptions) -> Stream<S, PagOutput> {
+        let prefix = prefix.to_owned();
+        let mut files = DumpFiles::new(options);
+        let mut pag_per_epoch = HashMap::new();
+        let mut vector = Vec::new();
+        self.unary_notify(Exchange::new(|_| 0), "Dump graph to CBOR", vec![], move |input, _output, notificator| {
+            input.for_each(|time, data| {
+                data.swap(&mut vector);
+                pag_per_epoch.entry(*time.time()).or_insert_with(Vec::new).extend(vector.drain(..));
+                notificator.notify_at(time.retain());
+            });
+            notificator.for_each(|time, _count, _notify| {
+                if let Some(mut pag) = pag_per_epoch.remove(time.time()) {
+                    pag.sort();
+                    let path = format!("{}pag_{:?}.cbor", prefix, time.time());
+                    let mut contents = Vec::new();
+                    crate::cbor::write(&mut contents, &pag).unwrap();
+                    files.write(time.time(), Path::new(&path), &contents);
+                }
+            });
         })
     }
 
-    fn dump_msgpack(&self, prefix: &str) -> Stream<S, PagOutput> {
+    fn dump_msgpack(&self, prefix: &str, options: DumpOptions) -> Stream<S, PagOutput> {
         let prefix = prefix.to_owned();
+        let mut files = DumpFiles::new(options);
         let mut pag_per_epoch = HashMap::new();
         let mut vector = Vec::new();
         self.unary_notify(Exchange::new(|_| 0), "Dump graph to msgpack", vec![], move |input, _output, notificator| {
@@ -138,116 +632,260 @@ impl<S: Scope<Timestamp = Duration>> DumpPAG<S> for Stream<S, PagOutput> {
             });
             // Sequentially assemble the edges for each worker timeline by pairing up log records
             notificator.for_each(|time, _count, _notify| {
-                if let Some(mut timelines) = pag_per_epoch.remove(time.time()) {
+                if let Some(timelines) = pag_per_epoch.remove(time.time()) {
                     let path = format!("{}pag_{:?}.msgpack", prefix, time.time());
-                    let path = std::path::Path::new(&path);
-                    if let Some(dir) = path.parent() {
-                        std::fs::DirBuilder::new().recursive(true).create(dir).unwrap();
-                    }
-                    let file = match File::create(path) {
-                        Err(why) => panic!("couldn't open {:?}: {:?}", path, why),
-                        Ok(file) => file,
-                    };
-                    let mut writer = std::io::BufWriter::new(file);
-                    // makes a record from a PagNode and additional info
-                    fn to_record(
-                        correlator_id: u64,
-                        pag_node: PagNode,
-                        activity_type: ActivityType,
-                        operator_id: Option<u64>,
-                        is_message: bool,
-                        is_start: bool,
-                        remote_worker: Option<u64>) -> LogRecord {
-
-                        LogRecord {
-                            timestamp: pag_node.timestamp,
-                            local_worker: pag_node.worker_id,
-                            activity_type: activity_type,
-                            operator_id: operator_id,
-                            remote_worker: remote_worker,
-                            event_type: match (is_start, is_message) {
-                                (true,  true)  => EventType::Sent,
-                                (false, true)  => EventType::Received,
-                                (true,  false) => EventType::Start,
-                                (false, false) => EventType::End,
-                            },
-                            correlator_id: Some(correlator_id),
-                            channel_id: None,
-                        }
+                    let mut writer = Vec::new();
+                    write_msgpack_edges(&mut writer, timelines);
+                    files.write(time.time(), Path::new(&path), &writer);
+                }
+            });
+
+        })
+    }
+
+    fn dump_msgpack_sharded(&self, prefix: &str, options: DumpOptions) -> Stream<S, PagOutput> {
+        let prefix = prefix.to_owned();
+        let index = self.scope().index();
+        let mut files = DumpFiles::new(options);
+        let mut pag_per_epoch = HashMap::new();
+        let mut vector = Vec::new();
+        self.unary_notify(Pipeline, "Dump graph shard to msgpack", vec![], move |input, _output, notificator| {
+            input.for_each(|time, data| {
+                let epoch_slot = pag_per_epoch.entry(*time.time())
+                    .or_insert_with(Vec::new);
+                data.swap(&mut vector);
+                for pag in vector.drain(..) {
+                    if let PagOutput::Edge(record) = pag {
+                        epoch_slot.push(record);
                     }
-                    let mut correlator_id: u64 = 1;
-                    for PagEdge { source, destination, edge_type, operator_id, ..} in timelines.drain(..) {
-                        let first = to_record(
-                            correlator_id,
-                            source,
-                            edge_type,
-                            operator_id,
-                            !edge_type.is_worker_local(),
-                            true,
-                            Some(destination.worker_id));
-                        first.write(&mut writer).unwrap();
-                        let second = to_record(
-                            correlator_id,
-                            destination,
-                            edge_type,
-                            operator_id,
-                            !edge_type.is_worker_local(),
-                            false,
-                            Some(source.worker_id));
-                        second.write(&mut writer).unwrap();
-                        correlator_id += 1;
+                }
+                notificator.notify_at(time.retain());
+            });
+            notificator.for_each(|time, _count, _notify| {
+                if let Some(timelines) = pag_per_epoch.remove(time.time()) {
+                    let path = format!("{}pag_{:?}-w{}.msgpack", prefix, time.time(), index);
+                    let mut writer = Vec::new();
+                    write_msgpack_edges(&mut writer, timelines);
+                    files.write(time.time(), Path::new(&path), &writer);
+                }
+            });
+        })
+    }
+
+    fn dump_graphml(&self, prefix: &str, options: DumpOptions) -> Stream<S, PagOutput> {
+        let prefix = prefix.to_owned();
+        let mut files = DumpFiles::new(options);
+        let mut pag_per_epoch = HashMap::new();
+        let mut vector = Vec::new();
+        self.unary_notify(Exchange::new(|_| 0), "Dump graph to GraphML", vec![], move |input, _output, notificator| {
+            input.for_each(|time, data| {
+                let epoch_slot = pag_per_epoch.entry(*time.time())
+                    .or_insert_with(Vec::new);
+                data.swap(&mut vector);
+                for pag in vector.drain(..) {
+                    if let PagOutput::Edge(record) = pag {
+                        epoch_slot.push(record);
                     }
                 }
+                notificator.notify_at(time.retain());
             });
+            notificator.for_each(|time, _count, _notify| {
+                if let Some(mut timelines) = pag_per_epoch.remove(time.time()) {
+                    timelines.sort_by_key(|e| (e.source, e.destination));
+                    let path = format!("{}_{:?}.graphml", prefix, time.time());
+                    let mut contents = Vec::new();
+                    write_graphml(&mut contents, timelines).unwrap();
+                    files.write(time.time(), Path::new(&path), &contents);
+                }
+            });
+        })
+    }
+}
+
+/// Writes every edge as a pair of log records (start and end, or send and receive) sharing a
+/// correlator, which is the layout of `DumpPAG::dump_msgpack`.
+fn write_msgpack_edges<W: Write>(writer: &mut W, mut timelines: Vec<PagEdge>) {
+    // makes a record from a PagNode and additional info
+    fn to_record(
+        correlator_id: u64,
+        pag_node: PagNode,
+        activity_type: ActivityType,
+        operator_id: Option<u64>,
+        is_message: bool,
+        is_start: bool,
+        remote_worker: Option<u64>) -> LogRecord {
 
+        LogRecord {
+            timestamp: pag_node.timestamp,
+            local_worker: pag_node.worker_id,
+            activity_type: activity_type,
+            operator_id: operator_id,
+            remote_worker: remote_worker,
+            event_type: match (is_start, is_message) {
+                (true,  true)  => EventType::Sent,
+                (false, true)  => EventType::Received,
+                (true,  false) => EventType::Start,
+                (false, false) => EventType::End,
+            },
+            correlator_id: Some(correlator_id),
+            channel_id: None,
+        }
+    }
+    let mut correlator_id: u64 = 1;
+    for PagEdge { source, destination, edge_type, operator_id, ..} in timelines.drain(..) {
+        let first = to_record(
+            correlator_id,
+            source,
+            edge_type,
+            operator_id,
+            !edge_type.is_worker_local(),
+            true,
+            Some(destination.worker_id));
+        first.write(writer).unwrap();
+        let second = to_record(
+            correlator_id,
+            destination,
+            edge_type,
+            operator_id,
+            !edge_type.is_worker_local(),
+            false,
+            Some(source.worker_id));
+        second.write(writer).unwrap();
+        correlator_id += 1;
+    }
+}
+
+/// Dumps the BC of PAG edges.
+pub trait DumpBC<S: Scope> {
+    /// Writes the edges of each epoch to `<prefix>pag_<epoch>.msgpack` like
+    /// `DumpPAG::dump_msgpack`, and their BC to `<prefix>bc_<epoch>.msgpack` as a sequence of
+    /// msgpack float64 values, one per edge in the same order. Edges without centrality are
+    /// written as well, so the files can be joined with the PAG.
+    fn dump_msgpack_bc(&self, prefix: &str, options: DumpOptions) -> Stream<S, (PagOutput, f64)>;
+}
+
+impl<S: Scope<Timestamp = Duration>> DumpBC<S> for Stream<S, (PagOutput, f64)> {
+    fn dump_msgpack_bc(&self, prefix: &str, options: DumpOptions) -> Stream<S, (PagOutput, f64)> {
+        let prefix = prefix.to_owned();
+        let mut pag_files = DumpFiles::new(options.clone());
+        let mut bc_files = DumpFiles::new(options);
+        let mut bc_per_epoch = HashMap::new();
+        let mut vector = Vec::new();
+        self.unary_notify(Exchange::new(|_| 0), "Dump BC to msgpack", vec![], move |input, _output, notificator| {
+            input.for_each(|time, data| {
+                let epoch_slot = bc_per_epoch.entry(*time.time())
+                    .or_insert_with(Vec::new);
+                data.swap(&mut vector);
+                for (pag, bc) in vector.drain(..) {
+                    if let PagOutput::Edge(record) = pag {
+                        epoch_slot.push((record, bc));
+                    }
+                }
+                notificator.notify_at(time.retain());
+            });
+            notificator.for_each(|time, _count, _notify| {
+                if let Some(edges) = bc_per_epoch.remove(time.time()) {
+                    let (edges, bc): (Vec<_>, Vec<_>) = edges.into_iter().unzip();
+                    let mut pag_writer = Vec::new();
+                    write_msgpack_edges(&mut pag_writer, edges);
+                    let mut bc_writer = Vec::with_capacity(9 * bc.len());
+                    for bc in bc {
+                        // msgpack float64
+                        bc_writer.push(0xcb);
+                        bc_writer.extend_from_slice(&bc.to_bits().to_be_bytes());
+                    }
+                    let pag_path = format!("{}pag_{:?}.msgpack", prefix, time.time());
+                    let bc_path = format!("{}bc_{:?}.msgpack", prefix, time.time());
+                    pag_files.write(time.time(), Path::new(&pag_path), &pag_writer);
+                    bc_files.write(time.time(), Path::new(&bc_path), &bc_writer);
+                }
+            });
         })
     }
 }
 
+/// Writes the edges of an epoch's PAG with their BC as Cypher statements for Neo4j, in a
+/// single transaction for `cypher-shell`. Nodes are `PagNode`s identified by
+/// `(epoch, worker, timestamp)`, which should be indexed for large imports; edges are
+/// relationships typed by their activity.
+pub fn write_cypher<W: Write>(writer: &mut W, epoch: &Duration, bc: &[(PagOutput, f64)]) -> std::io::Result<()> {
+    let epoch = epoch.as_nanos();
+    writeln!(writer, ":begin")?;
+    for &(ref output, bc) in bc {
+        let edge = match *output {
+            PagOutput::Edge(ref edge) => edge,
+            _ => continue,
+        };
+        let operator = match edge.operator_address {
+            Some(ref address) => format!("{:?}", address.to_string()),
+            None => "null".to_string(),
+        };
+        writeln!(writer,
+                 "MERGE (s:PagNode {{epoch: {}, worker: {}, timestamp: {}}}) \
+                  MERGE (d:PagNode {{epoch: {}, worker: {}, timestamp: {}}}) \
+                  CREATE (s)-[:{} {{weight: {}, bc: {}, operator: {}, traverse: {:?}}}]->(d);",
+                 epoch,
+                 edge.source.worker_id,
+                 edge.source.timestamp.as_nanos(),
+                 epoch,
+                 edge.destination.worker_id,
+                 edge.destination.timestamp.as_nanos(),
+                 edge.edge_type.name(),
+                 edge.weight(),
+                 bc,
+                 operator,
+                 format!("{:?}", edge.traverse))?;
+    }
+    writeln!(writer, ":commit")
+}
 
-pub trait DumpHistogram<S: Scope> {
-    fn dump_histogram(&self) -> Stream<S, (i64, u64)>;
+/// The default bucket boundaries of `DumpHistogram`: powers of ten from 1ns to 10s.
+pub fn default_histogram_buckets() -> Vec<u64> {
+    (0..11).map(|exponent| 10u64.pow(exponent)).collect()
 }
 
+pub trait DumpHistogram<S: Scope> {
+    /// Prints a histogram of the edge weights (activity durations in ns) of each epoch as
+    /// `HISTOGRAM <epoch> <lo>-<hi> <count>` lines, collected on worker 0. The sorted
+    /// `boundaries` `b_0, b_1, ...` delimit the buckets `0-b_0`, `b_0-b_1`, ..., each including
+    /// its lower bound, and the last bucket `b_n-inf`. Every bucket is printed, also if empty.
+    fn dump_histogram(&self, boundaries: Vec<u64>) -> Stream<S, PagOutput>;
+}
 
-impl<S: Scope> DumpHistogram<S> for Stream<S, LogRecord>
+impl<S: Scope> DumpHistogram<S> for Stream<S, PagOutput>
     where S::Timestamp: Hash
 {
-    fn dump_histogram(&self) -> Stream<S, (i64, u64)> {
-        // Retain communication edges
-        self.filter(|rec| rec.remote_worker.is_some())
-            // transform to (key, edge) format
-            .map(|rec| (rec.correlator_id, rec))
-            // Aggregate per key
-            .aggregate::<_, Vec<_>, _, _, _>(
-                |_key, val, agg| agg.push(val),
-                |_key, mut agg| {
-                    let tsdiff = if agg.len() > 1 {
-                        // Make sure send event is always first
-                        if agg[0].event_type == EventType::Received {
-                            agg.swap(0, 1);
-                        }
-                        let first_ts = agg[0].timestamp.as_nanos();
-                        let second_ts = agg[1].timestamp.as_nanos();
-                        ((second_ts - first_ts) / 100_000_000) as u64
-                    } else {
-                        0
-                    };
-                    (agg, tsdiff + u32::max_value() as u64)
-                },
-                |key| key.unwrap_or(0)
-            )
-            // Retain where two aggregates, i.e. matched communication
-            .filter(|&(ref agg, _)| agg.len() == 2)
-            // Transform to grouping for histogram
-            .map(|(_, tsdiff)| (tsdiff as i64, 0))
-            // Count elements per bucket
-            .aggregate::<_, u64, _, _, _>(
-                |_key, _val, agg| *agg += 1,
-                |key, agg| (key, agg),
-                |key| *key as u64
-            )
-            // Print it
-            .inspect(|&(key, agg)| println!("DEBUG histogram {} {}", (key - u32::max_value() as i64) as f64 / 10., agg))
+    fn dump_histogram(&self, mut boundaries: Vec<u64>) -> Stream<S, PagOutput> {
+        boundaries.sort();
+        boundaries.dedup();
+        let mut counts_per_epoch = HashMap::new();
+        let mut vector = Vec::new();
+        self.unary_notify(Exchange::new(|_| 0), "Dump histogram", vec![], move |input, _output, notificator| {
+            input.for_each(|time, data| {
+                let counts = counts_per_epoch.entry(time.time().clone())
+                    .or_insert_with(|| vec![0u64; boundaries.len() + 1]);
+                data.swap(&mut vector);
+                for pag in vector.drain(..) {
+                    if let PagOutput::Edge(ref e) = pag {
+                        // The number of boundaries at or below the weight is the bucket's index
+                        let bucket = match boundaries.binary_search(&e.weight()) {
+                            Ok(index) => index + 1,
+                            Err(index) => index,
+                        };
+                        counts[bucket] += 1;
+                    }
+                }
+                notificator.notify_at(time.retain());
+            });
+            notificator.for_each(|time, _count, _notify| {
+                if let Some(counts) = counts_per_epoch.remove(time.time()) {
+                    for (bucket, count) in counts.into_iter().enumerate() {
+                        let lo = if bucket == 0 { 0 } else { boundaries[bucket - 1] };
+                        let hi = boundaries.get(bucket).map_or("inf".to_string(), u64::to_string);
+                        println!("HISTOGRAM {:?} {}-{} {}", time.time(), lo, hi, count);
+                    }
+                }
+            });
+        })
     }
 }
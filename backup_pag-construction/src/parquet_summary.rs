@@ -0,0 +1,89 @@
+// Copyright 2017 ETH Zurich. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Writes summaries to a Parquet file for analytics tools such as DuckDB or Spark.
+//!
+//! The columns mirror the `SUMMARY` CSV columns: `epoch` (ns), `activity` (the numeric activity
+//! type), `operator`, `src`, `dst`, `crosses`, `bc`, `weighted_bc`, `count` and `weight`.
+//! Every epoch is written as one row group. Only built with the `parquet-output` feature.
+
+use std::fs::File;
+use std::sync::Arc;
+use std::time::Duration;
+
+use arrow::array::{ArrayRef, Float64Array, StringArray, UInt32Array, UInt64Array, UInt8Array};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+use ::parquet::arrow::ArrowWriter;
+use ::parquet::errors::{ParquetError, Result};
+
+use crate::dataflow::SummaryRow;
+
+fn schema() -> Schema {
+    Schema::new(vec![Field::new("epoch", DataType::UInt64, false),
+                     Field::new("activity", DataType::UInt8, false),
+                     // Operator IDs are not limited to 8 bits like activity types
+                     Field::new("operator", DataType::UInt64, false),
+                     Field::new("src", DataType::UInt32, false),
+                     Field::new("dst", DataType::UInt32, false),
+                     Field::new("crosses", DataType::Utf8, false),
+                     Field::new("bc", DataType::Float64, false),
+                     Field::new("weighted_bc", DataType::Float64, false),
+                     Field::new("count", DataType::UInt64, false),
+                     Field::new("weight", DataType::UInt64, false)])
+}
+
+/// A Parquet file of summaries, finished when dropped.
+pub struct ParquetSummary {
+    schema: Arc<Schema>,
+    writer: Option<ArrowWriter<File>>,
+}
+
+impl ParquetSummary {
+    /// Creates (or truncates) the file at `path`.
+    pub fn create(path: &str) -> Result<Self> {
+        let schema = Arc::new(schema());
+        let file = File::create(path).map_err(|why| ParquetError::General(format!("{}", why)))?;
+        let writer = ArrowWriter::try_new(file, schema.clone(), None)?;
+        Ok(ParquetSummary {
+            schema,
+            writer: Some(writer),
+        })
+    }
+
+    /// Writes an epoch's summary rows as a row group.
+    pub fn write_epoch(&mut self, epoch: &Duration, rows: &[SummaryRow]) -> Result<()> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let epoch = epoch.as_nanos() as u64;
+        let columns: Vec<ArrayRef> =
+            vec![Arc::new(UInt64Array::from(vec![epoch; rows.len()])),
+                 Arc::new(UInt8Array::from(rows.iter().map(|row| row.activity_type as u8).collect::<Vec<_>>())),
+                 Arc::new(UInt64Array::from(rows.iter().map(|row| row.operator_id).collect::<Vec<_>>())),
+                 Arc::new(UInt32Array::from(rows.iter().map(|row| row.src as u32).collect::<Vec<_>>())),
+                 Arc::new(UInt32Array::from(rows.iter().map(|row| row.dst as u32).collect::<Vec<_>>())),
+                 Arc::new(StringArray::from(rows.iter().map(|row| row.crosses.to_string()).collect::<Vec<_>>())),
+                 Arc::new(Float64Array::from(rows.iter().map(|row| row.bc).collect::<Vec<_>>())),
+                 Arc::new(Float64Array::from(rows.iter().map(|row| row.weighted_bc).collect::<Vec<_>>())),
+                 Arc::new(UInt64Array::from(rows.iter().map(|row| row.count).collect::<Vec<_>>())),
+                 Arc::new(UInt64Array::from(rows.iter().map(|row| row.weight).collect::<Vec<_>>()))];
+        let batch = RecordBatch::try_new(self.schema.clone(), columns)?;
+        self.writer.as_mut().expect("the writer is only taken when dropped").write(&batch)
+    }
+}
+
+impl Drop for ParquetSummary {
+    fn drop(&mut self) {
+        if let Some(writer) = self.writer.take() {
+            if let Err(why) = writer.close() {
+                eprintln!("couldn't finish the summary Parquet file: {:?}", why);
+            }
+        }
+    }
+}
@@ -0,0 +1,120 @@
+// Copyright 2017 ETH Zurich. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Per-edge slack of an epoch's PAG, computed with the critical path method (CPM).
+//!
+//! Edge durations are their weights. A forward pass over the DAG in topological order computes
+//! the earliest time each node can be reached (the longest path from any source), a backward
+//! pass the latest time it can be reached without extending the longest path of the epoch.
+//! Edges with zero slack are on a critical (longest) path. Waiting edges do not represent work
+//! and, as for BC, are not traversed.
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::{PagEdge, PagNode, TraversalType};
+
+/// Earliest and latest start of an edge, in nanoseconds relative to the start of the epoch's
+/// longest path.
+#[derive(Abomonation, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EdgeSlack {
+    pub earliest_start: u64,
+    pub latest_start: u64,
+}
+
+impl EdgeSlack {
+    /// How much the edge can be delayed without extending the epoch's longest path.
+    pub fn slack(&self) -> u64 {
+        self.latest_start - self.earliest_start
+    }
+}
+
+/// Returns the nodes of `edges` in topological order along with the outgoing edges (as
+/// indices into `edges`) of every node.
+fn topological_order(edges: &[&PagEdge]) -> (Vec<PagNode>, HashMap<PagNode, Vec<usize>>) {
+    let mut outgoing: HashMap<PagNode, Vec<usize>> = HashMap::new();
+    let mut in_degree: HashMap<PagNode, usize> = HashMap::new();
+    for (index, edge) in edges.iter().enumerate() {
+        outgoing.entry(edge.source).or_insert_with(Vec::new).push(index);
+        outgoing.entry(edge.destination).or_insert_with(Vec::new);
+        in_degree.entry(edge.source).or_insert(0);
+        *in_degree.entry(edge.destination).or_insert(0) += 1;
+    }
+
+    let mut ready: VecDeque<_> = in_degree
+        .iter()
+        .filter(|&(_, &degree)| degree == 0)
+        .map(|(node, _)| *node)
+        .collect();
+    let mut order = Vec::with_capacity(in_degree.len());
+    while let Some(node) = ready.pop_front() {
+        order.push(node);
+        for &index in &outgoing[&node] {
+            let degree = in_degree.get_mut(&edges[index].destination).unwrap();
+            *degree -= 1;
+            if *degree == 0 {
+                ready.push_back(edges[index].destination);
+            }
+        }
+    }
+    assert_eq!(order.len(), in_degree.len(), "PAG contains a cycle");
+    (order, outgoing)
+}
+
+/// Longest path from any source to every node, i.e. the earliest time the node is reached.
+pub fn longest_paths(edges: &[&PagEdge]) -> HashMap<PagNode, u64> {
+    let (order, outgoing) = topological_order(edges);
+    longest_paths_in_order(edges, &order, &outgoing)
+}
+
+fn longest_paths_in_order(edges: &[&PagEdge],
+                          order: &[PagNode],
+                          outgoing: &HashMap<PagNode, Vec<usize>>)
+                          -> HashMap<PagNode, u64> {
+    let mut earliest: HashMap<PagNode, u64> = order.iter().map(|node| (*node, 0)).collect();
+    for node in order {
+        let reached = earliest[node];
+        for &index in &outgoing[node] {
+            let edge = edges[index];
+            let next = earliest.get_mut(&edge.destination).unwrap();
+            *next = (*next).max(reached + edge.weight());
+        }
+    }
+    earliest
+}
+
+/// Computes the slack of every traversable edge of an epoch's PAG. Waiting edges are skipped.
+pub fn compute_slack(edges: &[PagEdge]) -> Vec<(PagEdge, EdgeSlack)> {
+    let edges: Vec<_> = edges
+        .iter()
+        .filter(|edge| edge.traverse != TraversalType::Block)
+        .collect();
+    let (order, outgoing) = topological_order(&edges);
+    let earliest = longest_paths_in_order(&edges, &order, &outgoing);
+    let length = earliest.values().cloned().max().unwrap_or(0);
+
+    let mut latest: HashMap<PagNode, u64> = order.iter().map(|node| (*node, length)).collect();
+    for node in order.iter().rev() {
+        for &index in &outgoing[node] {
+            let edge = edges[index];
+            let start = latest[&edge.destination] - edge.weight();
+            let current = latest.get_mut(node).unwrap();
+            *current = (*current).min(start);
+        }
+    }
+
+    edges
+        .into_iter()
+        .map(|edge| {
+                 let slack = EdgeSlack {
+                     earliest_start: earliest[&edge.source],
+                     latest_start: latest[&edge.destination] - edge.weight(),
+                 };
+                 (edge.clone(), slack)
+             })
+        .collect()
+}
@@ -0,0 +1,170 @@
+// Copyright 2017 ETH Zurich. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Writes PAG edges, BC values and summaries to a SQLite database for ad-hoc querying.
+//!
+//! Rows are inserted in one transaction per epoch and table. Timestamps and epochs are stored
+//! as nanoseconds.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rusqlite::{params, Connection};
+
+use crate::dataflow::SummaryRow;
+use crate::{PagEdge, PagOutput};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS edges (
+        epoch INTEGER NOT NULL,
+        src_worker INTEGER NOT NULL,
+        src_timestamp INTEGER NOT NULL,
+        dst_worker INTEGER NOT NULL,
+        dst_timestamp INTEGER NOT NULL,
+        activity TEXT NOT NULL,
+        operator TEXT,
+        weight INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS bc (
+        epoch INTEGER NOT NULL,
+        src_worker INTEGER NOT NULL,
+        src_timestamp INTEGER NOT NULL,
+        dst_worker INTEGER NOT NULL,
+        dst_timestamp INTEGER NOT NULL,
+        activity TEXT NOT NULL,
+        bc REAL NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS summary (
+        epoch INTEGER NOT NULL,
+        activity TEXT NOT NULL,
+        operator INTEGER NOT NULL,
+        src INTEGER NOT NULL,
+        dst INTEGER NOT NULL,
+        crosses TEXT NOT NULL,
+        bc REAL NOT NULL,
+        weighted_bc REAL NOT NULL,
+        count INTEGER NOT NULL,
+        weight INTEGER NOT NULL
+    );";
+
+/// A SQLite database holding the `edges`, `bc` and `summary` tables.
+pub struct SqliteOutput {
+    connection: Connection,
+}
+
+/// A database shared with the operators writing to it, see `Config::sqlite_output`.
+pub type SharedSqliteOutput = Arc<Mutex<SqliteOutput>>;
+
+/// Backoff before the first retry of a failed insert (see `SqliteOutput::insert_with_retries`).
+const RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+fn nanos(duration: &Duration) -> i64 {
+    duration.as_nanos() as i64
+}
+
+impl SqliteOutput {
+    /// Opens (or creates) the database at `path` and creates the tables if necessary.
+    pub fn create(path: &str) -> rusqlite::Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(SqliteOutput { connection })
+    }
+
+    /// Runs `insert` for the rows of `epoch` in `table` and retries it up to `retries` times,
+    /// e.g. while another process holds a lock on the database. The transaction of a failed
+    /// attempt is rolled back, so an insert that still fails after all retries is reported and
+    /// the epoch's rows are skipped rather than aborting the analysis.
+    pub fn insert_with_retries<F>(&mut self, table: &str, epoch: &Duration, retries: u32, mut insert: F)
+        where F: FnMut(&mut SqliteOutput) -> rusqlite::Result<()>
+    {
+        let mut backoff = RETRY_BACKOFF;
+        let mut attempt = 0;
+        loop {
+            match insert(self) {
+                Ok(()) => return,
+                Err(ref why) if attempt < retries => {
+                    eprintln!("WARNING couldn't insert into {} ({:?}), retrying in {:?}", table, why, backoff);
+                    std::thread::sleep(backoff);
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                Err(why) => {
+                    eprintln!("ERROR couldn't insert into {} after {} retries, skipping epoch {:?}: {:?}",
+                              table,
+                              retries,
+                              epoch,
+                              why);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Inserts the edges of an epoch's PAG.
+    pub fn insert_edges(&mut self, epoch: &Duration, edges: &[PagEdge]) -> rusqlite::Result<()> {
+        let transaction = self.connection.transaction()?;
+        {
+            let mut statement = transaction.prepare_cached(
+                "INSERT INTO edges VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)")?;
+            for edge in edges {
+                statement.execute(params![nanos(epoch),
+                                          edge.source.worker_id as i64,
+                                          nanos(&edge.source.timestamp),
+                                          edge.destination.worker_id as i64,
+                                          nanos(&edge.destination.timestamp),
+                                          edge.edge_type.name(),
+                                          edge.operator_address.as_ref().map(|a| a.to_string()),
+                                          edge.weight() as i64])?;
+            }
+        }
+        transaction.commit()
+    }
+
+    /// Inserts the BC of an epoch's edges.
+    pub fn insert_bc(&mut self, epoch: &Duration, bc: &[(PagOutput, f64)]) -> rusqlite::Result<()> {
+        let transaction = self.connection.transaction()?;
+        {
+            let mut statement = transaction.prepare_cached(
+                "INSERT INTO bc VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)")?;
+            for &(ref output, bc) in bc {
+                if let PagOutput::Edge(ref edge) = *output {
+                    statement.execute(params![nanos(epoch),
+                                              edge.source.worker_id as i64,
+                                              nanos(&edge.source.timestamp),
+                                              edge.destination.worker_id as i64,
+                                              nanos(&edge.destination.timestamp),
+                                              edge.edge_type.name(),
+                                              bc])?;
+                }
+            }
+        }
+        transaction.commit()
+    }
+
+    /// Inserts an epoch's summary rows.
+    pub fn insert_summary(&mut self, epoch: &Duration, rows: &[SummaryRow]) -> rusqlite::Result<()> {
+        let transaction = self.connection.transaction()?;
+        {
+            let mut statement = transaction.prepare_cached(
+                "INSERT INTO summary VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)")?;
+            for row in rows {
+                statement.execute(params![nanos(epoch),
+                                          row.activity_type.name(),
+                                          row.operator_id as i64,
+                                          row.src as i64,
+                                          row.dst as i64,
+                                          row.crosses.to_string(),
+                                          row.bc,
+                                          row.weighted_bc,
+                                          row.count as i64,
+                                          row.weight as i64])?;
+            }
+        }
+        transaction.commit()
+    }
+}
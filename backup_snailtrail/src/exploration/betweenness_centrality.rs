@@ -23,6 +23,7 @@ use timely::dataflow::operators::aggregation::Aggregate;
 use crate::hash_code;
 use crate::exploration::Capacity;
 use crate::exploration::groupexplore::GroupExplore;
+use crate::exploration::spill::SpillConfig;
 use crate::graph::{SrcDst, Partitioning};
 
 
@@ -93,6 +94,18 @@ pub trait BetweennessCentrality<G, N, D1>
                                      -> Stream<G, (D1, DO)>
         where E: Capacity<D1, DO>,
               DO: ExchangeData + AddAssign + Debug + Copy + Default + Mul + PartialOrd + ScaleReduce;
+
+    /// Like `betweenness_centrality`, but spills the per-epoch traversal state to disk according
+    /// to `spill`. This trades speed for the ability to finish very large epochs without
+    /// running out of memory.
+    fn betweenness_centrality_spilling<E, DO>(&self,
+                                              forward_edges: &Stream<G, (D1, DO)>,
+                                              backward_edges: &Stream<G, (D1, DO)>,
+                                              name: &str,
+                                              spill: Option<SpillConfig>)
+                                              -> Stream<G, (D1, DO)>
+        where E: Capacity<D1, DO>,
+              DO: ExchangeData + AddAssign + Debug + Copy + Default + Mul + PartialOrd + ScaleReduce;
 }
 
 impl<G, N, D1> BetweennessCentrality<G, N, D1> for Stream<G, D1>
@@ -107,6 +120,18 @@ impl<G, N, D1> BetweennessCentrality<G, N, D1> for Stream<G, D1>
                                      -> Stream<G, (D1, DO)>
         where E: Capacity<D1, DO>,
               DO: ExchangeData + AddAssign + Debug + Copy + Default + Mul + PartialOrd + ScaleReduce
+    {
+        self.betweenness_centrality_spilling::<E, DO>(forward_edges, backward_edges, name, None)
+    }
+
+    fn betweenness_centrality_spilling<E, DO>(&self,
+                                              forward_edges: &Stream<G, (D1, DO)>,
+                                              backward_edges: &Stream<G, (D1, DO)>,
+                                              name: &str,
+                                              spill: Option<SpillConfig>)
+                                              -> Stream<G, (D1, DO)>
+        where E: Capacity<D1, DO>,
+              DO: ExchangeData + AddAssign + Debug + Copy + Default + Mul + PartialOrd + ScaleReduce
     {
         let forward_edges = forward_edges.push_time().exchange(|x| x.0.as_nanos() as u64).map(|x| x.1);
         let backward_edges = backward_edges.push_time().exchange(|x| x.0.as_nanos() as u64).map(|x| x.1);
@@ -115,17 +140,19 @@ impl<G, N, D1> BetweennessCentrality<G, N, D1> for Stream<G, D1>
         let graph_stream_fwd = graph_stream.concat(&forward_edges.map(|(e, _)| e));
         let graph_stream_bwd = graph_stream.concat(&backward_edges.map(|(e, _)| e));
 
-        let output = graph_stream_fwd.group_explore::<E, _, _>(&forward_edges,
-                                                               format!("{} Forward", name)
-                                                                   .as_str(),
-                                                               |e| e.src(),
-                                                               |e| e.dst());
-
-        let output2 = graph_stream_bwd.group_explore::<E, _, _>(&backward_edges,
-                                                                format!("{} Backward", name)
-                                                                    .as_str(),
-                                                                |e| e.dst(),
-                                                                |e| e.src());
+        let output = graph_stream_fwd.group_explore_spilling::<E, _, _>(&forward_edges,
+                                                                        format!("{} Forward", name)
+                                                                            .as_str(),
+                                                                        |e| e.src(),
+                                                                        |e| e.dst(),
+                                                                        spill.clone());
+
+        let output2 = graph_stream_bwd.group_explore_spilling::<E, _, _>(&backward_edges,
+                                                                         format!("{} Backward", name)
+                                                                             .as_str(),
+                                                                         |e| e.dst(),
+                                                                         |e| e.src(),
+                                                                         spill);
 
         // concatenate the two outputs
         let combined = output.concat(&output2);
@@ -0,0 +1,154 @@
+// Copyright 2017 ETH Zurich. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Critical path computation traits.
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Debug;
+use std::time::Duration;
+
+use timely::ExchangeData;
+use timely::dataflow::{Stream, Scope};
+use timely::dataflow::channels::pact::Pipeline;
+use timely::dataflow::operators::*;
+use timely::dataflow::operators::generic::operator::Operator;
+
+use crate::exploration::betweenness_centrality::{ExtendedData, PushTime};
+use crate::graph::{SrcDst, Partitioning, Weight};
+
+/// Compute the critical path of a generic, acyclic graph.
+pub trait CriticalPath<G, N, D1>
+    where G: Scope<Timestamp = Duration>,
+          N: ExtendedData + Partitioning + Ord,
+          D1: SrcDst<N> + Weight + ExchangeData + Ord
+{
+    /// Compute the maximum-weight path from the destination of a start node to the source of an
+    /// end node, once per epoch.
+    ///
+    /// The path is emitted as its edges in order. Ties are broken towards the smaller edge, so
+    /// the same window always yields the same path. Epochs without a connected start and end
+    /// node emit nothing.
+    fn critical_path(&self, start_nodes: &Stream<G, D1>, end_nodes: &Stream<G, D1>) -> Stream<G, Vec<D1>>;
+}
+
+/// Returns the maximum-weight path through `edges` from any of `starts` to any of `ends`.
+/// `edges` must be sorted and acyclic; edges on a cycle are never part of the path.
+fn longest_path<N, D1>(edges: &[D1], starts: &BTreeSet<N>, ends: &BTreeSet<N>) -> Option<Vec<D1>>
+    where N: Partitioning + Ord + Copy,
+          D1: SrcDst<N> + Weight + Clone
+{
+    let mut outgoing = BTreeMap::new();
+    let mut in_degree = BTreeMap::new();
+    for (index, edge) in edges.iter().enumerate() {
+        let (src, dst) = (edge.src().unwrap(), edge.dst().unwrap());
+        outgoing.entry(src).or_insert_with(Vec::new).push(index);
+        in_degree.entry(src).or_insert(0);
+        *in_degree.entry(dst).or_insert(0) += 1;
+    }
+
+    // Visit nodes in topological order, keeping the longest distance from a start node and the
+    // edge it was reached by
+    let mut ready: BTreeSet<N> = in_degree.iter().filter(|&(_, &d)| d == 0).map(|(&n, _)| n).collect();
+    let mut distance: HashMap<N, (u64, Option<usize>)> = HashMap::new();
+    let mut best: Option<(u64, N)> = None;
+    while let Some(&node) = ready.iter().next() {
+        ready.remove(&node);
+        if starts.contains(&node) {
+            distance.entry(node).or_insert((0, None));
+        }
+        let reached = distance.get(&node).cloned();
+        if let Some((length, _)) = reached {
+            if ends.contains(&node) && best.map_or(true, |(b, _)| length > b) {
+                best = Some((length, node));
+            }
+        }
+        for &index in outgoing.get(&node).map_or(&[][..], |edges| &edges[..]) {
+            let dst = edges[index].dst().unwrap();
+            if let Some((length, _)) = reached {
+                let length = length + edges[index].weight();
+                let entry = distance.entry(dst).or_insert((length, Some(index)));
+                if length > entry.0 {
+                    *entry = (length, Some(index));
+                }
+            }
+            let degree = in_degree.get_mut(&dst).unwrap();
+            *degree -= 1;
+            if *degree == 0 {
+                ready.insert(dst);
+            }
+        }
+    }
+
+    let (_, mut node) = best?;
+    let mut path = Vec::new();
+    while let Some(&(_, Some(index))) = distance.get(&node) {
+        path.push(edges[index].clone());
+        node = edges[index].src().unwrap();
+    }
+    path.reverse();
+    if path.is_empty() { None } else { Some(path) }
+}
+
+/// Computes the critical path of every epoch once it is complete. Each epoch is processed on a
+/// single worker.
+impl<G, N, D1> CriticalPath<G, N, D1> for Stream<G, D1>
+    where G: Scope<Timestamp = Duration>,
+          N: ExtendedData + Partitioning + Ord,
+          D1: SrcDst<N> + Weight + ExchangeData + Ord + Debug
+{
+    fn critical_path(&self, start_nodes: &Stream<G, D1>, end_nodes: &Stream<G, D1>) -> Stream<G, Vec<D1>> {
+        let entry_points = start_nodes.map(|e| (true, e)).concat(&end_nodes.map(|e| (false, e)));
+        let entry_points = entry_points.push_time().exchange(|x| x.0.as_nanos() as u64).map(|x| x.1);
+        let graph_stream = self.push_time().exchange(|x| x.0.as_nanos() as u64).map(|x| x.1);
+
+        let mut graph_stash = HashMap::new();
+        let mut entry_stash = HashMap::new();
+        let mut graph_vector = Vec::new();
+        let mut entry_vector = Vec::new();
+
+        graph_stream.binary_notify(&entry_points,
+                                   Pipeline,
+                                   Pipeline,
+                                   "CriticalPath",
+                                   vec![],
+                                   move |graph, entries, output, notificator| {
+            graph.for_each(|time, data| {
+                data.swap(&mut graph_vector);
+                graph_stash.entry(time.time().clone())
+                    .or_insert_with(Vec::new)
+                    .extend(graph_vector.drain(..).filter(|e: &D1| e.src().is_some() && e.dst().is_some()));
+                notificator.notify_at(time.retain());
+            });
+
+            entries.for_each(|time, data| {
+                data.swap(&mut entry_vector);
+                entry_stash.entry(time.time().clone())
+                    .or_insert_with(Vec::new)
+                    .extend(entry_vector.drain(..));
+                notificator.notify_at(time.retain());
+            });
+
+            notificator.for_each(|time, _, _| {
+                let mut edges = graph_stash.remove(time.time()).unwrap_or_default();
+                edges.sort();
+                let mut starts = BTreeSet::new();
+                let mut ends = BTreeSet::new();
+                for (start, entry) in entry_stash.remove(time.time()).unwrap_or_default() {
+                    if start {
+                        starts.extend(entry.dst());
+                    } else {
+                        ends.extend(entry.src());
+                    }
+                }
+
+                if let Some(path) = longest_path(&edges, &starts, &ends) {
+                    output.session(&time).give(path);
+                }
+            });
+        })
+    }
+}
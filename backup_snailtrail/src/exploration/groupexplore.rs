@@ -21,6 +21,7 @@ use timely::dataflow::channels::pact::Pipeline;
 
 use crate::graph::Partitioning;
 use crate::exploration::Capacity;
+use crate::exploration::spill::{EpochSpill, SpillConfig};
 
 /// A trait defining an interface to explore a graph.
 pub trait GroupExplore<G: Scope,
@@ -46,6 +47,23 @@ pub trait GroupExplore<G: Scope,
               LG: Fn(&D1) -> Option<K> + 'static,
               LJ: Fn(&D1) -> Option<K> + 'static,
               E: Capacity<D1, DO>;
+
+    /// Like `group_explore`, but buffers each epoch's graph in an `EpochSpill` which writes
+    /// pending epochs to disk once `spill` is `Some` and its threshold is crossed.
+    ///
+    /// Only pending epochs are spilled. The epoch being traversed is indexed in memory, so a
+    /// single epoch's graph must still fit in memory.
+    fn group_explore_spilling<E, LG, LJ>(&self,
+                                         frontier_stream: &Stream<G, (D1, DO)>,
+                                         name: &str,
+                                         group: LG,
+                                         join: LJ,
+                                         spill: Option<SpillConfig>)
+                                         -> Stream<G, (D1, DO)>
+        where G::Timestamp: Hash,
+              LG: Fn(&D1) -> Option<K> + 'static,
+              LJ: Fn(&D1) -> Option<K> + 'static,
+              E: Capacity<D1, DO>;
 }
 
 #[derive(Debug)]
@@ -84,6 +102,11 @@ impl<'a, D, DO: Default> NodeInfo<D, DO> {
 /// with edges on the `frontier_stream`. The edges are stored in a queue to determine which nodes
 /// still need to be visited.
 ///
+/// Graph edges are buffered per epoch until the epoch is complete. With `group_explore_spilling`,
+/// buffered epochs are written to disk once the configured threshold is crossed and only read
+/// back when the epoch is traversed. The traversal indexes the whole epoch in memory; the
+/// outgoing edges of a node are released once they have been explored.
+///
 /// When visiting a node, two things can happen. Firstly, the count of pending in-edges is bigger
 /// than one. In this case, the input capacity is accumulated and the pending edges count is
 /// decreased. Secondly, the count of pending in-edges is one. In this case, the same as in the
@@ -108,11 +131,25 @@ impl<G, D1, DO, K> GroupExplore<G, D1, DO, K> for Stream<G, D1>
               LJ: Fn(&D1) -> Option<K> + 'static,
               E: Capacity<D1, DO>
     {
+        self.group_explore_spilling::<E, _, _>(frontier_stream, name, group, join, None)
+    }
+
+    fn group_explore_spilling<E, LG, LJ>(&self,
+                                         frontier_stream: &Stream<G, (D1, DO)>,
+                                         name: &str,
+                                         group: LG,
+                                         join: LJ,
+                                         spill: Option<SpillConfig>)
+                                         -> Stream<G, (D1, DO)>
+        where LG: Fn(&D1) -> Option<K> + 'static,
+              LJ: Fn(&D1) -> Option<K> + 'static,
+              E: Capacity<D1, DO>
+    {
 
         let join = Rc::new(join);
         let group = Rc::new(group);
 
-        let mut node_data = HashMap::new();
+        let mut graph_stash = EpochSpill::new(spill);
         let mut frontier_stash = HashMap::new();
 
         let stream: &Stream<G, D1> = self;
@@ -127,30 +164,9 @@ impl<G, D1, DO, K> GroupExplore<G, D1, DO, K> for Stream<G, D1>
                              vec![],
                              move |graph, frontier, output, notificator| {
             graph.for_each(|time, data| {
-                let t = time.time().clone();
                 data.swap(&mut graph_vector);
-                let time = time.retain();
-                for datum in graph_vector.drain(..) {
-                    if let Some(j) = join(&datum) {
-                        let node_info = node_data
-                            .entry(t.clone())
-                            .or_insert_with(HashMap::new)
-                            .entry(j)
-                            .or_insert_with(NodeInfo::new);
-                        node_info.in_degree += 1;
-                        // println!("Increasing in_degree: {:?} {:?}", j, node_info);
-                    }
-                    if let Some(g) = group(&datum) {
-                        let node_info = node_data
-                            .entry(t.clone())
-                            .or_insert_with(HashMap::new)
-                            .entry(g)
-                            .or_insert_with(NodeInfo::new);
-                        node_info.outgoing.push(datum.clone());
-                        // println!("Adding outgoing     : {:?} {:?}", g, node_info);
-                    }
-                    notificator.notify_at(time.clone());
-                }
+                graph_stash.extend(time.time(), graph_vector.drain(..));
+                notificator.notify_at(time.retain());
             });
 
             frontier.for_each(|time, data| {
@@ -172,35 +188,47 @@ impl<G, D1, DO, K> GroupExplore<G, D1, DO, K> for Stream<G, D1>
                 let t = time.time();
                 let mut frontier_edges = frontier_stash.remove(t).unwrap_or_default();
                 assert!(frontier_edges.len() < 100_000_000);
-                if let Some(data) = node_data.get_mut(t) {
-                    while let Some(datum) = frontier_edges.pop_front() {
-                        // let datum: Edge = datum;
-                        if let Some(j) = join(&datum.0) {
-                            // j is source of edge
-                            if let Some(node_info) = data.get_mut(&j) {
-                                // data is meta data for j
-                                // println!("Joining: {:?}", node_info);
-                                assert!(node_info.in_degree > 0,
-                                        "node_info: {:?} datum: {:?}",
-                                        node_info,
-                                        datum);
-                                node_info.in_sum += datum.1;
-                                node_info.in_degree -= 1;
-
-                                if node_info.in_degree == 0 {
-                                    for o in node_info.outgoing.clone() {
-                                        let in_sum = <E>::apply_capacity(&o, node_info.in_sum);
-                                        session.give((o.clone(), in_sum));
-                                        frontier_edges.push_back((o, in_sum));
-                                    }
+
+                // Index the epoch's graph by node
+                let mut node_data = HashMap::new();
+                graph_stash.take_each(t, |datum| {
+                    if let Some(j) = join(&datum) {
+                        let node_info = node_data.entry(j).or_insert_with(NodeInfo::new);
+                        node_info.in_degree += 1;
+                        // println!("Increasing in_degree: {:?} {:?}", j, node_info);
+                    }
+                    if let Some(g) = group(&datum) {
+                        let node_info = node_data.entry(g).or_insert_with(NodeInfo::new);
+                        node_info.outgoing.push(datum);
+                        // println!("Adding outgoing     : {:?} {:?}", g, node_info);
+                    }
+                });
+                while let Some(datum) = frontier_edges.pop_front() {
+                    // let datum: Edge = datum;
+                    if let Some(j) = join(&datum.0) {
+                        // j is source of edge
+                        if let Some(node_info) = node_data.get_mut(&j) {
+                            // data is meta data for j
+                            // println!("Joining: {:?}", node_info);
+                            assert!(node_info.in_degree > 0,
+                                    "node_info: {:?} datum: {:?}",
+                                    node_info,
+                                    datum);
+                            node_info.in_sum += datum.1;
+                            node_info.in_degree -= 1;
+
+                            if node_info.in_degree == 0 {
+                                // A node is only explored once, its edges are not needed anymore
+                                for o in std::mem::replace(&mut node_info.outgoing, Vec::new()) {
+                                    let in_sum = <E>::apply_capacity(&o, node_info.in_sum);
+                                    session.give((o.clone(), in_sum));
+                                    frontier_edges.push_back((o, in_sum));
                                 }
                             }
                         }
-
                     }
+
                 }
-                // cleanup
-                node_data.remove(time.time());
             });
         })
     }
@@ -0,0 +1,173 @@
+// Copyright 2017 ETH Zurich. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Harmonic centrality computation traits.
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::Hash;
+use std::fmt::Debug;
+use std::ops::AddAssign;
+use std::cmp::PartialOrd;
+use std::time::Duration;
+
+use timely::Data;
+use timely::ExchangeData;
+use timely::dataflow::{Stream, Scope};
+use timely::dataflow::channels::pact::Pipeline;
+use timely::dataflow::operators::*;
+use timely::dataflow::operators::generic::operator::Operator;
+
+use crate::exploration::Capacity;
+use crate::exploration::betweenness_centrality::{ExtendedData, PushTime};
+use crate::graph::{SrcDst, Partitioning};
+
+/// Trait describing the contribution `self / distance` of an entry point at `distance` edges.
+pub trait Harmonic {
+    /// Scale the weight `self` of an entry point by the reciprocal of `distance`.
+    fn harmonic(self, distance: usize) -> Self;
+}
+
+impl Harmonic for f64 {
+    fn harmonic(self, distance: usize) -> Self {
+        self / distance as f64
+    }
+}
+
+/// Compute the edge harmonic centrality for a generic graph.
+pub trait HarmonicCentrality<G, N, D1>
+    where G: Scope<Timestamp = Duration>,
+          N: ExtendedData + Partitioning,
+          D1: SrcDst<N> + Data + Eq + Hash
+{
+    /// Compute a stream of `(edge, centrality)` for a stream of forward and backward entry points
+    ///
+    /// The centrality of an edge is the sum of `weight / distance` over the forward entry points
+    /// it can be reached from and the backward entry points that can be reached from it, where
+    /// `distance` is the number of edges on the shortest such path including the edge itself.
+    /// Entry points which are not connected to an edge do not contribute to it, so unlike
+    /// `BetweennessCentrality` this does not require every edge to lie on a complete path.
+    ///
+    /// Edges that `E` gives no capacity are neither scored nor traversed.
+    fn harmonic_centrality<E, DO>(&self,
+                                  forward_edges: &Stream<G, (D1, DO)>,
+                                  backward_edges: &Stream<G, (D1, DO)>,
+                                  name: &str)
+                                  -> Stream<G, (D1, DO)>
+        where E: Capacity<D1, DO>,
+              DO: ExchangeData + AddAssign + Debug + Copy + Default + PartialOrd + Harmonic;
+}
+
+/// Adds `weight.harmonic(distance)` to every edge by breadth-first search from `start`, following
+/// the edges in `adjacent` to the node returned by `next`.
+fn explore<N, D1, DO, E, F>(edges: &[D1],
+                            adjacent: &HashMap<N, Vec<usize>>,
+                            start: N,
+                            weight: DO,
+                            next: F,
+                            centrality: &mut [DO])
+    where N: Hash + Eq + Copy,
+          DO: AddAssign + Copy + Default + PartialOrd + Harmonic,
+          E: Capacity<D1, DO>,
+          F: Fn(&D1) -> Option<N>
+{
+    let mut visited = HashSet::new();
+    let mut queue = VecDeque::new();
+    visited.insert(start);
+    queue.push_back((start, 0));
+    while let Some((node, distance)) = queue.pop_front() {
+        for &index in adjacent.get(&node).map_or(&[][..], |edges| &edges[..]) {
+            if !(E::apply_capacity(&edges[index], weight) > DO::default()) {
+                continue;
+            }
+            centrality[index] += weight.harmonic(distance + 1);
+            if let Some(other) = next(&edges[index]) {
+                if visited.insert(other) {
+                    queue.push_back((other, distance + 1));
+                }
+            }
+        }
+    }
+}
+
+/// Computes the harmonic centrality of every epoch once it is complete. Each epoch is processed
+/// on a single worker, with a breadth-first search per entry point.
+impl<G, N, D1> HarmonicCentrality<G, N, D1> for Stream<G, D1>
+    where G: Scope<Timestamp = Duration>,
+          N: ExtendedData + Partitioning,
+          D1: SrcDst<N> + Data + Eq + Hash + Debug + Send + ExchangeData
+{
+    fn harmonic_centrality<E, DO>(&self,
+                                  forward_edges: &Stream<G, (D1, DO)>,
+                                  backward_edges: &Stream<G, (D1, DO)>,
+                                  name: &str)
+                                  -> Stream<G, (D1, DO)>
+        where E: Capacity<D1, DO>,
+              DO: ExchangeData + AddAssign + Debug + Copy + Default + PartialOrd + Harmonic
+    {
+        let entry_points = forward_edges.map(|(e, weight)| (true, e, weight))
+            .concat(&backward_edges.map(|(e, weight)| (false, e, weight)));
+        let entry_points = entry_points.push_time().exchange(|x| x.0.as_nanos() as u64).map(|x| x.1);
+        let graph_stream = self.push_time().exchange(|x| x.0.as_nanos() as u64).map(|x| x.1);
+
+        let mut graph_stash = HashMap::new();
+        let mut entry_stash = HashMap::new();
+        let mut graph_vector = Vec::new();
+        let mut entry_vector = Vec::new();
+
+        graph_stream.binary_notify(&entry_points,
+                                   Pipeline,
+                                   Pipeline,
+                                   name,
+                                   vec![],
+                                   move |graph, entries, output, notificator| {
+            graph.for_each(|time, data| {
+                data.swap(&mut graph_vector);
+                graph_stash.entry(time.time().clone())
+                    .or_insert_with(Vec::new)
+                    .extend(graph_vector.drain(..).filter(|e: &D1| e.src().is_some() && e.dst().is_some()));
+                notificator.notify_at(time.retain());
+            });
+
+            entries.for_each(|time, data| {
+                data.swap(&mut entry_vector);
+                entry_stash.entry(time.time().clone())
+                    .or_insert_with(Vec::new)
+                    .extend(entry_vector.drain(..));
+                notificator.notify_at(time.retain());
+            });
+
+            notificator.for_each(|time, _, _| {
+                let edges = graph_stash.remove(time.time()).unwrap_or_default();
+                let entries = entry_stash.remove(time.time()).unwrap_or_default();
+
+                // Index the epoch's graph by node in both directions
+                let mut outgoing = HashMap::new();
+                let mut incoming = HashMap::new();
+                for (index, edge) in edges.iter().enumerate() {
+                    outgoing.entry(edge.src().unwrap()).or_insert_with(Vec::new).push(index);
+                    incoming.entry(edge.dst().unwrap()).or_insert_with(Vec::new).push(index);
+                }
+
+                let mut centrality = vec![DO::default(); edges.len()];
+                for (forward, entry, weight) in entries {
+                    if !(weight > DO::default()) {
+                        continue;
+                    }
+                    if forward {
+                        if let Some(start) = entry.dst() {
+                            explore::<_, _, _, E, _>(&edges, &outgoing, start, weight, |e| e.dst(), &mut centrality);
+                        }
+                    } else if let Some(start) = entry.src() {
+                        explore::<_, _, _, E, _>(&edges, &incoming, start, weight, |e| e.src(), &mut centrality);
+                    }
+                }
+
+                output.session(&time).give_iterator(edges.into_iter().zip(centrality.into_iter()));
+            });
+        })
+    }
+}
@@ -16,12 +16,18 @@
 extern crate rand;
 
 pub mod betweenness_centrality;
+pub mod critical_path;
 pub mod groupexplore;
+pub mod harmonic_centrality;
 pub mod single_path;
+pub mod spill;
 
 pub use self::betweenness_centrality::BetweennessCentrality;
-pub use self::single_path::SinglePath;
+pub use self::critical_path::CriticalPath;
+pub use self::single_path::{MaxWeightPath, SinglePath};
 pub use self::groupexplore::GroupExplore;
+pub use self::harmonic_centrality::HarmonicCentrality;
+pub use self::spill::SpillConfig;
 
 /// A trait that describes the capacity of an edge.
 ///
@@ -22,7 +22,7 @@ use timely::ExchangeData;
 
 use crate::exploration::rand::prelude::SliceRandom;
 use crate::exploration::rand::thread_rng;
-use crate::graph::{Partitioning, SrcDst};
+use crate::graph::{Partitioning, SrcDst, Weight};
 
 
 pub trait ExtendedData: Data + Eq + Hash + Copy + Debug {}
@@ -54,7 +54,8 @@ let graph_stream = self; //.concat(&forward_edges); //.concat(&backward_edges);
 
             let seed = edge.enter(inner).concat(&cycle);
 
-            let output = graph_stream.enter(inner).traverse_from(&seed,|e| e.src(),|e| e.dst(),|t| &t.outer);
+            let output = graph_stream.enter(inner).traverse_from(&seed,|e| e.src(),|e| e.dst(),|t| &t.outer,
+                                                                 |next_edges| next_edges.choose(&mut thread_rng()).cloned());
 
             output.connect_loop(helper);
             output.leave()
@@ -70,25 +71,63 @@ let graph_stream = self; //.concat(&forward_edges); //.concat(&backward_edges);
     }
 }
 
+pub trait MaxWeightPath<G: Scope, N: ExtendedData + Partitioning, D1: SrcDst<N> + Weight + Data + Eq + Hash + abomonation::Abomonation>
+     {
+    /// Traverses the heaviest path in a graph starting from a seed node: like `single_path`, but
+    /// at every node it follows the outgoing edge with the greatest `weight()`. Ties are broken
+    /// by the earliest destination and then by the edge order, so the path is deterministic.
+    fn max_weight_path(&self, edge: &Stream<G, D1>) -> Stream<G, D1> where G::Timestamp: Hash + Copy;
+}
+
+impl<G: Scope, N: ExtendedData + Partitioning + Ord, D1: SrcDst<N> + Weight + Data + Eq + Hash + Ord + Debug + Send + std::marker::Sync + abomonation::Abomonation> MaxWeightPath<G, N, D1> for Stream<G, D1> {
+    fn max_weight_path(&self, edge: &Stream<G, D1>) -> Stream<G, D1>
+        where G::Timestamp: Hash+Copy
+    {
+        let graph_stream = self;
+
+        self.scope().scoped("traversal", |inner| {
+            let (helper, cycle) = inner.loop_variable(1);
+
+            let seed = edge.enter(inner).concat(&cycle);
+
+            let output = graph_stream.enter(inner).traverse_from(&seed,|e| e.src(),|e| e.dst(),|t| &t.outer, |next_edges| {
+                next_edges.iter()
+                    .max_by(|a, b| {
+                        a.weight().cmp(&b.weight())
+                            .then_with(|| b.dst().cmp(&a.dst()))
+                            .then_with(|| b.cmp(a))
+                    })
+                    .cloned()
+            });
+
+            output.connect_loop(helper);
+            output.leave()
+        })
+    }
+}
+
 pub trait TraverseFrom<G: Scope, D1: ExchangeData, K: Hash + Eq + Copy + Data + Partitioning + 'static>
      {
-    /// Explores a graph iteratively based on a frontier stream.
+    /// Explores a graph iteratively based on a frontier stream. From every seed, `choose` picks
+    /// the next edge to visit among the edges joining it.
     ///
     /// #Examples
     ///
     /// ```
     /// ```
-    fn traverse_from<LG, LJ, TO, TS>(&self,
-                                     seed: &Stream<G, D1>,
-                                     group: LG,
-                                     join: LJ,
-                                     outer: TO)
-                                     -> Stream<G, D1>
+    fn traverse_from<LG, LJ, TO, TS, LC>(&self,
+                                         seed: &Stream<G, D1>,
+                                         group: LG,
+                                         join: LJ,
+                                         outer: TO,
+                                         choose: LC)
+                                         -> Stream<G, D1>
         where G::Timestamp: Hash,
               LG: Fn(&D1) -> Option<K> + 'static,
               LJ: Fn(&D1) -> Option<K> + 'static,
               TO: Fn(&G::Timestamp) -> &TS + 'static,
-              TS: Hash + Eq + Copy + Clone + 'static;
+              TS: Hash + Eq + Copy + Clone + 'static,
+              LC: Fn(&[D1]) -> Option<D1> + 'static;
 }
 
 impl<TOuter: Timestamp,
@@ -96,17 +135,19 @@ impl<TOuter: Timestamp,
      D1: ExchangeData,
      K: Hash + Eq + Copy + Data + Partitioning + Debug + 'static> TraverseFrom<G, D1, K>
     for Stream<G, D1> {
-    fn traverse_from<LG, LJ, TO, TS>(&self,
-                                     seed: &Stream<G, D1>,
-                                     group: LG,
-                                     join: LJ,
-                                     outer: TO)
-                                     -> Stream<G, D1>
+    fn traverse_from<LG, LJ, TO, TS, LC>(&self,
+                                         seed: &Stream<G, D1>,
+                                         group: LG,
+                                         join: LJ,
+                                         outer: TO,
+                                         choose: LC)
+                                         -> Stream<G, D1>
         where G::Timestamp: Hash,
               LG: Fn(&D1) -> Option<K> + 'static,
               LJ: Fn(&D1) -> Option<K> + 'static,
               TO: Fn(&G::Timestamp) -> &TS + 'static,
-              TS: Hash + Eq + Copy + Clone + 'static
+              TS: Hash + Eq + Copy + Clone + 'static,
+              LC: Fn(&[D1]) -> Option<D1> + 'static
     {
         // Local state
         let mut snapshots = HashMap::new();
@@ -159,14 +200,9 @@ impl<TOuter: Timestamp,
                             // Always assume a single initial seed
                             assert_eq!(epoch_seeds.len(), 1);
                             for seed in epoch_seeds.drain(..) {
-                                // Pick a next edge to visit at random
+                                // Pick a next edge to visit
                                 if let Some(next_edges) = snapshot.get(&join(&seed)) {
-                                    //.expect("No edges found.");
-                                    let mut rng = thread_rng();
-                                    let next = next_edges[..]
-                                        .choose(&mut rng)
-                                        .expect("No edges found")
-                                        .clone(); //next_edges[0].clone();
+                                    let next = choose(&next_edges[..]).expect("No edges found");
                                     session.give(next);
                                 }
                             }
@@ -0,0 +1,184 @@
+// Copyright 2017 ETH Zurich. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Per-epoch buffers that spill to disk once they grow too large.
+//!
+//! Traversal operators have to buffer a whole epoch's graph before they can explore it. For
+//! the rare giant epoch (or many epochs in flight) this exhausts memory. An `EpochSpill` keeps
+//! buffered data in memory until a configurable number of elements is exceeded and then
+//! appends the largest in-memory buffer to a file on disk. The data is read back when the
+//! epoch is taken out of the buffer, i.e. when it is about to be traversed.
+//!
+//! Only epochs that are still waiting are spilled: the epoch being traversed has to fit in
+//! memory, as the traversal indexes all of its data. `EpochSpill::take_each` reads it back one
+//! spilled batch at a time so that it is not held twice while it is indexed.
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::hash::Hash;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use abomonation::{decode, encode, Abomonation};
+
+/// Distinguishes spill files of different operators within a process.
+static SPILL_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Configures when and where per-epoch state is spilled to disk.
+#[derive(Clone, Debug)]
+pub struct SpillConfig {
+    /// Number of buffered elements (summed over all epochs) above which data is spilled.
+    pub threshold: usize,
+    /// Directory to write spill files to.
+    pub directory: PathBuf,
+}
+
+impl SpillConfig {
+    /// Spill once more than `threshold` elements are buffered, using the system's temporary
+    /// directory.
+    pub fn new(threshold: usize) -> Self {
+        SpillConfig {
+            threshold,
+            directory: std::env::temp_dir().join("snailtrail-spill"),
+        }
+    }
+}
+
+/// A map from epochs to buffered data which optionally spills to disk.
+pub struct EpochSpill<T: Eq + Hash, D: Abomonation + Clone> {
+    config: Option<SpillConfig>,
+    id: usize,
+    in_memory: HashMap<T, Vec<D>>,
+    in_memory_len: usize,
+    spilled: HashMap<T, PathBuf>,
+    next_file: usize,
+}
+
+impl<T: Eq + Hash + Clone, D: Abomonation + Clone> EpochSpill<T, D> {
+    /// Constructs a new buffer. If `config` is `None`, data is never spilled.
+    pub fn new(config: Option<SpillConfig>) -> Self {
+        EpochSpill {
+            config,
+            id: SPILL_ID.fetch_add(1, Ordering::SeqCst),
+            in_memory: HashMap::new(),
+            in_memory_len: 0,
+            spilled: HashMap::new(),
+            next_file: 0,
+        }
+    }
+
+    /// Buffers `data` for epoch `time`, spilling to disk if the threshold is exceeded.
+    pub fn extend<I: IntoIterator<Item = D>>(&mut self, time: &T, data: I) {
+        let slot = self.in_memory.entry(time.clone()).or_insert_with(Vec::new);
+        let before = slot.len();
+        slot.extend(data);
+        self.in_memory_len += slot.len() - before;
+
+        let threshold = match self.config {
+            Some(ref config) => config.threshold,
+            None => return,
+        };
+        while self.in_memory_len > threshold {
+            // Spill the largest epoch as it frees the most memory per file write
+            let largest = self.in_memory
+                .iter()
+                .max_by_key(|&(_, v)| v.len())
+                .map(|(t, _)| t.clone());
+            match largest {
+                Some(t) => self.spill(&t),
+                None => break,
+            }
+        }
+    }
+
+    /// Removes and returns all data buffered for epoch `time`, including spilled data.
+    pub fn take(&mut self, time: &T) -> Vec<D> {
+        let mut result = Vec::new();
+        self.take_each(time, |datum| result.push(datum));
+        result
+    }
+
+    /// Removes all data buffered for epoch `time` and passes it to `logic`, one element at a
+    /// time. Spilled data is read back one batch at a time.
+    pub fn take_each<F: FnMut(D)>(&mut self, time: &T, mut logic: F) {
+        if let Some(path) = self.spilled.remove(time) {
+            let file = File::open(&path)
+                .unwrap_or_else(|why| panic!("couldn't open spill file {:?}: {:?}", path, why));
+            let mut reader = BufReader::new(file);
+            let mut len_bytes = [0u8; 8];
+            while reader.read_exact(&mut len_bytes).is_ok() {
+                let len = u64::from_le_bytes(len_bytes) as usize;
+                // Abomonation decodes in place, so the bytes have to be aligned for `D`
+                assert!(std::mem::align_of::<D>() <= std::mem::align_of::<u64>(),
+                        "spilled data must not be aligned to more than 8 bytes");
+                let mut words = vec![0u64; (len + 7) / 8];
+                let bytes = unsafe {
+                    std::slice::from_raw_parts_mut(words.as_mut_ptr() as *mut u8, len)
+                };
+                reader.read_exact(bytes).expect("truncated spill file");
+                let (batch, _) = unsafe { decode::<Vec<D>>(bytes) }
+                    .expect("couldn't decode spill file");
+                for datum in batch.iter() {
+                    logic(datum.clone());
+                }
+            }
+            fs::remove_file(&path).unwrap_or_else(|why| {
+                eprintln!("couldn't remove spill file {:?}: {:?}", path, why)
+            });
+        }
+        if let Some(data) = self.in_memory.remove(time) {
+            self.in_memory_len -= data.len();
+            for datum in data {
+                logic(datum);
+            }
+        }
+    }
+
+    /// Appends the in-memory buffer of epoch `time` to its spill file.
+    fn spill(&mut self, time: &T) {
+        let data = match self.in_memory.remove(time) {
+            Some(data) => data,
+            None => return,
+        };
+        self.in_memory_len -= data.len();
+
+        let directory = &self.config.as_ref().expect("spilling is disabled").directory;
+        if !self.spilled.contains_key(time) {
+            fs::DirBuilder::new().recursive(true).create(directory).unwrap();
+            let path = directory.join(format!("spill_{}_{}_{}.bin",
+                                              std::process::id(),
+                                              self.id,
+                                              self.next_file));
+            self.next_file += 1;
+            self.spilled.insert(time.clone(), path);
+        }
+        let path = &self.spilled[time];
+
+        let mut bytes = Vec::new();
+        unsafe { encode(&data, &mut bytes) }.unwrap();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap_or_else(|why| panic!("couldn't open spill file {:?}: {:?}", path, why));
+        let mut writer = BufWriter::new(file);
+        // Dropping the writer would discard errors of the final write
+        writer.write_all(&(bytes.len() as u64).to_le_bytes())
+            .and_then(|()| writer.write_all(&bytes))
+            .and_then(|()| writer.flush())
+            .unwrap_or_else(|why| panic!("couldn't write spill file {:?}: {:?}", path, why));
+    }
+}
+
+impl<T: Eq + Hash, D: Abomonation + Clone> Drop for EpochSpill<T, D> {
+    fn drop(&mut self) {
+        for path in self.spilled.values() {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
@@ -23,6 +23,11 @@ pub trait SrcDst<N: Partitioning> {
     fn dst(&self) -> Option<N>;
 }
 
+/// Something with a weight, e.g. the duration of an edge.
+pub trait Weight {
+    fn weight(&self) -> u64;
+}
+
 impl<N: Partitioning> Partitioning for Option<N> {
     fn partition(&self) -> u64 {
         self.iter().map(Partitioning::partition).sum()
@@ -37,7 +37,7 @@ mod tests {
     use time;
 
     use crate::graph::{SrcDst, Partitioning};
-    use crate::exploration::{UnboundCapacity, BetweennessCentrality};
+    use crate::exploration::{UnboundCapacity, BetweennessCentrality, HarmonicCentrality};
 
     use timely;
     use timely::dataflow::operators::*;
@@ -178,4 +178,52 @@ mod tests {
             .collect();
         assert_eq!(result, expected);
     }
+
+    #[test]
+    fn harmonic_centrality() {
+        let node = |id| Some(Node { id, worker: 0 });
+        let edges = vec![Edge { src: node(1), dst: node(2) },
+                         Edge { src: node(2), dst: node(3) },
+                         Edge { src: node(2), dst: node(4) },
+                         Edge { src: node(3), dst: node(4) },
+                         Edge { src: node(4), dst: node(5) }];
+        let s = Edge { src: None, dst: node(1) };
+        let t = Edge { src: node(5), dst: None };
+
+        let (send, recv) = ::std::sync::mpsc::channel();
+        let send = Arc::new(Mutex::new(send));
+
+        let inputs = edges.clone();
+        ::timely::execute(timely::Configuration::Process(1), move |root| {
+            let send = send.lock().unwrap().clone();
+            let (mut graph_input, mut edge_input, mut edge_input2) = root.dataflow(move |scope| {
+                let (graph_input, graph_stream) = scope.new_input();
+                let (edge_input, forward_stream) = scope.new_input();
+                let (edge_input2, backward_stream) = scope.new_input();
+                graph_stream.harmonic_centrality::<UnboundCapacity, f64>(&forward_stream, &backward_stream, "comp")
+                    .capture_into(send);
+                (graph_input, edge_input, edge_input2)
+            });
+            if root.index() == 0 {
+                for edge in inputs.iter() {
+                    graph_input.send(edge.clone());
+                }
+                edge_input.send((s, 1.));
+                edge_input2.send((t, 1.));
+            }
+            graph_input.close();
+            edge_input.close();
+            edge_input2.close();
+            while root.step() {}
+        }).unwrap();
+        let mut result = recv.extract()[0].1.clone();
+        result.sort_by(|a, b| a.0.cmp(&b.0));
+        // Forward from 1 at distances 1, 2, 2, 3, 3 and backward from 5 at 3, 3, 2, 2, 1
+        let expected = [1. + 1. / 3., 0.5 + 1. / 3., 1., 1. / 3. + 0.5, 1. / 3. + 1.];
+        assert_eq!(result.len(), expected.len());
+        for ((edge, centrality), (expected_edge, expected)) in result.into_iter().zip(edges.into_iter().zip(expected.iter())) {
+            assert_eq!(edge, expected_edge);
+            assert!((centrality - expected).abs() < 1e-9, "{:?}: {} != {}", edge, centrality, expected);
+        }
+    }
 }
@@ -184,6 +184,43 @@ impl ActivityType {
             ActivityType::BusyWaiting => true,
         }
     }
+
+    /// A human-readable name of the activity type
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ActivityType::Input => "Input",
+            ActivityType::Buffer => "Buffer",
+            ActivityType::Scheduling => "Scheduling",
+            ActivityType::Processing => "Processing",
+            ActivityType::BarrierProcessing => "BarrierProcessing",
+            ActivityType::Serialization => "Serialization",
+            ActivityType::Deserialization => "Deserialization",
+            ActivityType::FaultTolerance => "FaultTolerance",
+            ActivityType::ControlMessage => "ControlMessage",
+            ActivityType::DataMessage => "DataMessage",
+            ActivityType::Unknown => "Unknown",
+            ActivityType::Waiting => "Waiting",
+            ActivityType::BusyWaiting => "BusyWaiting",
+        }
+    }
+}
+
+impl std::fmt::Display for ActivityType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl std::str::FromStr for ActivityType {
+    type Err = String;
+
+    /// Parses the name returned by `ActivityType::name`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        (0..=std::u8::MAX)
+            .filter_map(ActivityType::from_u8)
+            .find(|activity_type| activity_type.name() == s)
+            .ok_or_else(|| format!("unknown activity type {:?}", s))
+    }
 }
 
 /// What "side" of the event did we log? E.g., for
@@ -32,7 +32,8 @@ fn main() {
     let load_balance_factor = std::env::args().nth(1).unwrap().parse::<usize>().unwrap();
 
     timely::execute_from_args(std::env::args().skip(1), move |worker| {
-        register_logger::<Pair<u64, Duration>>(worker, load_balance_factor);
+        register_logger::<Pair<u64, Duration>>(worker, load_balance_factor)
+            .expect("could not register the SnailTrail logger");
         let timer = std::time::Instant::now();
 
         let index = worker.index();
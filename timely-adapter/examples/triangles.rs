@@ -29,7 +29,8 @@ fn main() {
     let inspect = std::env::args().any(|x| x == "inspect");
 
     timely::execute_from_args(std::env::args().skip(3), move |worker| {
-        register_logger::<Pair<u64, Duration>>(worker, load_balance_factor);
+        register_logger::<Pair<u64, Duration>>(worker, load_balance_factor)
+            .expect("could not register the SnailTrail logger");
 
         let timer = std::time::Instant::now();
         let graph = GraphMMap::new(&filename);
@@ -5,6 +5,14 @@
 //! online via TCP. Regardless of offline/online logging, `register_logger`'s contract has
 //! to be upheld. See `log_pag`'s docstring for more information.
 //!
+//! If SnailTrail is not listening yet, connecting is retried up to
+//! `TIMELY_WORKER_LOG_CONNECT_RETRIES` times (5 by default), waiting twice as long before
+//! every retry, starting at 100ms.
+//!
+//! Within an epoch, events are written in batches of at most `TIMELY_WORKER_LOG_BATCH`
+//! events (512 by default). If `TIMELY_WORKER_LOG_BATCH_MS` is set, a batch is also written
+//! once its first event is that many milliseconds old, whichever comes first.
+//!
 //! To obtain the logged computation's events, use `make_replayers` and `replay_into` them
 //! into a dataflow of your choice. If you pass `Some(sockets)` created with `open_sockets`
 //! to `make_replayer`, an online connection will be used as the event source.
@@ -12,10 +20,11 @@
 use std::{
     error::Error,
     fs::File,
-    io::{Read, Write},
+    io::{self, Read, Write},
     net::{TcpListener, TcpStream},
     path::Path,
     sync::{Arc, Mutex},
+    thread,
     time::Duration,
 };
 
@@ -127,42 +136,103 @@ where T: Lattice + Ord {
     }
 }
 
+/// When `log_pag` writes out a batch of events before the end of an epoch.
+#[derive(Clone, Copy, Debug)]
+pub struct BatchConfig {
+    /// Write the batch once it holds this many events
+    pub max_events: usize,
+    /// Write the batch once its first event is this old
+    pub max_delay: Option<Duration>,
+}
+
+impl BatchConfig {
+    /// Reads `TIMELY_WORKER_LOG_BATCH` (events) and `TIMELY_WORKER_LOG_BATCH_MS`. Returns an
+    /// `InvalidInput` error if either is malformed.
+    pub fn from_env() -> io::Result<Self> {
+        let max_events = env_number("TIMELY_WORKER_LOG_BATCH", "a number of events")?.unwrap_or(512);
+        if max_events < 2 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "TIMELY_WORKER_LOG_BATCH must be at least 2"));
+        }
+        let max_delay = env_number("TIMELY_WORKER_LOG_BATCH_MS", "a number of ms")?.map(Duration::from_millis);
+        Ok(BatchConfig { max_events, max_delay })
+    }
+}
+
+/// Parses the env variable `name`, if it is set. Returns an `InvalidInput` error saying that
+/// it must be `what` if it doesn't parse.
+fn env_number<N: ::std::str::FromStr>(name: &str, what: &str) -> io::Result<Option<N>> {
+    match ::std::env::var(name) {
+        Ok(value) => value.parse().map(Some).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput,
+                           format!("{} must be {}, not {:?}", name, what, value))
+        }),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Connects to `addr`, retrying `retries` times with exponential backoff if that fails, e.g.
+/// because SnailTrail is not listening yet.
+fn connect_with_retries(addr: &str, retries: u32) -> io::Result<TcpStream> {
+    let mut backoff = Duration::from_millis(100);
+    let mut attempt = 0;
+    loop {
+        match TcpStream::connect(addr) {
+            Ok(stream) => return Ok(stream),
+            Err(why) if attempt < retries => {
+                attempt += 1;
+                info!("could not connect to {} ({}), retry {}/{} in {:?}", addr, why, attempt, retries, backoff);
+                thread::sleep(backoff);
+                backoff *= 2;
+            }
+            Err(why) => {
+                return Err(io::Error::new(why.kind(),
+                                          format!("could not connect to logging stream at {} after {} retries: {}",
+                                                  addr, retries, why)));
+            }
+        }
+    }
+}
+
 /// Logging of events to TCP or file.
 /// For live analysis, provide `SNAILTRAIL_ADDR` as env variable.
 /// Else, the computation will log to file for later replay.
-pub fn register_logger<T: 'static + NextEpoch + Lattice + Ord + Debug + Default + Clone + Abomonation> (worker: &mut Worker<Generic>, load_balance_factor: usize) {
+/// Batches are configured by env variables, see `BatchConfig::from_env`.
+/// Returns an error if SnailTrail cannot be connected to (see the module docs on retries), a
+/// log file cannot be created or one of the env variables is malformed; nothing is logged in
+/// that case.
+pub fn register_logger<T: 'static + NextEpoch + Lattice + Ord + Debug + Default + Clone + Abomonation> (worker: &mut Worker<Generic>, load_balance_factor: usize) -> io::Result<()> {
     assert!(load_balance_factor > 0);
+    let batch = BatchConfig::from_env()?;
 
     if let Ok(addr) = ::std::env::var("SNAILTRAIL_ADDR") {
+        let retries = env_number("TIMELY_WORKER_LOG_CONNECT_RETRIES", "a number of retries")?.unwrap_or(5);
         let writers = (0 .. load_balance_factor)
-            .map(|_| TcpStream::connect(&addr).expect("could not connect to logging stream"))
-            .map(|stream| {
+            .map(|_| {
+                let stream = connect_with_retries(&addr, retries)?;
                 // SnailTrail should be able to keep up with an online computation.
                 // If batch sizes are too large, they should be buffered. Blocking the
                 // TCP connection is not an option as it slows down the main computation.
-                stream
-                    .set_nonblocking(true)
-                    .expect("set_nonblocking call failed");
+                stream.set_nonblocking(true)?;
 
-                EventWriter::<T, _, _>::new(stream)
+                Ok(EventWriter::<T, _, _>::new(stream))
             })
-            .collect::<Vec<_>>();
+            .collect::<io::Result<Vec<_>>>()?;
 
-        unsafe { log_pag(worker, writers); }
+        unsafe { log_pag(worker, writers, batch); }
     } else {
         let writers = (0 .. load_balance_factor).map(|i| {
             let name = format!("../timely-snailtrail/{:?}.dump", (worker.index() + i * worker.peers()));
             info!("creating {}", name);
             let path = Path::new(&name);
-            let file = match File::create(&path) {
-                Err(why) => panic!("couldn't create {}: {}", path.display(), why.description()),
-                Ok(file) => file,
-            };
-            EventWriter::<T, _, _>::new(file)
-        }).collect::<Vec<_>>();
+            let file = File::create(&path)
+                .map_err(|why| io::Error::new(why.kind(), format!("couldn't create {}: {}", path.display(), why)))?;
+            Ok(EventWriter::<T, _, _>::new(file))
+        }).collect::<io::Result<Vec<_>>>()?;
 
-        unsafe { log_pag(worker, writers); }
+        unsafe { log_pag(worker, writers, batch); }
     }
+    Ok(())
 }
 
 /// Wrapper for timestamps that defines how they progress system and epoch time
@@ -222,7 +292,8 @@ enum ComputationStatus {
 //        rounds.
 /// Registers a `TimelyEvent` logger which outputs relevant log events for PAG construction.
 /// 1. Only relevant events are written to `writer`.
-/// 2. Using `Text` events as markers, logged events are written out at one time per epoch.
+/// 2. Using `Text` events as markers, logged events are written out at one time per epoch,
+///    in batches as configured by `batch`.
 /// 3. Dataflow setup is collapsed into t=(0, 0ns) so that peel_operators is more efficient.
 /// 4. If the computation is bounded, capabilities will be dropped correctly at the end of
 ///    computation.
@@ -239,6 +310,7 @@ enum ComputationStatus {
 unsafe fn log_pag<W: 'static + Write, T: 'static + NextEpoch + Lattice + Ord + Debug + Default + Clone + Abomonation>(
     worker: &mut Worker<Generic>,
     mut writers: Vec<ReplayWriter<T, W>>,
+    batch: BatchConfig,
 ) {
     // initialized to default, dropped as soon as the
     // computation starts running.
@@ -262,12 +334,15 @@ unsafe fn log_pag<W: 'static + Write, T: 'static + NextEpoch + Lattice + Ord + D
     // for the current progress batch.
     let mut tick_sys = true;
 
-    // If a computation's epoch size exceeds `MAX_FUEL` events are batched
+    // If a computation's epoch size exceeds `batch.max_events` events are batched
     // into multiple processing times within that epoch.
     // Received data messages don't use up fuel to avoid separating
     // them from the schedules event that is used to reorder them.
-    const MAX_FUEL: usize = 512;
-    let mut fuel = MAX_FUEL;
+    let max_fuel = batch.max_events;
+    let mut fuel = max_fuel;
+
+    // Time of the first event in `buffer`, for `batch.max_delay`
+    let mut batch_start: Option<Duration> = None;
 
     let mut curr_writer = 0;
 
@@ -294,8 +369,9 @@ unsafe fn log_pag<W: 'static + Write, T: 'static + NextEpoch + Lattice + Ord + D
                                              &mut curr_cap,
                                              worker_index);
                                 curr_writer = (curr_writer + 1) % writers.len();
-                                fuel = MAX_FUEL;
+                                fuel = max_fuel;
                                 tick_sys = true;
+                                batch_start = None;
 
                                 if e.starts_with("[st] computation done") {
                                     status = ComputationStatus::WrappingUp;
@@ -369,15 +445,30 @@ unsafe fn log_pag<W: 'static + Write, T: 'static + NextEpoch + Lattice + Ord + D
                             _ => {}
                         }
 
-                        if fuel == 0 {
+                        if batch_start.is_none() && !buffer.is_empty() {
+                            batch_start = Some(t);
+                        }
+                        let expired = match (batch.max_delay, batch_start) {
+                            (Some(max_delay), Some(start)) => t >= start + max_delay,
+                            _ => false,
+                        };
+                        // Like fuel, expiry must not separate a schedule start from the
+                        // data messages received in it
+                        let last_is_start = match buffer.last() {
+                            Some((_, _, (_, _, Schedule(e)))) => e.start_stop == StartStop::Start,
+                            _ => false,
+                        };
+
+                        if fuel == 0 || (expired && !last_is_start) {
                             info!("flushing to {}", curr_writer);
                             flush_buffer(std::mem::replace(&mut buffer, Vec::new()),
                                          &mut writers[curr_writer],
                                          &mut curr_cap,
                                          worker_index);
                             curr_writer = (curr_writer + 1) % writers.len();
-                            fuel = MAX_FUEL;
+                            fuel = max_fuel;
                             tick_sys = true;
+                            batch_start = None;
                         }
                     }
                 }